@@ -0,0 +1,992 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::Path,
+};
+
+use super::{
+    expression::Expression,
+    listing::SourceMap,
+    parser::{Line, Statement, Token},
+    preprocessor::preprocess,
+    pseudo::{expand, is_pseudo, Word},
+    Gpr, Instruction, Operand,
+};
+
+/// An error in a source line, shown as `line 3: Undefined label `LOOP``
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblerError {
+    /// line of the source, or of the included file, from 1
+    pub line: usize,
+    /// what is wrong
+    pub message: String,
+    /// where the line came from, innermost first e.g. `in macro PUSH called from line 12`
+    pub context: Vec<String>,
+}
+
+impl AssemblerError {
+    pub(crate) fn new(line: usize, message: String) -> Self {
+        AssemblerError {
+            line,
+            message,
+            context: vec![],
+        }
+    }
+
+    // An error on a parsed line, keeping track of where the line came from
+    pub(crate) fn at(line: &Line, message: String) -> Self {
+        AssemblerError::new(line.number, message).with_context(line.context.clone())
+    }
+
+    pub(crate) fn with_context(mut self, context: Vec<String>) -> Self {
+        self.context = context;
+        self
+    }
+}
+
+impl Display for AssemblerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("line {}: {}", self.line, self.message))?;
+        if !self.context.is_empty() {
+            f.write_fmt(format_args!(" ({})", self.context.join(", ")))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AssemblerError {}
+
+/// A block of words loaded at `origin`, one for every `.ORIG`/`.END` pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// address of the first word
+    pub origin: u16,
+    /// the words in address order
+    pub words: Vec<u16>,
+}
+
+impl Segment {
+    /// Address just past the last word
+    pub fn end(&self) -> usize {
+        self.origin as usize + self.words.len()
+    }
+
+    pub(crate) fn overlaps(&self, other: &Segment) -> bool {
+        (self.origin as usize) < other.end() && (other.origin as usize) < self.end()
+    }
+}
+
+// How a relocated word refers to its symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RelocationKind {
+    // the whole word is the address of the symbol
+    Absolute,
+    // the low bits hold the offset of the symbol from the incremented PC
+    PcOffset(usize),
+}
+
+// A word that has to be patched once the address of `symbol` is known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Relocation {
+    pub(crate) address: u16,
+    pub(crate) kind: RelocationKind,
+    pub(crate) symbol: String,
+    // added to the address of the symbol, e.g. 2 for `TABLE+2`
+    pub(crate) addend: i32,
+}
+
+impl Relocation {
+    // The word at `address` with its reference pointing at `target` plus the addend
+    pub(crate) fn apply(&self, word: u16, target: u16) -> Result<u16, String> {
+        let target = (target as i32).wrapping_add(self.addend) as u16;
+        match self.kind {
+            RelocationKind::Absolute => Ok(target),
+            RelocationKind::PcOffset(bit_count) => {
+                let offset = target as i32 - (self.address as i32 + 1);
+                let offset = check_range(offset, bit_count).map_err(|_| {
+                    format!(
+                        "`{}` is too far from x{:04X} for a PC-relative reference ({} words)",
+                        self.reference(),
+                        self.address,
+                        offset
+                    )
+                })?;
+                let mask = (1 << bit_count) - 1;
+                Ok(word & !mask | offset as u16 & mask)
+            }
+        }
+    }
+
+    // The symbol and its addend as written in the source, e.g. `TABLE+2`
+    pub(crate) fn reference(&self) -> String {
+        match self.addend {
+            0 => self.symbol.clone(),
+            addend if addend > 0 => format!("{}+{}", self.symbol, addend),
+            addend => format!("{}{}", self.symbol, addend),
+        }
+    }
+}
+
+/// An assembled program: its segments and the address of every label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    /// in source order
+    pub segments: Vec<Segment>,
+    /// address of every label
+    pub symbols: BTreeMap<String, u16>,
+    /// source line of every instruction
+    pub lines: BTreeMap<u16, usize>,
+    /// labels other modules may refer to
+    pub globals: BTreeSet<String>,
+    /// labels this module expects other modules to define
+    pub externals: BTreeSet<String>,
+    // words that depend on where segments end up or on external labels
+    pub(crate) relocations: Vec<Relocation>,
+    // where words and labels come from in the source, for listings
+    pub(crate) source_map: SourceMap,
+}
+
+impl Program {
+    /// A single segment without external references can be loaded as it is
+    pub fn is_flat(&self) -> bool {
+        self.segments.len() == 1 && self.externals.is_empty()
+    }
+
+    /// The image layout expected by [`Vm::load_program`](crate::vm::Vm::load_program): the
+    /// origin followed by the words
+    pub fn to_image(&self) -> Result<Vec<u16>, String> {
+        let mut images = self.to_images()?;
+        match images.len() {
+            1 => Ok(images.remove(0)),
+            count => Err(format!(
+                "Program has {} segments, load one image per segment",
+                count
+            )),
+        }
+    }
+
+    /// One image per segment in the order of the segments, as expected by
+    /// [`Vm::load_programs`](crate::vm::Vm::load_programs)
+    pub fn to_images(&self) -> Result<Vec<Vec<u16>>, String> {
+        if !self.externals.is_empty() {
+            return Err("Program refers to external labels and must be linked first".to_string());
+        }
+        Ok(self
+            .segments
+            .iter()
+            .map(|segment| {
+                let mut image = vec![segment.origin];
+                image.extend(&segment.words);
+                image
+            })
+            .collect())
+    }
+}
+
+// The lines between a `.ORIG` directive and its `.END`
+struct Block<'a> {
+    origin: u16,
+    line: usize,
+    lines: &'a [Line],
+}
+
+// Labels and the index of the segment defining them
+struct Symbols {
+    addresses: BTreeMap<String, u16>,
+    segments: BTreeMap<String, usize>,
+}
+
+// What the second pass knows about the statement it encodes
+struct Context<'a> {
+    address: u16,
+    segment: usize,
+    symbols: &'a Symbols,
+    externals: &'a BTreeSet<String>,
+}
+
+// An operand once evaluated
+enum Value {
+    Constant(i32),
+    // `addend` words after `symbol`
+    Address {
+        symbol: String,
+        addend: i32,
+        // the address and the segment holding it, None for an external label
+        resolved: Option<(i32, usize)>,
+    },
+}
+
+/// Assembles a source, `.INCLUDE` paths are relative to the working directory
+pub fn assemble(source: &str) -> Result<Program, AssemblerError> {
+    assemble_lines(preprocess(source, None)?)
+}
+
+/// Assembles the source read from `path`, `.INCLUDE` paths are relative to its directory
+pub fn assemble_file(source: &str, path: &Path) -> Result<Program, AssemblerError> {
+    assemble_lines(preprocess(source, Some(path))?)
+}
+
+fn assemble_lines(lines: Vec<Line>) -> Result<Program, AssemblerError> {
+    let blocks = split_segments(&lines)?;
+    let globals = declared_labels(&lines, "GLOBAL")?;
+    let externals = declared_labels(&lines, "EXTERNAL")?;
+    let symbols = collect_symbols(&blocks)?;
+    check_declarations(&lines, &symbols, &globals)?;
+
+    let mut segments: Vec<Segment> = vec![];
+    let mut source_lines = BTreeMap::new();
+    let mut relocations = vec![];
+    let mut source_map = SourceMap::default();
+    for (index, block) in blocks.iter().enumerate() {
+        let mut words = vec![];
+        for line in block.lines {
+            let address = block.origin.wrapping_add(words.len() as u16);
+            let start = words.len();
+            if let Some(statement) = &line.statement {
+                let error = |message: String| AssemblerError::at(line, message);
+                let context = Context {
+                    address,
+                    segment: index,
+                    symbols: &symbols,
+                    externals: &externals,
+                };
+                words.extend(
+                    encode_statement(statement, &context, &mut relocations).map_err(error)?,
+                );
+
+                // debug info only maps addresses to lines of the main source
+                if let (Statement::Instruction { .. }, None) = (statement, &line.file) {
+                    source_lines.insert(address, line.number);
+                }
+            }
+            source_map.record(line, address, &words[start..]);
+        }
+
+        let segment = Segment {
+            origin: block.origin,
+            words,
+        };
+        if let Some(other) = segments.iter().find(|other| other.overlaps(&segment)) {
+            return Err(AssemblerError::new(
+                block.line,
+                format!(
+                    "Segment at x{:04X} overlaps the segment at x{:04X}",
+                    segment.origin, other.origin
+                ),
+            ));
+        }
+        segments.push(segment);
+    }
+
+    Ok(Program {
+        segments,
+        symbols: symbols.addresses,
+        lines: source_lines,
+        globals,
+        externals,
+        relocations,
+        source_map,
+    })
+}
+
+// Finds every `.ORIG` block, only `.EXTERNAL` and `.GLOBAL` may appear outside of them
+fn split_segments(lines: &[Line]) -> Result<Vec<Block<'_>>, AssemblerError> {
+    let mut blocks = vec![];
+    // origin, line and index of the first line of the block being read
+    let mut current: Option<(u16, usize, usize)> = None;
+
+    for (index, line) in lines.iter().enumerate() {
+        let error = |message: &str| AssemblerError::at(line, message.to_string());
+
+        match (&line.statement, current) {
+            (Some(Statement::Directive { name, operands }), None) if name == "ORIG" => {
+                let origin = match operands.as_slice() {
+                    [Token::Number(origin)] => to_word(*origin)
+                        .ok_or(format!("Origin x{:X} is out of range", origin))
+                        .map_err(|message| AssemblerError::at(line, message))?,
+                    _ => return Err(error(".ORIG expects an address")),
+                };
+                current = Some((origin, line.number, index + 1));
+            }
+            (Some(Statement::Directive { name, .. }), Some(_)) if name == "ORIG" => {
+                return Err(error("Missing .END before the next .ORIG"));
+            }
+            (Some(Statement::Directive { name, .. }), Some((origin, number, start)))
+                if name == "END" =>
+            {
+                blocks.push(Block {
+                    origin,
+                    line: number,
+                    lines: &lines[start..index],
+                });
+                current = None;
+            }
+            (Some(Statement::Directive { name, .. }), None) if name == "END" => {
+                return Err(error(".END without .ORIG"));
+            }
+            (_, Some(_)) => {}
+            (Some(Statement::Directive { name, .. }), None)
+                if name == "EXTERNAL" || name == "GLOBAL" => {}
+            (None, None) if line.label.is_none() => {}
+            (_, None) if blocks.is_empty() => {
+                return Err(error("Program must start with .ORIG"));
+            }
+            (_, None) => return Err(error("Statement outside of a .ORIG/.END block")),
+        }
+    }
+
+    match (current, lines.last()) {
+        (Some(_), Some(last)) => Err(AssemblerError::new(
+            last.number,
+            "Missing .END directive".to_string(),
+        )),
+        _ if blocks.is_empty() => Err(AssemblerError::new(
+            1,
+            "Missing .ORIG directive".to_string(),
+        )),
+        _ => Ok(blocks),
+    }
+}
+
+// Labels listed by every `.GLOBAL` or `.EXTERNAL` directive
+fn declared_labels(lines: &[Line], directive: &str) -> Result<BTreeSet<String>, AssemblerError> {
+    let mut labels = BTreeSet::new();
+
+    for line in lines {
+        if let Some(Statement::Directive { name, operands }) = &line.statement {
+            if name != directive {
+                continue;
+            }
+            if operands.is_empty() {
+                return Err(AssemblerError::at(
+                    line,
+                    format!(".{} expects labels", directive),
+                ));
+            }
+            for operand in operands {
+                match operand {
+                    Token::Label(label) => labels.insert(label.clone()),
+                    _ => {
+                        return Err(AssemblerError::at(
+                            line,
+                            format!(".{} expects labels", directive),
+                        ))
+                    }
+                };
+            }
+        }
+    }
+
+    Ok(labels)
+}
+
+// Globals must be defined in this module and externals must not
+fn check_declarations(
+    lines: &[Line],
+    symbols: &Symbols,
+    globals: &BTreeSet<String>,
+) -> Result<(), AssemblerError> {
+    for line in lines {
+        let (name, operands) = match &line.statement {
+            Some(Statement::Directive { name, operands }) => (name, operands),
+            _ => continue,
+        };
+        for operand in operands {
+            let label = match operand {
+                Token::Label(label) => label,
+                _ => continue,
+            };
+            let defined = symbols.addresses.contains_key(label);
+            if name == "GLOBAL" && !defined {
+                return Err(AssemblerError::at(
+                    line,
+                    format!("Global label `{}` is not defined", label),
+                ));
+            }
+            if name == "EXTERNAL" && defined {
+                return Err(AssemblerError::at(
+                    line,
+                    format!("External label `{}` is also defined here", label),
+                ));
+            }
+            if name == "EXTERNAL" && globals.contains(label) {
+                return Err(AssemblerError::at(
+                    line,
+                    format!("Label `{}` is both .GLOBAL and .EXTERNAL", label),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// First pass: assigns an address to every label
+fn collect_symbols(blocks: &[Block]) -> Result<Symbols, AssemblerError> {
+    let mut symbols = Symbols {
+        addresses: BTreeMap::new(),
+        segments: BTreeMap::new(),
+    };
+
+    for (index, block) in blocks.iter().enumerate() {
+        let mut address = block.origin as usize;
+
+        for line in block.lines {
+            if let Some(label) = &line.label {
+                if symbols
+                    .addresses
+                    .insert(label.clone(), address as u16)
+                    .is_some()
+                {
+                    return Err(AssemblerError::at(
+                        line,
+                        format!("Label `{}` is defined more than once", label),
+                    ));
+                }
+                symbols.segments.insert(label.clone(), index);
+            }
+
+            if let Some(statement) = &line.statement {
+                address += statement_size(statement, &symbols.addresses)
+                    .map_err(|message| AssemblerError::at(line, message))?;
+            }
+
+            if address > u16::MAX as usize + 1 {
+                return Err(AssemblerError::at(
+                    line,
+                    "Program does not fit in memory".to_string(),
+                ));
+            }
+        }
+    }
+
+    Ok(symbols)
+}
+
+// Number of words a statement occupies
+// `.BLKW` counts may only use the labels defined before them, listed by `symbols`
+fn statement_size(statement: &Statement, symbols: &BTreeMap<String, u16>) -> Result<usize, String> {
+    match statement {
+        // the size of an expansion never depends on the value of an operand
+        Statement::Instruction { mnemonic, operands } if is_pseudo(mnemonic) => {
+            Ok(expand(mnemonic, operands, &mut |_| Ok(0))?.len())
+        }
+        Statement::Instruction { .. } => Ok(1),
+        Statement::Directive { name, operands } => match (name.as_str(), operands.as_slice()) {
+            ("FILL", _) => Ok(1),
+            ("BLKW", [count]) => {
+                let count = match to_expression(count) {
+                    Some(expression) if expression.weight() == Ok(0) => {
+                        expression.evaluate(&|label| symbols.get(label).map(|&a| a as i32))?
+                    }
+                    _ => return Err(".BLKW expects a word count".to_string()),
+                };
+                match count > 0 {
+                    true => Ok(count as usize),
+                    false => Err(format!(
+                        ".BLKW expects a positive word count, found {}",
+                        count
+                    )),
+                }
+            }
+            ("BLKW", _) => Err(".BLKW expects a positive word count".to_string()),
+            ("STRINGZ", [Token::String(string)]) => Ok(string.chars().count() + 1),
+            ("STRINGZ", _) => Err(".STRINGZ expects a string".to_string()),
+            ("EXTERNAL" | "GLOBAL", _) => Ok(0),
+            _ => Err(format!("Unknown directive `.{}`", name)),
+        },
+    }
+}
+
+// Second pass: produces the words for a single statement
+fn encode_statement(
+    statement: &Statement,
+    context: &Context,
+    relocations: &mut Vec<Relocation>,
+) -> Result<Vec<u16>, String> {
+    match statement {
+        Statement::Instruction { mnemonic, operands } if is_pseudo(mnemonic) => {
+            let words = expand(mnemonic, operands, &mut |token| constant(token, context))?;
+            Ok(words.iter().map(Word::encode).collect())
+        }
+        Statement::Instruction { mnemonic, operands } => Ok(vec![build_instruction(
+            mnemonic,
+            operands,
+            context,
+            relocations,
+        )?
+        .encode()]),
+        Statement::Directive { name, operands } => match (name.as_str(), operands.as_slice()) {
+            ("FILL", [Token::String(_)]) => Err(".FILL expects a value or a label".to_string()),
+            ("FILL", [operand]) => match evaluate(operand, context)? {
+                Value::Constant(value) => to_word(value)
+                    .map(|word| vec![word])
+                    .ok_or(format!("Value {} does not fit in 16 bits", value)),
+                Value::Address {
+                    symbol,
+                    addend,
+                    resolved,
+                } => {
+                    // the address changes whenever the segment is placed elsewhere
+                    relocations.push(Relocation {
+                        address: context.address,
+                        kind: RelocationKind::Absolute,
+                        symbol,
+                        addend,
+                    });
+                    Ok(vec![resolved.map_or(0, |(address, _)| address as u16)])
+                }
+            },
+            ("FILL", _) => Err(".FILL expects a value or a label".to_string()),
+            ("BLKW", _) => Ok(vec![
+                0;
+                statement_size(statement, &context.symbols.addresses)?
+            ]),
+            ("STRINGZ", [Token::String(string)]) => {
+                let mut words = string.chars().map(|c| c as u16).collect::<Vec<u16>>();
+                words.push(0);
+                Ok(words)
+            }
+            ("EXTERNAL" | "GLOBAL", _) => Ok(vec![]),
+            _ => Err(format!("Unknown directive `.{}`", name)),
+        },
+    }
+}
+
+fn build_instruction(
+    mnemonic: &str,
+    operands: &[Token],
+    context: &Context,
+    relocations: &mut Vec<Relocation>,
+) -> Result<Instruction, String> {
+    let mut offset =
+        |token: &Token, bit_count: usize| pc_offset(token, bit_count, context, relocations);
+
+    let instruction = match (mnemonic, operands) {
+        ("ADD" | "AND", [Token::Register(dr), Token::Register(sr1), second]) => {
+            let operand = match second {
+                Token::Register(sr2) => Operand::Reg(*sr2),
+                Token::String(_) => {
+                    return Err(format!("{} expects a register or an immediate", mnemonic))
+                }
+                imm5 => Operand::Imm(check_range(constant(imm5, context)?, 5)?),
+            };
+            if mnemonic == "ADD" {
+                Instruction::Add {
+                    dr: *dr,
+                    sr1: *sr1,
+                    operand,
+                }
+            } else {
+                Instruction::And {
+                    dr: *dr,
+                    sr1: *sr1,
+                    operand,
+                }
+            }
+        }
+        ("NOT", [Token::Register(dr), Token::Register(sr)]) => {
+            Instruction::Not { dr: *dr, sr: *sr }
+        }
+        ("NOP", []) => Instruction::Br {
+            n: false,
+            z: false,
+            p: false,
+            offset: 0,
+        },
+        ("NOP", [target]) => Instruction::Br {
+            n: false,
+            z: false,
+            p: false,
+            offset: offset(target, 9)?,
+        },
+        (_, [target]) if mnemonic.starts_with("BR") => {
+            let flags = &mnemonic[2..];
+            // a bare BR branches unconditionally
+            let always = flags.is_empty();
+            Instruction::Br {
+                n: always || flags.contains('N'),
+                z: always || flags.contains('Z'),
+                p: always || flags.contains('P'),
+                offset: offset(target, 9)?,
+            }
+        }
+        ("JMP", [Token::Register(base_r)]) => Instruction::Jmp { base_r: *base_r },
+        ("RET", []) => Instruction::Jmp { base_r: Gpr::R7 },
+        ("JSR", [target]) => Instruction::Jsr {
+            offset: offset(target, 11)?,
+        },
+        ("JSRR", [Token::Register(base_r)]) => Instruction::Jsrr { base_r: *base_r },
+        ("LD", [Token::Register(dr), target]) => Instruction::Ld {
+            dr: *dr,
+            offset: offset(target, 9)?,
+        },
+        ("LDI", [Token::Register(dr), target]) => Instruction::Ldi {
+            dr: *dr,
+            offset: offset(target, 9)?,
+        },
+        ("LEA", [Token::Register(dr), target]) => Instruction::Lea {
+            dr: *dr,
+            offset: offset(target, 9)?,
+        },
+        ("ST", [Token::Register(sr), target]) => Instruction::St {
+            sr: *sr,
+            offset: offset(target, 9)?,
+        },
+        ("STI", [Token::Register(sr), target]) => Instruction::Sti {
+            sr: *sr,
+            offset: offset(target, 9)?,
+        },
+        ("LDR", [Token::Register(dr), Token::Register(base_r), offset]) => Instruction::Ldr {
+            dr: *dr,
+            base_r: *base_r,
+            offset: check_range(constant(offset, context)?, 6)?,
+        },
+        ("STR", [Token::Register(sr), Token::Register(base_r), offset]) => Instruction::Str {
+            sr: *sr,
+            base_r: *base_r,
+            offset: check_range(constant(offset, context)?, 6)?,
+        },
+        ("TRAP", [trap_vect]) => {
+            let trap_vect = constant(trap_vect, context)?;
+            match u8::try_from(trap_vect) {
+                Ok(trap_vect) => Instruction::Trap { trap_vect },
+                Err(_) => return Err(format!("Trap vector {} is out of range", trap_vect)),
+            }
+        }
+        ("GETC", []) => Instruction::Trap { trap_vect: 0x20 },
+        ("OUT", []) => Instruction::Trap { trap_vect: 0x21 },
+        ("PUTS", []) => Instruction::Trap { trap_vect: 0x22 },
+        ("IN", []) => Instruction::Trap { trap_vect: 0x23 },
+        ("PUTSP", []) => Instruction::Trap { trap_vect: 0x24 },
+        ("HALT", []) => Instruction::Trap { trap_vect: 0x25 },
+        ("RTI", []) => Instruction::Rti,
+        ("RES", []) => Instruction::Res,
+        _ => return Err(format!("Invalid operands for {}", mnemonic)),
+    };
+
+    Ok(instruction)
+}
+
+// A constant operand is the offset itself, an address is made relative to the incremented PC
+// Addresses in other segments or modules are only known once linked and get a relocation
+fn pc_offset(
+    token: &Token,
+    bit_count: usize,
+    context: &Context,
+    relocations: &mut Vec<Relocation>,
+) -> Result<i16, String> {
+    let (symbol, addend, resolved) = match evaluate(token, context)? {
+        Value::Constant(offset) => return check_range(offset, bit_count),
+        Value::Address {
+            symbol,
+            addend,
+            resolved,
+        } => (symbol, addend, resolved),
+    };
+
+    let local = matches!(resolved, Some((_, segment)) if segment == context.segment);
+    let target = match resolved {
+        Some((target, _)) => target,
+        None => 0,
+    };
+    if !local {
+        relocations.push(Relocation {
+            address: context.address,
+            kind: RelocationKind::PcOffset(bit_count),
+            symbol,
+            addend,
+        });
+        if resolved.is_none() {
+            return Ok(0);
+        }
+    }
+
+    let offset = target - (context.address as i32 + 1);
+    if !local {
+        // the linker checks the range again after placing the segments
+        return Ok(check_range(offset, bit_count).unwrap_or(0));
+    }
+    check_range(offset, bit_count).map_err(|_| {
+        format!(
+            "Label `{}` is too far away ({} words)",
+            to_expression(token).unwrap(),
+            offset
+        )
+    })
+}
+
+// Evaluates an operand that must not depend on where the program is loaded
+fn constant(token: &Token, context: &Context) -> Result<i32, String> {
+    match evaluate(token, context)? {
+        Value::Constant(value) => Ok(value),
+        Value::Address { .. } => Err(format!(
+            "`{}` is an address, expected a constant",
+            to_expression(token).unwrap()
+        )),
+    }
+}
+
+// Numbers, labels and expressions all evaluate to a value
+fn to_expression(token: &Token) -> Option<Expression> {
+    match token {
+        Token::Number(value) => Some(Expression::Number(*value)),
+        Token::Label(label) => Some(Expression::Label(label.clone())),
+        Token::Expression(expression) => Some(expression.clone()),
+        _ => None,
+    }
+}
+
+// Differences of labels in the same segment such as `END-START` are constants,
+// a label plus or minus a constant such as `TABLE+2` is an address
+fn evaluate(token: &Token, context: &Context) -> Result<Value, String> {
+    let expression = to_expression(token).ok_or("Expected a value or a label".to_string())?;
+    let weight = expression.weight()?;
+    let labels = expression.labels();
+    let unlinked = || {
+        format!(
+            "`{}` can't be computed before the program is linked",
+            expression
+        )
+    };
+
+    if let Some(external) = labels
+        .iter()
+        .find(|label| context.externals.contains(**label))
+    {
+        if weight != 1 || labels.len() > 1 {
+            return Err(unlinked());
+        }
+        return Ok(Value::Address {
+            symbol: external.to_string(),
+            addend: expression.evaluate(&|_| Some(0))?,
+            resolved: None,
+        });
+    }
+
+    let mut segments = BTreeSet::new();
+    for label in &labels {
+        resolve(label, &context.symbols.addresses)?;
+        segments.insert(context.symbols.segments[*label]);
+    }
+    if segments.len() > 1 {
+        return Err(unlinked());
+    }
+    let value =
+        expression.evaluate(&|label| context.symbols.addresses.get(label).map(|&a| a as i32))?;
+
+    match (weight, labels.first()) {
+        (0, _) => Ok(Value::Constant(value)),
+        (1, Some(symbol)) => {
+            let address = context.symbols.addresses[*symbol] as i32;
+            Ok(Value::Address {
+                symbol: symbol.to_string(),
+                addend: value - address,
+                resolved: Some((value, context.symbols.segments[*symbol])),
+            })
+        }
+        _ => Err(format!(
+            "`{}` is neither a constant nor an address",
+            expression
+        )),
+    }
+}
+
+fn resolve(label: &str, symbols: &BTreeMap<String, u16>) -> Result<u16, String> {
+    symbols
+        .get(label)
+        .copied()
+        .ok_or(format!("Undefined label `{}`", label))
+}
+
+// Checks that a value fits in a signed field of `bit_count` bits
+fn check_range(value: i32, bit_count: usize) -> Result<i16, String> {
+    let limit = 1 << (bit_count - 1);
+    if (-limit..limit).contains(&value) {
+        Ok(value as i16)
+    } else {
+        Err(format!(
+            "Value {} does not fit in {} bits ({}..{})",
+            value,
+            bit_count,
+            -limit,
+            limit - 1
+        ))
+    }
+}
+
+// Accepts both signed and unsigned 16 bit values
+fn to_word(value: i32) -> Option<u16> {
+    if (i16::MIN as i32..=u16::MAX as i32).contains(&value) {
+        Some(value as u16)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, Relocation, RelocationKind};
+
+    #[test]
+    fn test_assemble_program() {
+        let program = assemble(
+            ".ORIG x3000
+                    LEA R0, MSG     ; load the message
+                    PUTS
+            LOOP    ADD R1, R1, #-1
+                    BRp LOOP
+                    HALT
+            MSG     .STRINGZ \"Hi\"
+            DATA    .FILL x1234
+                    .BLKW 2
+                    .FILL MSG
+            .END",
+        )
+        .unwrap();
+
+        assert_eq!(program.segments[0].origin, 0x3000);
+        assert_eq!(
+            program.segments[0].words,
+            vec![0xE004, 0xF022, 0x127F, 0x03FE, 0xF025, 0x48, 0x69, 0x0, 0x1234, 0x0, 0x0, 0x3005]
+        );
+        assert_eq!(program.symbols["LOOP"], 0x3002);
+        assert_eq!(program.symbols["MSG"], 0x3005);
+        assert_eq!(program.lines[&0x3000], 2);
+        assert_eq!(program.lines[&0x3004], 6);
+        assert!(!program.lines.contains_key(&0x3005));
+        assert_eq!(program.to_image().unwrap()[0], 0x3000);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let error = assemble(".ORIG x3000\nBR NOWHERE\n.END").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "Undefined label `NOWHERE`");
+
+        let error = assemble(".ORIG x3000\nADD R1, R1, #16\n.END").unwrap_err();
+        assert_eq!(error.line, 2);
+
+        let error = assemble(".ORIG x3000\nA HALT\nA HALT\n.END").unwrap_err();
+        assert_eq!(error.line, 3);
+
+        assert!(assemble("HALT\n.END").is_err());
+        assert!(assemble(".ORIG x3000\nHALT").is_err());
+        assert!(assemble(".ORIG x3000\nLD R1, FAR\n.BLKW 300\nFAR .FILL 0\n.END").is_err());
+    }
+
+    #[test]
+    fn test_assemble_expressions() {
+        let program = assemble(
+            ".ORIG x3000
+            START   LD R0, TABLE + 1
+                    ADD R1, R1, #-1
+                    ADD R1, R1, 'A' - 'a' + 32
+                    TRAP x10 << 1 | 5
+                    LDR R2, R6, DONE - TABLE
+                    BR START+1
+            TABLE   .FILL DONE-START
+                    .FILL TABLE+2
+                    .FILL ';'
+                    .BLKW TABLE - START
+            DONE    HALT
+                    .END",
+        )
+        .unwrap();
+
+        assert_eq!(
+            &program.segments[0].words[..9],
+            &[0x2006, 0x127F, 0x1260, 0xF025, 0x6589, 0x0FFB, 15, 0x3008, 0x3B]
+        );
+        assert_eq!(program.segments[0].words.len(), 9 + 6 + 1);
+        assert_eq!(program.relocations[0].addend, 2);
+
+        let error = |source: &str| {
+            assemble(&format!(".ORIG x3000\n{}\n.END", source))
+                .unwrap_err()
+                .message
+        };
+        assert_eq!(
+            error("A ADD R1, R1, A + 1"),
+            "`A+1` is an address, expected a constant"
+        );
+        assert_eq!(
+            error("ADD R1, R1, 8 * 2"),
+            "Value 16 does not fit in 5 bits (-16..15)"
+        );
+        assert_eq!(
+            error(".BLKW LATER\nLATER HALT"),
+            ".BLKW expects a word count"
+        );
+        assert_eq!(
+            error(".FILL 1 / 0"),
+            "Invalid operand `1/0`: Division by zero"
+        );
+        assert_eq!(
+            error(".EXTERNAL X\n.FILL X - X"),
+            "`X-X` can't be computed before the program is linked"
+        );
+        assert_eq!(
+            error("A .FILL A * 2"),
+            "`A*2` can only add or subtract addresses"
+        );
+    }
+
+    #[test]
+    fn test_assemble_segments() {
+        let program = assemble(
+            ".ORIG x3000
+                    LD R0, VALUE
+                    HALT
+            .END
+            .ORIG x3200
+            VALUE   .FILL x1234
+            .END",
+        )
+        .unwrap();
+
+        assert_eq!(program.segments.len(), 2);
+        assert_eq!(program.segments[1].origin, 0x3200);
+        // VALUE is in another segment, too far for a PC-relative offset until it is placed
+        assert_eq!(program.segments[0].words[0], 0x2000);
+        assert_eq!(
+            program.relocations,
+            vec![Relocation {
+                address: 0x3000,
+                kind: RelocationKind::PcOffset(9),
+                symbol: "VALUE".to_string(),
+                addend: 0,
+            }]
+        );
+        assert!(!program.is_flat());
+        assert!(program.to_image().is_err());
+
+        let error = assemble(".ORIG x3000\n.BLKW 4\n.END\n.ORIG x3002\nHALT\n.END").unwrap_err();
+        assert_eq!(error.line, 4);
+        assert!(assemble(".ORIG x3000\nHALT\n.ORIG x4000\n.END").is_err());
+    }
+
+    #[test]
+    fn test_assemble_externals_and_globals() {
+        let program = assemble(
+            ".GLOBAL MAIN
+            .EXTERNAL PRINT, TABLE
+            .ORIG x3000
+            MAIN    JSR PRINT
+                    HALT
+            POINTER .FILL TABLE
+            .END",
+        )
+        .unwrap();
+
+        assert_eq!(program.segments[0].words, vec![0x4800, 0xF025, 0x0000]);
+        assert!(program.globals.contains("MAIN"));
+        assert_eq!(program.externals.len(), 2);
+        assert_eq!(program.relocations[0].kind, RelocationKind::PcOffset(11));
+        assert_eq!(program.relocations[1].kind, RelocationKind::Absolute);
+        assert!(program.to_image().is_err());
+
+        let error = assemble(".GLOBAL MISSING\n.ORIG x3000\nHALT\n.END").unwrap_err();
+        assert_eq!(error.message, "Global label `MISSING` is not defined");
+        assert!(assemble(".EXTERNAL A\n.ORIG x3000\nA HALT\n.END").is_err());
+    }
+}
@@ -0,0 +1,361 @@
+use std::{collections::BTreeMap, path::Path};
+
+use super::{parser::parse_address, Program};
+
+// Where a word comes from: an index into `DebugInfo::files`, a line and a column
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Location {
+    pub(crate) file: usize,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+}
+
+// Consecutive words holding either instructions or data, `end` is the last address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Region {
+    pub(crate) start: u16,
+    pub(crate) end: u16,
+    pub(crate) code: bool,
+}
+
+/// Maps the words of an assembled program back to its source files
+///
+/// Written next to the object file as one record per line:
+///
+/// ```text
+/// SOURCE prog.asm           the main source, file 0, the path is the rest of the line
+/// FILE 1 lib/io.asm         every included file, the path is the rest of the line too
+/// LABEL LOOP x3002 x3004    first and last address of every label
+/// CODE x3000 x3004          instruction and data regions
+/// DATA x3005 x300B
+/// LOCATION x3000 0 3 9      file, line and column of every word
+/// x3000 3                   main source line of every instruction, as used for coverage
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub(crate) source: String,
+    pub(crate) lines: BTreeMap<u16, usize>,
+    // the main source followed by the included files
+    pub(crate) files: Vec<String>,
+    pub(crate) locations: BTreeMap<u16, Location>,
+    pub(crate) labels: BTreeMap<String, (u16, u16)>,
+    pub(crate) regions: Vec<Region>,
+}
+
+impl DebugInfo {
+    /// The debug info of `program` assembled from the file `source`
+    pub fn new(source: &str, program: &Program) -> Self {
+        let mut debug_info = DebugInfo {
+            source: source.to_string(),
+            lines: program.lines.clone(),
+            files: vec![source.to_string()],
+            ..DebugInfo::default()
+        };
+
+        for (address, word) in &program.source_map.locations {
+            let file = match &word.file {
+                None => 0,
+                Some(name) => match debug_info.files.iter().position(|file| file == name) {
+                    Some(index) => index,
+                    None => {
+                        debug_info.files.push(name.clone());
+                        debug_info.files.len() - 1
+                    }
+                },
+            };
+            let location = Location {
+                file,
+                line: word.line,
+                column: word.column,
+            };
+            debug_info.locations.insert(*address, location);
+
+            match debug_info.regions.last_mut() {
+                Some(region)
+                    if region.end as usize + 1 == *address as usize && region.code == word.code =>
+                {
+                    region.end = *address;
+                }
+                _ => debug_info.regions.push(Region {
+                    start: *address,
+                    end: *address,
+                    code: word.code,
+                }),
+            }
+        }
+
+        // a label covers the words up to the next label of its segment
+        for (label, address) in &program.symbols {
+            let segment = program.segments.iter().find(|segment| {
+                (segment.origin as usize..segment.end()).contains(&(*address as usize))
+            });
+            let end = match segment {
+                Some(segment) => program
+                    .symbols
+                    .values()
+                    .map(|other| *other as usize)
+                    .filter(|other| other > &(*address as usize))
+                    .chain([segment.end()])
+                    .min()
+                    .unwrap(),
+                None => continue,
+            };
+            debug_info
+                .labels
+                .insert(label.clone(), (*address, (end - 1) as u16));
+        }
+
+        debug_info
+    }
+
+    /// Reads debug info written by its `Display` implementation
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        let source = match lines.next() {
+            Some((_, line)) => line
+                .strip_prefix("SOURCE ")
+                .ok_or("line 1: expected `SOURCE <path>`".to_string())?,
+            None => return Err("Empty debug info".to_string()),
+        };
+
+        let mut debug_info = DebugInfo {
+            source: source.to_string(),
+            files: vec![source.to_string()],
+            ..DebugInfo::default()
+        };
+        for (index, line) in lines {
+            let error = |record: &str| format!("line {}: invalid {} record", index + 1, record);
+            let address =
+                |word: &str, record: &str| parse_address(word).ok_or_else(|| error(record));
+            let number = |word: &str, record: &str| word.parse().map_err(|_| error(record));
+
+            // paths may hold spaces
+            if let Some(rest) = line.strip_prefix("FILE ") {
+                let (file, name) = rest.split_once(' ').ok_or_else(|| error("FILE"))?;
+                if number(file, "FILE")? != debug_info.files.len() || name.is_empty() {
+                    return Err(error("FILE"));
+                }
+                debug_info.files.push(name.to_string());
+                continue;
+            }
+
+            let words = line.split_whitespace().collect::<Vec<&str>>();
+            match words.as_slice() {
+                [] => {}
+                ["LABEL", label, start, end] => {
+                    let range = (address(start, "LABEL")?, address(end, "LABEL")?);
+                    debug_info.labels.insert(label.to_string(), range);
+                }
+                [kind @ ("CODE" | "DATA"), start, end] => debug_info.regions.push(Region {
+                    start: address(start, kind)?,
+                    end: address(end, kind)?,
+                    code: *kind == "CODE",
+                }),
+                ["LOCATION", word, file, line, column] => {
+                    let location = Location {
+                        file: number(file, "LOCATION")?,
+                        line: number(line, "LOCATION")?,
+                        column: number(column, "LOCATION")?,
+                    };
+                    if location.file >= debug_info.files.len() {
+                        return Err(error("LOCATION"));
+                    }
+                    debug_info
+                        .locations
+                        .insert(address(word, "LOCATION")?, location);
+                }
+                [word, line] => {
+                    let line = number(line, "line")?;
+                    debug_info.lines.insert(address(word, "line")?, line);
+                }
+                _ => return Err(format!("line {}: unknown record", index + 1)),
+            }
+        }
+
+        Ok(debug_info)
+    }
+
+    /// The main source line of the instruction at `address`
+    pub fn line(&self, address: u16) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    /// `file:line:column` of the statement a word comes from
+    pub fn location(&self, address: u16) -> Option<String> {
+        self.locations.get(&address).map(|location| {
+            format!(
+                "{}:{}:{}",
+                self.files[location.file], location.line, location.column
+            )
+        })
+    }
+
+    /// The closest label at or before the address, e.g. `LOOP+2`
+    pub fn label(&self, address: u16) -> Option<String> {
+        let (label, (start, _)) = self
+            .labels
+            .iter()
+            .filter(|(_, (start, end))| (*start..=*end).contains(&address))
+            .max_by_key(|(_, (start, _))| *start)?;
+
+        match address - start {
+            0 => Some(label.clone()),
+            offset => Some(format!("{}+{}", label, offset)),
+        }
+    }
+
+    /// An address with its label and source location when known, e.g. `x3004 LOOP+2 (prog.asm:7:9)`
+    pub fn describe(&self, address: u16) -> String {
+        let mut text = format!("x{:04X}", address);
+        if let Some(label) = self.label(address) {
+            text.push_str(&format!(" {}", label));
+        }
+        if let Some(location) = self.location(address) {
+            text.push_str(&format!(" ({})", location));
+        }
+        text
+    }
+
+    /// Whether the word at `address` was assembled from an instruction
+    pub fn is_code(&self, address: u16) -> bool {
+        self.regions
+            .iter()
+            .any(|region| region.code && (region.start..=region.end).contains(&address))
+    }
+
+    /// The first instruction of a source line given as `file:line`, or `line` for the main source
+    ///
+    /// The file may be named by its path as recorded or by its file name alone.
+    pub fn address_of(&self, position: &str) -> Result<u16, String> {
+        let (file, line) = match position.rsplit_once(':') {
+            Some((file, line)) => (file, line),
+            None => (self.source.as_str(), position),
+        };
+        let line = line
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid line `{}`", line))?;
+        let index = self
+            .files
+            .iter()
+            .position(|name| {
+                name == file || Path::new(name).file_name() == Path::new(file).file_name()
+            })
+            .ok_or(format!("Unknown source file `{}`", file))?;
+
+        self.locations
+            .iter()
+            .find(|(address, location)| {
+                location.file == index && location.line == line && self.is_code(**address)
+            })
+            .map(|(address, _)| *address)
+            .ok_or(format!("No instruction at {}:{}", file, line))
+    }
+}
+
+impl std::fmt::Display for DebugInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "SOURCE {}", self.source)?;
+        for (index, file) in self.files.iter().enumerate().skip(1) {
+            writeln!(f, "FILE {} {}", index, file)?;
+        }
+        for (label, (start, end)) in &self.labels {
+            writeln!(f, "LABEL {} x{:04X} x{:04X}", label, start, end)?;
+        }
+        for region in &self.regions {
+            let kind = if region.code { "CODE" } else { "DATA" };
+            writeln!(f, "{} x{:04X} x{:04X}", kind, region.start, region.end)?;
+        }
+        for (address, location) in &self.locations {
+            writeln!(
+                f,
+                "LOCATION x{:04X} {} {} {}",
+                address, location.file, location.line, location.column
+            )?;
+        }
+        for (address, line) in &self.lines {
+            writeln!(f, "x{:04X} {}", address, line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::assembler::{assemble, assemble_file};
+
+    use super::DebugInfo;
+
+    #[test]
+    fn test_debug_info_roundtrip() {
+        let program = assemble(".ORIG x3000\n\nADD R1, R1, #1\nDATA .FILL 3\nHALT\n.END").unwrap();
+        let debug_info = DebugInfo::new("prog.asm", &program);
+        let text = debug_info.to_string();
+
+        assert_eq!(
+            text,
+            "SOURCE prog.asm\n\
+             LABEL DATA x3001 x3002\n\
+             CODE x3000 x3000\n\
+             DATA x3001 x3001\n\
+             CODE x3002 x3002\n\
+             LOCATION x3000 0 3 1\n\
+             LOCATION x3001 0 4 6\n\
+             LOCATION x3002 0 5 1\n\
+             x3000 3\n\
+             x3002 5\n"
+        );
+        assert_eq!(DebugInfo::parse(&text).unwrap(), debug_info);
+        assert_eq!(debug_info.line(0x3002), Some(5));
+        assert_eq!(debug_info.line(0x3001), None);
+        assert!(DebugInfo::parse("x3000 3").is_err());
+        assert!(DebugInfo::parse("SOURCE a.asm\nLOCATION x3000 1 1 1").is_err());
+
+        // older files only map instructions to lines
+        let old = DebugInfo::parse("SOURCE prog.asm\nx3000 3\n").unwrap();
+        assert_eq!(old.line(0x3000), Some(3));
+    }
+
+    #[test]
+    fn test_debug_info_locations() {
+        let directory = std::env::temp_dir().join(format!("lc3_vm_dbg_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("my util.asm"),
+            "CLEAR   AND R0, R0, #0\n        RET\n",
+        )
+        .unwrap();
+        let main = directory.join("main.asm");
+        let source = ".ORIG x3000
+        JSR CLEAR
+LOOP    ADD R1, R1, #1
+        BR LOOP
+.INCLUDE \"my util.asm\"
+.END";
+        let program = assemble_file(source, &main).unwrap();
+        let debug_info = DebugInfo::new("main.asm", &program);
+        fs::remove_dir_all(directory).unwrap();
+
+        assert_eq!(debug_info.files, vec!["main.asm", "my util.asm"]);
+        assert_eq!(debug_info.location(0x3001).unwrap(), "main.asm:3:9");
+        assert_eq!(debug_info.location(0x3004).unwrap(), "my util.asm:2:9");
+
+        // the path of an included file keeps its spaces
+        let text = debug_info.to_string();
+        assert!(text.contains("\nFILE 1 my util.asm\n"));
+        assert_eq!(DebugInfo::parse(&text).unwrap(), debug_info);
+        assert!(DebugInfo::parse("SOURCE a.asm\nFILE 1").is_err());
+        assert!(DebugInfo::parse("SOURCE a.asm\nFILE 2 b.asm").is_err());
+        assert_eq!(debug_info.labels["LOOP"], (0x3001, 0x3002));
+        assert_eq!(debug_info.describe(0x3002), "x3002 LOOP+1 (main.asm:4:9)");
+
+        assert_eq!(debug_info.address_of("3"), Ok(0x3001));
+        assert_eq!(debug_info.address_of("my util.asm:2"), Ok(0x3004));
+        assert_eq!(
+            debug_info.address_of("main.asm:5"),
+            Err("No instruction at main.asm:5".to_string())
+        );
+        assert!(debug_info.address_of("other.asm:1").is_err());
+    }
+}
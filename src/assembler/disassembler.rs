@@ -0,0 +1,108 @@
+use std::fmt::Write;
+
+use super::{decode_instruction, pseudo::expansion, Program};
+
+/// Every segment as source with one instruction per line, followed by the address and the word
+///
+/// The words a pseudo-instruction of the source expanded into are marked with it, e.g.
+/// `[PUSH R1]`. Nothing is marked for a program read from an object file, which has no source.
+pub fn disassemble(program: &Program) -> String {
+    let mut text = String::new();
+
+    for segment in &program.segments {
+        writeln!(text, ".ORIG x{:04X}", segment.origin).unwrap();
+        let mut index = 0;
+        while index < segment.words.len() {
+            let address = segment.origin.wrapping_add(index as u16);
+            // the words of a pseudo-instruction are those it was assembled into, whatever follows
+            let pseudo = program
+                .source_map
+                .pseudo
+                .get(&address)
+                .map(|(text, count)| {
+                    let end = (index + count).min(segment.words.len());
+                    let mnemonic = text.split_whitespace().next().unwrap_or_default();
+                    (text, expansion(mnemonic, &segment.words[index..end]))
+                });
+            match pseudo {
+                Some((pseudo, expansion)) => {
+                    for (address, word) in (address..).zip(&expansion) {
+                        writeln!(
+                            text,
+                            "{:<24}; x{:04X}: x{:04X}  [{}]",
+                            word.to_string(),
+                            address,
+                            word.encode(),
+                            pseudo
+                        )
+                        .unwrap();
+                    }
+                    index += expansion.len();
+                }
+                None => {
+                    let word = segment.words[index];
+                    let instruction = decode_instruction(word).to_string();
+                    writeln!(text, "{:<24}; x{:04X}: x{:04X}", instruction, address, word).unwrap();
+                    index += 1;
+                }
+            }
+        }
+        writeln!(text, ".END").unwrap();
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+
+    use super::disassemble;
+
+    #[test]
+    fn test_disassemble() {
+        let program = assemble(
+            ".ORIG x3000\nADD R1, R1, R2\nPUSH R1\nAND R2, R2, #0\nADD R2, R2, #1\nCLR R3\nHALT\n.END",
+        )
+        .unwrap();
+
+        // only the pseudo-instructions of the source are marked
+        assert_eq!(
+            disassemble(&program),
+            ".ORIG x3000
+ADD R1, R1, R2          ; x3000: x1242
+ADD R6, R6, #-1         ; x3001: x1DBF  [PUSH R1]
+STR R1, R6, #0          ; x3002: x7380  [PUSH R1]
+AND R2, R2, #0          ; x3003: x54A0
+ADD R2, R2, #1          ; x3004: x14A1
+AND R3, R3, #0          ; x3005: x56E0  [CLR R3]
+TRAP x25                ; x3006: xF025
+.END
+"
+        );
+
+        // the words after a pseudo-instruction aren't taken for part of it, even when they look
+        // like a longer expansion
+        let program = assemble(
+            ".ORIG x3000\nNEG R1\nADD R1, R1, R2\nDEC R6\nSTR R0, R6, #0\nSUB R1, R1, R1\n.END",
+        )
+        .unwrap();
+        assert_eq!(
+            disassemble(&program),
+            ".ORIG x3000
+NOT R1, R1              ; x3000: x927F  [NEG R1]
+ADD R1, R1, #1          ; x3001: x1261  [NEG R1]
+ADD R1, R1, R2          ; x3002: x1242
+ADD R6, R6, #-1         ; x3003: x1DBF  [DEC R6]
+STR R0, R6, #0          ; x3004: x7180
+AND R1, R1, #0          ; x3005: x5260  [SUB R1, R1, R1]
+.END
+"
+        );
+
+        // without a source map, e.g. read from an object file, nothing is marked
+        let mut program = program;
+        program.source_map = Default::default();
+        assert!(!disassemble(&program).contains('['));
+    }
+}
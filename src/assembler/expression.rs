@@ -0,0 +1,402 @@
+use std::fmt::Display;
+
+use super::parser::{is_label, parse_number};
+
+// Characters that can join the words around them into a single expression
+pub(crate) const OPERATOR_CHARS: &str = "+-*/%<>=!&|^~";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    Xor,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+// An operand such as `LABEL+2`, `END-START` or `x10 << 2`
+// Expressions without labels are folded into numbers when they are parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Expression {
+    Number(i32),
+    Label(String),
+    Unary(UnaryOperator, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+}
+
+// Operators by increasing precedence
+const PRECEDENCE: [&[(&str, BinaryOperator)]; 7] = [
+    &[
+        ("==", BinaryOperator::Equal),
+        ("!=", BinaryOperator::NotEqual),
+        ("<=", BinaryOperator::LessOrEqual),
+        (">=", BinaryOperator::GreaterOrEqual),
+        ("<", BinaryOperator::Less),
+        (">", BinaryOperator::Greater),
+    ],
+    &[("|", BinaryOperator::Or)],
+    &[("^", BinaryOperator::Xor)],
+    &[("&", BinaryOperator::And)],
+    &[
+        ("<<", BinaryOperator::ShiftLeft),
+        (">>", BinaryOperator::ShiftRight),
+    ],
+    &[("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract)],
+    &[
+        ("*", BinaryOperator::Multiply),
+        ("/", BinaryOperator::Divide),
+        ("%", BinaryOperator::Remainder),
+    ],
+];
+
+impl BinaryOperator {
+    fn symbol(&self) -> &'static str {
+        PRECEDENCE
+            .iter()
+            .flat_map(|level| level.iter())
+            .find(|(_, operator)| operator == self)
+            .map(|(symbol, _)| *symbol)
+            .unwrap()
+    }
+
+    fn apply(&self, a: i32, b: i32) -> Result<i32, String> {
+        Ok(match self {
+            BinaryOperator::Add => a.wrapping_add(b),
+            BinaryOperator::Subtract => a.wrapping_sub(b),
+            BinaryOperator::Multiply => a.wrapping_mul(b),
+            BinaryOperator::Divide | BinaryOperator::Remainder if b == 0 => {
+                return Err("Division by zero".to_string())
+            }
+            BinaryOperator::Divide => a.wrapping_div(b),
+            BinaryOperator::Remainder => a.wrapping_rem(b),
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight if !(0..32).contains(&b) => {
+                return Err(format!("Shift amount {} is out of range", b))
+            }
+            BinaryOperator::ShiftLeft => a << b,
+            BinaryOperator::ShiftRight => a >> b,
+            BinaryOperator::And => a & b,
+            BinaryOperator::Or => a | b,
+            BinaryOperator::Xor => a ^ b,
+            BinaryOperator::Equal => (a == b) as i32,
+            BinaryOperator::NotEqual => (a != b) as i32,
+            BinaryOperator::Less => (a < b) as i32,
+            BinaryOperator::LessOrEqual => (a <= b) as i32,
+            BinaryOperator::Greater => (a > b) as i32,
+            BinaryOperator::GreaterOrEqual => (a >= b) as i32,
+        })
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Number(value) => write!(f, "{}", value),
+            Expression::Label(label) => f.write_str(label),
+            Expression::Unary(UnaryOperator::Negate, operand) => write!(f, "-{}", operand),
+            Expression::Unary(UnaryOperator::Not, operand) => write!(f, "~{}", operand),
+            Expression::Binary(operator, a, b) => {
+                let operand = |f: &mut std::fmt::Formatter<'_>, operand: &Expression| match operand
+                {
+                    Expression::Binary(..) => write!(f, "({})", operand),
+                    _ => write!(f, "{}", operand),
+                };
+                operand(f, a)?;
+                f.write_str(operator.symbol())?;
+                operand(f, b)
+            }
+        }
+    }
+}
+
+impl Expression {
+    // Evaluates the expression, `lookup` gives the value of every label
+    pub(crate) fn evaluate(&self, lookup: &dyn Fn(&str) -> Option<i32>) -> Result<i32, String> {
+        match self {
+            Expression::Number(value) => Ok(*value),
+            Expression::Label(label) => lookup(label).ok_or(format!("Undefined label `{}`", label)),
+            Expression::Unary(UnaryOperator::Negate, operand) => {
+                Ok(operand.evaluate(lookup)?.wrapping_neg())
+            }
+            Expression::Unary(UnaryOperator::Not, operand) => Ok(!operand.evaluate(lookup)?),
+            Expression::Binary(operator, a, b) => {
+                operator.apply(a.evaluate(lookup)?, b.evaluate(lookup)?)
+            }
+        }
+    }
+
+    // How many times the expression counts an address: 0 for a constant such as `END-START`,
+    // 1 for an address such as `TABLE+2`, anything else has no meaning
+    pub(crate) fn weight(&self) -> Result<i32, String> {
+        match self {
+            Expression::Number(_) => Ok(0),
+            Expression::Label(_) => Ok(1),
+            Expression::Unary(UnaryOperator::Negate, operand) => Ok(-operand.weight()?),
+            Expression::Binary(BinaryOperator::Add, a, b) => Ok(a.weight()? + b.weight()?),
+            Expression::Binary(BinaryOperator::Subtract, a, b) => Ok(a.weight()? - b.weight()?),
+            Expression::Unary(_, operand) if operand.weight()? == 0 => Ok(0),
+            Expression::Binary(_, a, b) if a.weight()? == 0 && b.weight()? == 0 => Ok(0),
+            _ => Err(format!("`{}` can only add or subtract addresses", self)),
+        }
+    }
+
+    // Calls `rename` on every label of the expression
+    pub(crate) fn rename_labels(&mut self, rename: &dyn Fn(&mut String)) {
+        match self {
+            Expression::Number(_) => {}
+            Expression::Label(label) => rename(label),
+            Expression::Unary(_, operand) => operand.rename_labels(rename),
+            Expression::Binary(_, a, b) => {
+                a.rename_labels(rename);
+                b.rename_labels(rename);
+            }
+        }
+    }
+
+    // Every label used by the expression, in order of appearance
+    pub(crate) fn labels(&self) -> Vec<&str> {
+        let mut labels = vec![];
+        self.collect_labels(&mut labels);
+        labels
+    }
+
+    fn collect_labels<'a>(&'a self, labels: &mut Vec<&'a str>) {
+        match self {
+            Expression::Number(_) => {}
+            Expression::Label(label) => {
+                if !labels.contains(&label.as_str()) {
+                    labels.push(label);
+                }
+            }
+            Expression::Unary(_, operand) => operand.collect_labels(labels),
+            Expression::Binary(_, a, b) => {
+                a.collect_labels(labels);
+                b.collect_labels(labels);
+            }
+        }
+    }
+
+    // Replaces the labels `values` knows with numbers, folding what becomes constant
+    pub(crate) fn substitute(&self, values: &dyn Fn(&str) -> Option<i32>) -> Result<Self, String> {
+        let expression = match self {
+            Expression::Label(label) => match values(label) {
+                Some(value) => Expression::Number(value),
+                None => self.clone(),
+            },
+            Expression::Number(_) => self.clone(),
+            Expression::Unary(operator, operand) => {
+                Expression::Unary(*operator, Box::new(operand.substitute(values)?))
+            }
+            Expression::Binary(operator, a, b) => Expression::Binary(
+                *operator,
+                Box::new(a.substitute(values)?),
+                Box::new(b.substitute(values)?),
+            ),
+        };
+
+        match expression.labels().is_empty() {
+            true => Ok(Expression::Number(expression.evaluate(&|_| None)?)),
+            false => Ok(expression),
+        }
+    }
+}
+
+pub(crate) fn parse_expression(text: &str) -> Result<Expression, String> {
+    let tokens = lex(text)?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+    };
+
+    let expression = parser.binary(0)?;
+    match parser.tokens.get(parser.position) {
+        Some(token) => Err(format!("Unexpected `{}`", token)),
+        None => expression.substitute(&|_| None),
+    }
+}
+
+// Splits an expression into numbers, labels, operators and parentheses
+fn lex(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '\'' {
+            chars.next();
+            let value = match chars.next() {
+                Some('\\') => match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('0') => '\0',
+                    Some('e') => '\x1B',
+                    Some(c @ ('\\' | '\'' | '"')) => c,
+                    _ => return Err("Unknown escape sequence in character literal".to_string()),
+                },
+                Some(c) => c,
+                None => return Err("Unterminated character literal".to_string()),
+            };
+            if chars.next() != Some('\'') {
+                return Err("Unterminated character literal".to_string());
+            }
+            tokens.push((value as u32).to_string());
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '#' {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_' || c == '#') {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(word);
+        } else if c == '(' || c == ')' {
+            tokens.push(chars.next().unwrap().to_string());
+        } else if OPERATOR_CHARS.contains(c) {
+            let mut operator = chars.next().unwrap().to_string();
+            if let Some(&next) = chars.peek() {
+                let pair = format!("{}{}", operator, next);
+                if ["<<", ">>", "==", "!=", "<=", ">="].contains(&pair.as_str()) {
+                    chars.next();
+                    operator = pair;
+                }
+            }
+            tokens.push(operator);
+        } else {
+            return Err(format!("Unexpected character `{}`", c));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expression, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut expression = self.binary(level + 1)?;
+        while let Some(operator) = self.peek().and_then(|token| {
+            PRECEDENCE[level]
+                .iter()
+                .find(|(symbol, _)| *symbol == token)
+                .map(|(_, operator)| *operator)
+        }) {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(right));
+        }
+        Ok(expression)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        match self.next().as_deref() {
+            Some("-") => Ok(Expression::Unary(
+                UnaryOperator::Negate,
+                Box::new(self.unary()?),
+            )),
+            Some("~") => Ok(Expression::Unary(
+                UnaryOperator::Not,
+                Box::new(self.unary()?),
+            )),
+            Some("+") => self.unary(),
+            Some("(") => {
+                let expression = self.binary(0)?;
+                match self.next().as_deref() {
+                    Some(")") => Ok(expression),
+                    _ => Err("Missing `)`".to_string()),
+                }
+            }
+            Some(word) => {
+                if let Some(value) = parse_number(word) {
+                    Ok(Expression::Number(value))
+                } else if is_label(word) {
+                    Ok(Expression::Label(word.to_string()))
+                } else {
+                    Err(format!("Unexpected `{}`", word))
+                }
+            }
+            None => Err("Incomplete expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_expression, Expression};
+
+    fn evaluate(text: &str) -> i32 {
+        parse_expression(text)
+            .unwrap()
+            .evaluate(&|label| match label {
+                "START" => Some(0x3000),
+                "END" => Some(0x3010),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_constant_expressions() {
+        assert_eq!(parse_expression("x10<<2"), Ok(Expression::Number(64)));
+        assert_eq!(parse_expression("1+2*3"), Ok(Expression::Number(7)));
+        assert_eq!(parse_expression("(1+2)*3"), Ok(Expression::Number(9)));
+        assert_eq!(parse_expression("1<<2+1"), Ok(Expression::Number(8)));
+        assert_eq!(parse_expression("'A'+1"), Ok(Expression::Number(66)));
+        assert_eq!(parse_expression("'\\n'"), Ok(Expression::Number(10)));
+        assert_eq!(parse_expression("-#3&~0"), Ok(Expression::Number(-3)));
+        assert_eq!(parse_expression("2==2"), Ok(Expression::Number(1)));
+        assert!(parse_expression("1/0").is_err());
+        assert!(parse_expression("(1+2").is_err());
+        assert!(parse_expression("1 2").is_err());
+    }
+
+    #[test]
+    fn test_label_expressions() {
+        assert_eq!(evaluate("END-START"), 16);
+        assert_eq!(evaluate("START+2"), 0x3002);
+
+        let weight = |text: &str| parse_expression(text).unwrap().weight();
+        assert_eq!(weight("END-START"), Ok(0));
+        assert_eq!(weight("START+(END-START)/2"), Ok(1));
+        assert!(weight("START*2").is_err());
+        assert_eq!(
+            parse_expression("END - START + 1").unwrap().to_string(),
+            "(END-START)+1"
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use super::{
+    parser::{Line, Statement, Token},
+    pseudo::{expansion, is_pseudo},
+    Program,
+};
+
+// The statement a word was assembled from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WordSource {
+    // the included file holding the statement, None for the main source
+    pub(crate) file: Option<String>,
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    // whether the word is an instruction rather than data
+    pub(crate) code: bool,
+}
+
+// Where the words and labels of a program come from, used for listings and debug info
+// For listings, lines of macro bodies and included files count as the line of the call or include
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SourceMap {
+    // address and words of every statement, by source line
+    pub(crate) words: BTreeMap<usize, Vec<(u16, Vec<u16>)>>,
+    // line defining every label, and the lines referring to it
+    pub(crate) definitions: BTreeMap<String, usize>,
+    pub(crate) references: BTreeMap<String, BTreeSet<usize>>,
+    // the source text and the number of words of every expanded pseudo-instruction, by address
+    pub(crate) pseudo: BTreeMap<u16, (String, usize)>,
+    // the statement every word comes from, macro bodies and included files included
+    pub(crate) locations: BTreeMap<u16, WordSource>,
+}
+
+impl SourceMap {
+    // Records the words a line was assembled into and the labels it defines or uses
+    pub(crate) fn record(&mut self, line: &Line, address: u16, words: &[u16]) {
+        if let Some(label) = &line.label {
+            self.definitions.insert(label.clone(), line.origin);
+        }
+        if let Some(Statement::Instruction { mnemonic, operands }) = &line.statement {
+            if is_pseudo(mnemonic) {
+                let operands = operands
+                    .iter()
+                    .map(Token::to_string)
+                    .collect::<Vec<String>>();
+                let text = format!("{} {}", mnemonic, operands.join(", "));
+                self.pseudo
+                    .insert(address, (text.trim_end().to_string(), words.len()));
+            }
+        }
+        let code = matches!(line.statement, Some(Statement::Instruction { .. }));
+        for (address, _) in (address..).zip(words) {
+            let source = WordSource {
+                file: line.file.clone(),
+                line: line.number,
+                column: line.column,
+                code,
+            };
+            self.locations.insert(address, source);
+        }
+        if let Some(
+            Statement::Instruction { operands, .. } | Statement::Directive { operands, .. },
+        ) = &line.statement
+        {
+            for operand in operands {
+                let labels = match operand {
+                    Token::Label(label) => vec![label.as_str()],
+                    Token::Expression(expression) => expression.labels(),
+                    _ => vec![],
+                };
+                for label in labels {
+                    self.references
+                        .entry(label.to_string())
+                        .or_default()
+                        .insert(line.origin);
+                }
+            }
+        }
+        if line.label.is_some() || line.statement.is_some() {
+            self.words
+                .entry(line.origin)
+                .or_default()
+                .push((address, words.to_vec()));
+        }
+    }
+}
+
+/// Every line of `source` next to the address and the words it was assembled into, in hex and
+/// binary, followed by the symbol table with the lines defining and using every label
+///
+/// The words of a pseudo-instruction are listed under it with the instruction they encode.
+pub fn listing(source: &str, program: &Program) -> String {
+    let map = &program.source_map;
+    let mut text = String::new();
+
+    writeln!(text, "Addr   Hex   Binary               Line  Source").unwrap();
+    for (index, source_line) in source.lines().enumerate() {
+        let number = index + 1;
+        let entries = map.words.get(&number).map_or(&[][..], Vec::as_slice);
+        // address, word and what the word encodes for pseudo-instructions
+        let mut rows: Vec<(Option<u16>, Option<u16>, String)> = vec![];
+        for (address, words) in entries {
+            let expansion = map.pseudo.get(address).map(|(text, _)| {
+                let mnemonic = text.split_whitespace().next().unwrap_or_default();
+                expansion(mnemonic, words)
+            });
+            match expansion {
+                Some(expansion) => {
+                    if rows.is_empty() {
+                        rows.push((Some(*address), None, String::new()));
+                    }
+                    rows.extend((*address..).zip(expansion).map(|(address, word)| {
+                        (Some(address), Some(word.encode()), format!("+ {}", word))
+                    }));
+                }
+                None => rows.extend(
+                    (*address..)
+                        .zip(words)
+                        .map(|(address, word)| (Some(address), Some(*word), String::new())),
+                ),
+            }
+        }
+        if rows.is_empty() {
+            // a label alone still has an address
+            rows.push((
+                entries.first().map(|(address, _)| *address),
+                None,
+                String::new(),
+            ));
+        }
+
+        for (row, (address, word, note)) in rows.into_iter().enumerate() {
+            let (number, source_line) = match row {
+                0 => (format!("{:>4}", number), source_line),
+                _ => (String::new(), note.as_str()),
+            };
+            let line = format!(
+                "{:<5}  {:<4}  {:<19}  {:>4}  {}",
+                address.map_or(String::new(), |address| format!("x{:04X}", address)),
+                word.map_or(String::new(), |word| format!("{:04X}", word)),
+                word.map_or(String::new(), binary),
+                number,
+                source_line
+            );
+            writeln!(text, "{}", line.trim_end()).unwrap();
+        }
+    }
+
+    let labels = program
+        .symbols
+        .keys()
+        .chain(&program.externals)
+        .collect::<BTreeSet<&String>>();
+    let width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+    writeln!(text).unwrap();
+    writeln!(
+        text,
+        "{:<width$}  Addr    Line  References",
+        "Symbol",
+        width = width.max(6)
+    )
+    .unwrap();
+    for label in labels {
+        let address = match program.symbols.get(label) {
+            Some(address) => format!("x{:04X}", address),
+            None => "extern".to_string(),
+        };
+        let definition = map
+            .definitions
+            .get(label)
+            .map_or(String::new(), usize::to_string);
+        let references = map
+            .references
+            .get(label)
+            .into_iter()
+            .flatten()
+            .map(usize::to_string)
+            .collect::<Vec<String>>();
+        let line = format!(
+            "{:<width$}  {:<6}  {:>4}  {}",
+            label,
+            address,
+            definition,
+            references.join(", "),
+            width = width.max(6)
+        );
+        writeln!(text, "{}", line.trim_end()).unwrap();
+    }
+
+    text
+}
+
+// A word in binary, grouped by nibble
+fn binary(word: u16) -> String {
+    let digits = format!("{:016b}", word);
+    let nibbles = digits.as_bytes().chunks(4).map(|nibble| {
+        // the digits are ASCII
+        std::str::from_utf8(nibble).unwrap()
+    });
+    nibbles.collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+
+    use super::listing;
+
+    #[test]
+    fn test_listing() {
+        let source = ".MACRO CLEAR reg
+    AND \\reg, \\reg, #0
+.ENDM
+.ORIG x3000
+        CLEAR R1
+LOOP    ADD R1, R1, #1
+        BRp LOOP
+MSG     .STRINGZ \"A\"
+        LEA R0, MSG
+        PUSH R0
+.END";
+        let program = assemble(source).unwrap();
+        let text = listing(source, &program);
+        let lines = text.lines().collect::<Vec<&str>>();
+
+        assert_eq!(lines[0], "Addr   Hex   Binary               Line  Source");
+        assert_eq!(
+            lines[1],
+            "                                     1  .MACRO CLEAR reg"
+        );
+        assert_eq!(
+            lines[5],
+            "x3000  5260  0101 0010 0110 0000     5          CLEAR R1"
+        );
+        assert_eq!(
+            lines[8],
+            "x3003  0041  0000 0000 0100 0001     8  MSG     .STRINGZ \"A\""
+        );
+        assert_eq!(lines[9], "x3004  0000  0000 0000 0000 0000");
+        assert_eq!(
+            lines[10],
+            "x3005  E1FD  1110 0001 1111 1101     9          LEA R0, MSG"
+        );
+
+        assert_eq!(
+            lines[11],
+            "x3006                               10          PUSH R0"
+        );
+        assert_eq!(
+            lines[12],
+            "x3006  1DBF  0001 1101 1011 1111        + ADD R6, R6, #-1"
+        );
+        assert_eq!(
+            lines[13],
+            "x3007  7180  0111 0001 1000 0000        + STR R0, R6, #0"
+        );
+
+        assert_eq!(lines[16], "Symbol  Addr    Line  References");
+        assert_eq!(lines[17], "LOOP    x3001      6  7");
+        assert_eq!(lines[18], "MSG     x3003      8  9");
+    }
+
+    #[test]
+    fn test_listing_pseudo_instruction_before_a_look_alike() {
+        let source = ".ORIG x3000\nNEG R1\nADD R1, R1, R2\n.END";
+        let program = assemble(source).unwrap();
+        let text = listing(source, &program);
+        let lines = text.lines().collect::<Vec<&str>>();
+
+        assert_eq!(lines[2], "x3000                                2  NEG R1");
+        assert_eq!(
+            lines[4],
+            "x3001  1261  0001 0010 0110 0001        + ADD R1, R1, #1"
+        );
+        assert_eq!(
+            lines[5],
+            "x3002  1242  0001 0010 0100 0010     3  ADD R1, R1, R2"
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::{BTreeSet, HashMap};
+
+use super::{
+    parser::{is_label, parse_line, tokenize, Line, Statement, Token},
+    pseudo::is_pseudo,
+    AssemblerError,
+};
+
+// Expansions nested deeper than this, counting include files, are assumed to be recursive
+const MAX_DEPTH: usize = 16;
+
+// A `.MACRO NAME param, param` ... `.ENDM` definition, the body is kept as source text
+#[derive(Debug, Clone)]
+struct Macro {
+    name: String,
+    params: Vec<String>,
+    // the included file defining the macro, None for the main source
+    file: Option<String>,
+    // source line number and text of every line of the body
+    body: Vec<(usize, String)>,
+}
+
+// A line invoking a macro, e.g. `SAVE PUSH R7`
+struct Call {
+    label: Option<String>,
+    name: String,
+    args: Vec<String>,
+}
+
+// The macros defined so far
+#[derive(Debug, Default)]
+pub(crate) struct Macros {
+    definitions: HashMap<String, Macro>,
+    // number of expansions so far, used to make local labels unique
+    expansions: usize,
+}
+
+// True for a `.MACRO` line
+pub(crate) fn is_definition(words: &[String]) -> bool {
+    words
+        .first()
+        .is_some_and(|word| word.eq_ignore_ascii_case(".MACRO"))
+}
+
+impl Macros {
+    // Reads a definition from its header up to `.ENDM`
+    pub(crate) fn define<'a>(
+        &mut self,
+        header: &[String],
+        number: usize,
+        file: Option<&str>,
+        lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    ) -> Result<(), AssemblerError> {
+        let error = |message: String| AssemblerError::new(number, message);
+
+        // a macro may replace a pseudo-instruction, e.g. a PUSH using another stack pointer
+        let name = match header.get(1) {
+            Some(name) if is_label(name) || is_pseudo(name) => name.to_uppercase(),
+            Some(name) => return Err(error(format!("Invalid macro name `{}`", name))),
+            None => return Err(error(".MACRO expects a name".to_string())),
+        };
+        if self.definitions.contains_key(&name) {
+            return Err(error(format!("Macro {} is defined more than once", name)));
+        }
+
+        let mut params = vec![];
+        for param in &header[2..] {
+            let param = param.strip_prefix('\\').unwrap_or(param);
+            if !is_label(param) || params.iter().any(|other| other == param) {
+                return Err(error(format!("Invalid macro parameter `{}`", param)));
+            }
+            params.push(param.to_string());
+        }
+
+        let mut body = vec![];
+        for (line, text) in lines.by_ref() {
+            let words = tokenize(text).map_err(|message| AssemblerError::new(line, message))?;
+            if is_definition(&words) {
+                return Err(AssemblerError::new(
+                    line,
+                    "Macro definitions can't be nested".to_string(),
+                ));
+            }
+            if words
+                .first()
+                .is_some_and(|word| word.eq_ignore_ascii_case(".ENDM"))
+            {
+                let file = file.map(str::to_string);
+                self.definitions.insert(
+                    name.clone(),
+                    Macro {
+                        name,
+                        params,
+                        file,
+                        body,
+                    },
+                );
+                return Ok(());
+            }
+            body.push((line, text.to_string()));
+        }
+
+        Err(error(format!("Missing .ENDM for macro {}", name)))
+    }
+
+    fn is_macro(&self, word: &str) -> bool {
+        self.definitions.contains_key(&word.to_uppercase())
+    }
+
+    fn call(&self, words: &[String]) -> Option<Call> {
+        let (label, words) = match words {
+            [label, name, ..] if !self.is_macro(label) && self.is_macro(name) => {
+                (Some(label.clone()), &words[1..])
+            }
+            _ => (None, words),
+        };
+        let name = words.first()?.to_uppercase();
+
+        self.definitions.contains_key(&name).then(|| Call {
+            label,
+            name,
+            args: words[1..].to_vec(),
+        })
+    }
+
+    // Parses a source line, expanding it when it invokes a macro
+    // `file` is the included file holding the line and `context` where the line itself comes from
+    pub(crate) fn parse(
+        &mut self,
+        text: &str,
+        number: usize,
+        file: Option<&str>,
+        context: &[String],
+    ) -> Result<Vec<Line>, AssemblerError> {
+        let words = tokenize(text).map_err(|message| {
+            AssemblerError::new(number, message).with_context(context.to_vec())
+        })?;
+
+        match self.call(&words) {
+            Some(call) => self.expand(&call, number, file, context),
+            None => {
+                let mut line = parse_line(text, number)
+                    .map_err(|error| error.with_context(context.to_vec()))?;
+                line.file = file.map(str::to_string);
+                line.context = context.to_vec();
+                Ok(vec![line])
+            }
+        }
+    }
+
+    fn expand(
+        &mut self,
+        call: &Call,
+        number: usize,
+        file: Option<&str>,
+        context: &[String],
+    ) -> Result<Vec<Line>, AssemblerError> {
+        let definition = self.definitions[&call.name].clone();
+        let error =
+            |message: String| AssemblerError::new(number, message).with_context(context.to_vec());
+
+        if context.len() >= MAX_DEPTH {
+            return Err(error(format!(
+                "Macro {} is nested too deeply, is it recursive?",
+                definition.name
+            )));
+        }
+        if call.args.len() != definition.params.len() {
+            return Err(error(format!(
+                "Macro {} expects {} arguments, found {}",
+                definition.name,
+                definition.params.len(),
+                call.args.len()
+            )));
+        }
+        if let Some(label) = call.label.as_ref().filter(|label| !is_label(label)) {
+            return Err(error(format!("Invalid label `{}`", label)));
+        }
+
+        self.expansions += 1;
+        let suffix = self.expansions;
+        let mut inner_context = vec![match &definition.file {
+            Some(file) => format!(
+                "in macro {} of {} called from line {}",
+                definition.name, file, number
+            ),
+            None => format!("in macro {} called from line {}", definition.name, number),
+        }];
+        inner_context.extend_from_slice(context);
+
+        let mut lines = vec![];
+        // labels written in the body itself are local to this expansion
+        let mut locals = BTreeSet::new();
+        for (line, text) in &definition.body {
+            if let Some(label) = self.body_label(text) {
+                locals.insert(label);
+            }
+            let text = substitute(text, &definition.params, &call.args);
+            let body_file = definition.file.as_deref();
+            lines.extend(self.parse(&text, *line, body_file, &inner_context)?);
+        }
+
+        for line in &mut lines {
+            rename_locals(line, &locals, suffix);
+            line.origin = number;
+        }
+        if let Some(label) = &call.label {
+            lines.insert(
+                0,
+                Line {
+                    number,
+                    label: Some(label.clone()),
+                    statement: None,
+                    file: file.map(str::to_string),
+                    context: context.to_vec(),
+                    origin: number,
+                    column: 1,
+                },
+            );
+        }
+
+        Ok(lines)
+    }
+
+    // The label defined by a line of a macro body, if it is written out rather than a parameter
+    fn body_label(&self, text: &str) -> Option<String> {
+        let words = tokenize(text).ok()?;
+        let first = words.first()?;
+
+        (is_label(first) && !self.is_macro(first)).then(|| first.clone())
+    }
+}
+
+// Replaces every `\param` with the matching argument
+fn substitute(text: &str, params: &[String], args: &[String]) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        match params.iter().position(|param| *param == name) {
+            Some(index) => result.push_str(&args[index]),
+            None => {
+                result.push('\\');
+                result.push_str(&name);
+            }
+        }
+    }
+
+    result
+}
+
+// Renames the local labels of an expansion `LOOP` to `LOOP.<suffix>`
+fn rename_locals(line: &mut Line, locals: &BTreeSet<String>, suffix: usize) {
+    let rename = |label: &mut String| {
+        if locals.contains(label) {
+            *label = format!("{}.{}", label, suffix);
+        }
+    };
+
+    if let Some(label) = &mut line.label {
+        rename(label);
+    }
+    if let Some(Statement::Instruction { operands, .. } | Statement::Directive { operands, .. }) =
+        &mut line.statement
+    {
+        for operand in operands {
+            match operand {
+                Token::Label(label) => rename(label),
+                Token::Expression(expression) => expression.rename_labels(&rename),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble;
+
+    const MACROS: &str = "
+        .MACRO PUSH reg
+                ADD R6, R6, #-1
+                STR \\reg, R6, #0
+        .ENDM
+        .MACRO POP reg
+                LDR \\reg, R6, #0
+                ADD R6, R6, #1
+        .ENDM
+        .MACRO COUNTDOWN reg, count
+                AND \\reg, \\reg, #0
+                ADD \\reg, \\reg, \\count
+        LOOP    ADD \\reg, \\reg, #-1
+                BRp LOOP
+        .ENDM
+        .MACRO SWAP a, b
+                PUSH \\a
+                PUSH \\b
+                POP \\a
+                POP \\b
+        .ENDM
+    ";
+
+    #[test]
+    fn test_expand_macros() {
+        let program = assemble(&format!(
+            "{}
+            .ORIG x3000
+            START   PUSH R1
+                    POP R2
+                    .END",
+            MACROS
+        ))
+        .unwrap();
+
+        assert_eq!(
+            program.segments[0].words,
+            vec![0x1DBF, 0x7380, 0x6580, 0x1DA1]
+        );
+        assert_eq!(program.symbols["START"], 0x3000);
+        // instructions map back to the lines of the macro body
+        assert_eq!(program.lines[&0x3001], 4);
+    }
+
+    #[test]
+    fn test_local_labels_and_nesting() {
+        let program = assemble(&format!(
+            "{}
+            .ORIG x3000
+                    COUNTDOWN R1, #3
+                    COUNTDOWN R2, #5
+                    SWAP R1, R2
+                    HALT
+                    .END",
+            MACROS
+        ))
+        .unwrap();
+
+        assert_eq!(program.symbols["LOOP.1"], 0x3002);
+        assert_eq!(program.symbols["LOOP.2"], 0x3006);
+        // each BRp goes back to the loop of its own expansion
+        assert_eq!(program.segments[0].words[3], 0x03FE);
+        assert_eq!(program.segments[0].words[7], 0x03FE);
+        assert_eq!(program.segments[0].words.len(), 8 + 8 + 1);
+    }
+
+    #[test]
+    fn test_local_labels_in_expressions() {
+        let program = assemble(
+            "
+        .MACRO TABLE reg
+                LEA \\reg, DATA+1
+                BRnzp SKIP+0
+        DATA    .FILL DATA+1
+                .FILL 7
+        SKIP    ADD \\reg, \\reg, #0
+        .ENDM
+            .ORIG x3000
+                    TABLE R1
+                    TABLE R2
+                    .END",
+        )
+        .unwrap();
+
+        assert_eq!(program.symbols["DATA.1"], 0x3002);
+        assert_eq!(program.symbols["DATA.2"], 0x3007);
+        // LEA R1, DATA.1+1 and BRnzp SKIP.1
+        assert_eq!(program.segments[0].words[0], 0xE202);
+        assert_eq!(program.segments[0].words[1], 0x0E02);
+        assert_eq!(program.segments[0].words[2], 0x3003);
+        assert_eq!(program.segments[0].words[7], 0x3008);
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        let error = assemble(&format!(
+            "{}
+            .ORIG x3000
+                    SWAP R1, #2
+                    .END",
+            MACROS
+        ))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 4: Invalid operands for STR \
+             (in macro PUSH called from line 18, in macro SWAP called from line 24)"
+        );
+
+        let error = assemble(&format!("{}\n.ORIG x3000\nPUSH R1, R2\n.END", MACROS)).unwrap_err();
+        assert_eq!(error.message, "Macro PUSH expects 1 arguments, found 2");
+
+        let recursive = ".MACRO AGAIN\nAGAIN\n.ENDM\n.ORIG x3000\nAGAIN\n.END";
+        assert!(assemble(recursive)
+            .unwrap_err()
+            .message
+            .contains("nested too deeply"));
+
+        assert!(assemble(".MACRO OPEN\nHALT\n").is_err());
+    }
+}
@@ -2,402 +2,492 @@ use std::fmt::Display;
 
 use crate::vm::opcodes::Opcodes;
 
-#[derive(Debug)]
-pub(crate) struct Instruction {
-    pub(crate) opcode: Opcodes,
-    pub(crate) dr: u16,
-    pub(crate) sr1: u16,
-    pub(crate) sr2: u16,
-    pub(crate) imm5: u16,
-    pub(crate) imm_or_cond_flag: u16,
-    pub(crate) nzp: u16,
-    pub(crate) base_r: u16,
-    pub(crate) offset_6: u16,
-    pub(crate) trap_vect_8: u16,
-    pub(crate) pc_offset_9: u16,
-    pub(crate) pc_offset_11: u16,
+mod assemble;
+pub use assemble::{assemble, assemble_file, AssemblerError, Program, Segment};
+pub(crate) use assemble::{Relocation, RelocationKind};
+
+mod disassembler;
+pub use disassembler::disassemble;
+
+mod expression;
+mod macros;
+mod parser;
+mod preprocessor;
+mod pseudo;
+pub(crate) use parser::parse_string;
+pub use parser::{parse_address, parse_number};
+
+mod symbols;
+pub use symbols::{parse_symbols, symbols_to_string};
+
+mod debug_info;
+pub use debug_info::DebugInfo;
+
+mod listing;
+pub use listing::listing;
+pub(crate) use listing::SourceMap;
+
+/// A register field of an instruction: one of the general purpose registers R0 to R7
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Gpr(u8);
+
+impl Gpr {
+    /// R0
+    pub const R0: Gpr = Gpr(0);
+    /// R1
+    pub const R1: Gpr = Gpr(1);
+    /// R2
+    pub const R2: Gpr = Gpr(2);
+    /// R3
+    pub const R3: Gpr = Gpr(3);
+    /// R4
+    pub const R4: Gpr = Gpr(4);
+    /// R5, the frame pointer by convention
+    pub const R5: Gpr = Gpr(5);
+    /// R6, the stack pointer by convention
+    pub const R6: Gpr = Gpr(6);
+    /// R7, the return address of JSR, JSRR and TRAP
+    pub const R7: Gpr = Gpr(7);
+
+    /// The register numbered `number`, None past R7
+    pub fn new(number: u16) -> Option<Gpr> {
+        (number < 8).then_some(Gpr(number as u8))
+    }
+
+    // The register in the 3 bits of `word` starting at bit `shift`
+    fn field(word: u16, shift: u16) -> Gpr {
+        Gpr(((word >> shift) & 0x7) as u8)
+    }
+
+    /// Its number, 0 to 7, which is also its index in the registers of the vm
+    pub fn number(self) -> u16 {
+        self.0 as u16
+    }
+}
+
+impl Display for Gpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "R{}", self.0)
+    }
+}
+
+/// Second operand of ADD and AND: either a source register or a
+/// sign-extended 5 bit immediate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// A source register
+    Reg(Gpr),
+    /// The immediate value
+    Imm(i16),
+}
+
+/// A decoded instruction
+///
+/// Every variant only carries the fields its encoding uses and all
+/// offsets are stored already sign-extended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// `BRnzp`: jumps to PC + offset when one of the flags matches the condition codes
+    Br {
+        /// branch when the last result was negative
+        n: bool,
+        /// branch when the last result was zero
+        z: bool,
+        /// branch when the last result was positive
+        p: bool,
+        /// offset from the incremented PC
+        offset: i16,
+    },
+    /// `ADD`: DR = SR1 + operand
+    Add {
+        /// destination register
+        dr: Gpr,
+        /// first source register
+        sr1: Gpr,
+        /// second source
+        operand: Operand,
+    },
+    /// `LD`: loads the word at PC + offset
+    Ld {
+        /// destination register
+        dr: Gpr,
+        /// offset from the incremented PC
+        offset: i16,
+    },
+    /// `ST`: stores SR at PC + offset
+    St {
+        /// source register
+        sr: Gpr,
+        /// offset from the incremented PC
+        offset: i16,
+    },
+    /// `JSR`: calls the subroutine at PC + offset, R7 gets the return address
+    Jsr {
+        /// offset from the incremented PC
+        offset: i16,
+    },
+    /// `JSRR`: calls the subroutine whose address is in the base register
+    Jsrr {
+        /// base register
+        base_r: Gpr,
+    },
+    /// `AND`: DR = SR1 & operand
+    And {
+        /// destination register
+        dr: Gpr,
+        /// first source register
+        sr1: Gpr,
+        /// second source
+        operand: Operand,
+    },
+    /// `LDR`: loads the word at base register + offset
+    Ldr {
+        /// destination register
+        dr: Gpr,
+        /// base register
+        base_r: Gpr,
+        /// offset from the base register
+        offset: i16,
+    },
+    /// `STR`: stores SR at base register + offset
+    Str {
+        /// source register
+        sr: Gpr,
+        /// base register
+        base_r: Gpr,
+        /// offset from the base register
+        offset: i16,
+    },
+    /// `RTI`: return from interrupt, illegal in user mode
+    Rti,
+    /// `NOT`: DR = !SR
+    Not {
+        /// destination register
+        dr: Gpr,
+        /// source register
+        sr: Gpr,
+    },
+    /// `LDI`: loads the word whose address is at PC + offset
+    Ldi {
+        /// destination register
+        dr: Gpr,
+        /// offset from the incremented PC
+        offset: i16,
+    },
+    /// `STI`: stores SR at the address found at PC + offset
+    Sti {
+        /// source register
+        sr: Gpr,
+        /// offset from the incremented PC
+        offset: i16,
+    },
+    /// `JMP`, or `RET` for R7: jumps to the address in the base register
+    Jmp {
+        /// base register
+        base_r: Gpr,
+    },
+    /// The reserved opcode
+    Res,
+    /// `LEA`: DR = PC + offset
+    Lea {
+        /// destination register
+        dr: Gpr,
+        /// offset from the incremented PC
+        offset: i16,
+    },
+    /// `TRAP`: calls a service routine of the operating system
+    Trap {
+        /// trap vector, e.g. x25 for HALT
+        trap_vect: u8,
+    },
 }
 
 impl Instruction {
-    fn new(opcode: Opcodes) -> Self {
-        Instruction {
-            opcode,
-            dr: 0,
-            sr1: 0,
-            sr2: 0,
-            imm5: 0,
-            imm_or_cond_flag: 0,
-            nzp: 0,
-            base_r: 0,
-            offset_6: 0,
-            trap_vect_8: 0,
-            pc_offset_9: 0,
-            pc_offset_11: 0,
+    pub(crate) fn opcode(&self) -> Opcodes {
+        match self {
+            Instruction::Br { .. } => Opcodes::Br,
+            Instruction::Add { .. } => Opcodes::Add,
+            Instruction::Ld { .. } => Opcodes::Ld,
+            Instruction::St { .. } => Opcodes::St,
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. } => Opcodes::Jsr,
+            Instruction::And { .. } => Opcodes::And,
+            Instruction::Ldr { .. } => Opcodes::Ldr,
+            Instruction::Str { .. } => Opcodes::Str,
+            Instruction::Rti => Opcodes::Rti,
+            Instruction::Not { .. } => Opcodes::Not,
+            Instruction::Ldi { .. } => Opcodes::Ldi,
+            Instruction::Sti { .. } => Opcodes::Sti,
+            Instruction::Jmp { .. } => Opcodes::Jmp,
+            Instruction::Res => Opcodes::Res,
+            Instruction::Lea { .. } => Opcodes::Lea,
+            Instruction::Trap { .. } => Opcodes::Trap,
         }
     }
 
-    fn encode(&self) -> u16 {
-        match self.opcode {
-            Opcodes::Br => ((Opcodes::Br as u16) << 12) | (self.nzp << 9) | self.pc_offset_9,
-            Opcodes::Add => {
-                let mut res = ((Opcodes::Add as u16) << 12) | (self.dr << 9) | (self.sr1 << 6);
-                if self.imm_or_cond_flag == 1 {
-                    res |= (1 << 5) | self.imm5;
-                    res
-                } else {
-                    res |= self.sr2;
-                    res
-                }
+    /// The instruction word, `decode_instruction(instruction.encode())` gives `instruction` back
+    pub fn encode(&self) -> u16 {
+        let op = (self.opcode() as u16) << 12;
+        match *self {
+            Instruction::Br { n, z, p, offset } => {
+                op | (n as u16) << 11 | (z as u16) << 10 | (p as u16) << 9 | mask(offset, 9)
             }
-            Opcodes::Ld => ((Opcodes::Ld as u16) << 12) | self.dr << 9 | self.pc_offset_9,
-            Opcodes::St => ((Opcodes::St as u16) << 12) | self.sr1 << 9 | self.pc_offset_9,
-            Opcodes::Jsr => {
-                let mut res = (Opcodes::Jsr as u16) << 12;
-                if self.imm_or_cond_flag == 1 {
-                    res |= 1 << 11 | self.pc_offset_11;
-                    res
-                } else {
-                    res |= self.base_r << 6;
-                    res
+            Instruction::Add { dr, sr1, operand } | Instruction::And { dr, sr1, operand } => {
+                let res = op | dr.number() << 9 | sr1.number() << 6;
+                match operand {
+                    Operand::Reg(sr2) => res | sr2.number(),
+                    Operand::Imm(imm5) => res | 1 << 5 | mask(imm5, 5),
                 }
             }
-            Opcodes::And => {
-                let mut res = (Opcodes::And as u16) << 12 | self.dr << 9 | self.sr1 << 6;
-                if self.imm_or_cond_flag == 1 {
-                    res |= 1 << 5 | self.imm5;
-                    res
-                } else {
-                    res |= self.sr2;
-                    res
-                }
+            Instruction::Ld { dr, offset } => op | dr.number() << 9 | mask(offset, 9),
+            Instruction::St { sr, offset } => op | sr.number() << 9 | mask(offset, 9),
+            Instruction::Jsr { offset } => op | 1 << 11 | mask(offset, 11),
+            Instruction::Jsrr { base_r } => op | base_r.number() << 6,
+            Instruction::Ldr { dr, base_r, offset } => {
+                op | dr.number() << 9 | base_r.number() << 6 | mask(offset, 6)
             }
-            Opcodes::Ldr => {
-                (Opcodes::Ldr as u16) << 12 | self.dr << 9 | self.base_r << 6 | self.offset_6
+            Instruction::Str { sr, base_r, offset } => {
+                op | sr.number() << 9 | base_r.number() << 6 | mask(offset, 6)
             }
-            Opcodes::Str => {
-                (Opcodes::Str as u16) << 12 | self.sr1 << 9 | self.base_r << 6 | self.offset_6
-            }
-            Opcodes::Rti => unimplemented!(),
-            Opcodes::Not => (Opcodes::Not as u16) << 12 | self.dr << 9 | self.sr1 << 6 | 0x1F,
-            Opcodes::Ldi => ((Opcodes::Ldi as u16) << 12) | self.dr << 9 | self.pc_offset_9,
-            Opcodes::Sti => ((Opcodes::Sti as u16) << 12) | self.sr1 << 9 | self.pc_offset_9,
-            Opcodes::Jmp => ((Opcodes::Jmp as u16) << 12) | self.base_r << 6,
-            Opcodes::Res => unimplemented!(),
-            Opcodes::Lea => ((Opcodes::Lea as u16) << 12) | self.dr << 9 | self.pc_offset_9,
-            Opcodes::Trap => ((Opcodes::Trap as u16) << 12) | self.trap_vect_8,
+            Instruction::Rti => op,
+            Instruction::Not { dr, sr } => op | dr.number() << 9 | sr.number() << 6 | 0x3F,
+            Instruction::Ldi { dr, offset } => op | dr.number() << 9 | mask(offset, 9),
+            Instruction::Sti { sr, offset } => op | sr.number() << 9 | mask(offset, 9),
+            Instruction::Jmp { base_r } => op | base_r.number() << 6,
+            Instruction::Res => op,
+            Instruction::Lea { dr, offset } => op | dr.number() << 9 | mask(offset, 9),
+            Instruction::Trap { trap_vect } => op | trap_vect as u16,
         }
     }
 }
 
-fn encode_instruction_string(instruction: String) -> Instruction {
-    let assembly_code = instruction.split(" ").collect::<Vec<&str>>();
-    if assembly_code[0] == "BR" {
-        let mut instr = Instruction::new(Opcodes::Br);
-        instr.nzp = assembly_code[1].parse().unwrap();
-        instr.pc_offset_9 = assembly_code[2].parse().unwrap();
-        instr
-    } else if assembly_code[0] == "ADD" {
-        let mut instr = Instruction::new(Opcodes::Add);
-        instr.dr = assembly_code[1].parse().unwrap();
-        instr.sr1 = assembly_code[2].parse().unwrap();
-        if assembly_code[3].parse::<u8>().unwrap() == 1 {
-            instr.imm_or_cond_flag = 1;
-            instr.imm5 = assembly_code[4].parse().unwrap();
-        } else {
-            instr.sr2 = assembly_code[4].parse().unwrap();
-        }
-        instr
-    } else if assembly_code[0] == "LD" {
-        let mut instr = Instruction::new(Opcodes::Ld);
-        instr.dr = assembly_code[1].parse().unwrap();
-        instr.pc_offset_9 = assembly_code[2].parse().unwrap();
-        instr
-    } else if assembly_code[0] == "ST" {
-        let mut instr = Instruction::new(Opcodes::St);
-        instr.sr1 = assembly_code[1].parse().unwrap();
-        instr.pc_offset_9 = assembly_code[2].parse().unwrap();
-        instr
-    } else if assembly_code[0] == "JSR" {
-        let mut instr = Instruction::new(Opcodes::Jsr);
-        if assembly_code[1].parse::<u8>().unwrap() == 1 {
-            instr.imm_or_cond_flag = 1;
-            instr.pc_offset_11 = assembly_code[2].parse().unwrap();
-        } else {
-            instr.base_r = assembly_code[2].parse().unwrap();
-        }
-        instr
-    } else if assembly_code[0] == "AND" {
-        let mut instr = Instruction::new(Opcodes::And);
-        instr.dr = assembly_code[1].parse().unwrap();
-        instr.sr1 = assembly_code[2].parse().unwrap();
-        if assembly_code[3].parse::<u8>().unwrap() == 1 {
-            instr.imm_or_cond_flag = 1;
-            instr.imm5 = assembly_code[4].parse().unwrap();
-        } else {
-            instr.sr2 = assembly_code[4].parse().unwrap();
-        }
-        instr
-    } else if assembly_code[0] == "LDR" {
-        let mut instr = Instruction::new(Opcodes::Ldr);
-        instr.dr = assembly_code[1].parse().unwrap();
-        instr.base_r = assembly_code[2].parse().unwrap();
-        instr.offset_6 = assembly_code[3].parse().unwrap();
-        instr
-    } else if assembly_code[0] == "STR" {
-        let mut instr = Instruction::new(Opcodes::Str);
-        instr.sr1 = assembly_code[1].parse().unwrap();
-        instr.base_r = assembly_code[2].parse().unwrap();
-        instr.offset_6 = assembly_code[3].parse().unwrap();
-        instr
-    } else if assembly_code[0] == "RTI" {
-        Instruction::new(Opcodes::Rti)
-    } else if assembly_code[0] == "NOT" {
-        let mut instr = Instruction::new(Opcodes::Not);
-        instr.dr = assembly_code[1].parse().unwrap();
-        instr.sr1 = assembly_code[2].parse().unwrap();
-        instr.offset_6 = 0x3F;
-        instr
-    } else if assembly_code[0] == "LDI" {
-        let mut instr = Instruction::new(Opcodes::Ldi);
-        instr.dr = assembly_code[1].parse().unwrap();
-        instr.pc_offset_9 = assembly_code[2].parse().unwrap();
-        instr
-    } else if assembly_code[0] == "STI" {
-        let mut instr = Instruction::new(Opcodes::Sti);
-        instr.sr1 = assembly_code[1].parse().unwrap();
-        instr.pc_offset_9 = assembly_code[2].parse().unwrap();
-        instr
-    } else if assembly_code[0] == "JMP" {
-        let mut instr = Instruction::new(Opcodes::Jmp);
-        instr.base_r = assembly_code[1].parse().unwrap();
-        instr
-    } else if assembly_code[0] == "RES" {
-        Instruction::new(Opcodes::Res)
-    } else if assembly_code[0] == "LEA" {
-        let mut instr = Instruction::new(Opcodes::Lea);
-        instr.dr = assembly_code[1].parse().unwrap();
-        instr.pc_offset_9 = assembly_code[2].parse().unwrap();
-        instr
-    } else if assembly_code[0] == "TRAP" {
-        let mut instr = Instruction::new(Opcodes::Trap);
-        instr.trap_vect_8 = assembly_code[1].parse().unwrap();
-        instr
+// Truncates a signed value to its lowest `bit_count` bits
+fn mask(value: i16, bit_count: usize) -> u16 {
+    (value as u16) & ((1 << bit_count) - 1)
+}
+
+pub(crate) fn sign_extend(value: u16, bit_count: usize) -> u16 {
+    if (value >> (bit_count - 1)) & 1 == 1 {
+        (0xFFFF << bit_count) | value
     } else {
-        panic!("Incorrect instruction")
+        value
     }
 }
 
+// Extracts the lowest `bit_count` bits of an instruction as a signed offset
+fn signed_field(instruction: u16, bit_count: usize) -> i16 {
+    sign_extend(instruction & ((1 << bit_count) - 1), bit_count) as i16
+}
+
+// Disassembles an instruction into the syntax accepted by the assembler
 impl Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.opcode {
-            Opcodes::Br => f.write_fmt(format_args!(
-                "{} {} {}",
-                self.opcode, self.nzp, self.pc_offset_9
-            )),
-            Opcodes::Add => f.write_fmt(format_args!(
-                "{} {} {} {} {}",
-                self.opcode,
-                self.dr,
-                self.sr1,
-                self.imm_or_cond_flag,
-                if self.imm_or_cond_flag == 1 {
-                    self.imm5
-                } else {
-                    self.sr2
-                }
-            )),
-            Opcodes::Ld => f.write_fmt(format_args!(
-                "{} {} {}",
-                self.opcode, self.dr, self.pc_offset_9
-            )),
-            Opcodes::St => f.write_fmt(format_args!(
-                "{} {} {}",
-                self.opcode, self.sr1, self.pc_offset_9
+        let opcode = self.opcode();
+        match *self {
+            Instruction::Br {
+                n: false,
+                z: false,
+                p: false,
+                offset,
+            } => match offset {
+                0 => f.write_str("NOP"),
+                _ => f.write_fmt(format_args!("NOP #{}", offset)),
+            },
+            Instruction::Br { n, z, p, offset } => f.write_fmt(format_args!(
+                "{}{}{}{} #{}",
+                opcode,
+                if n { "n" } else { "" },
+                if z { "z" } else { "" },
+                if p { "p" } else { "" },
+                offset
             )),
-            Opcodes::Jsr => {
-                let op = self.opcode.to_string();
-                f.write_fmt(format_args!(
-                    "{} {} {}",
-                    if self.imm_or_cond_flag == 1 {
-                        op
-                    } else {
-                        "JSRR".to_string()
-                    },
-                    self.imm_or_cond_flag,
-                    if self.imm_or_cond_flag == 1 {
-                        self.pc_offset_11
-                    } else {
-                        self.base_r
+            Instruction::Add { dr, sr1, operand } | Instruction::And { dr, sr1, operand } => {
+                match operand {
+                    Operand::Reg(sr2) => {
+                        f.write_fmt(format_args!("{} {}, {}, {}", opcode, dr, sr1, sr2))
+                    }
+                    Operand::Imm(imm5) => {
+                        f.write_fmt(format_args!("{} {}, {}, #{}", opcode, dr, sr1, imm5))
                     }
-                ))
-            }
-            Opcodes::And => f.write_fmt(format_args!(
-                "{} {} {} {} {}",
-                self.opcode,
-                self.dr,
-                self.sr1,
-                self.imm_or_cond_flag,
-                if self.imm_or_cond_flag == 1 {
-                    self.imm5
-                } else {
-                    self.sr2
                 }
-            )),
-            Opcodes::Ldr => f.write_fmt(format_args!(
-                "{} {} {} {}",
-                self.opcode, self.dr, self.base_r, self.offset_6
-            )),
-            Opcodes::Str => f.write_fmt(format_args!(
-                "{} {} {} {}",
-                self.opcode, self.sr1, self.base_r, self.offset_6
-            )),
-            Opcodes::Rti => f.write_fmt(format_args!("{}", self.opcode)),
-            Opcodes::Not => f.write_fmt(format_args!("{} {} {}", self.opcode, self.dr, self.sr1)),
-            Opcodes::Ldi => f.write_fmt(format_args!(
-                "{} {} {}",
-                self.opcode, self.dr, self.pc_offset_9
-            )),
-            Opcodes::Sti => f.write_fmt(format_args!(
-                "{} {} {}",
-                self.opcode, self.sr1, self.pc_offset_9
-            )),
-            Opcodes::Jmp => {
-                let op = self.opcode.to_string();
-                f.write_fmt(format_args!(
-                    "{} {}",
-                    if self.base_r == 0x7 { "RET" } else { &op },
-                    self.base_r
-                ))
             }
-            Opcodes::Res => f.write_fmt(format_args!("{}", self.opcode)),
-            Opcodes::Lea => f.write_fmt(format_args!(
-                "{} {} {}",
-                self.opcode, self.dr, self.pc_offset_9
-            )),
-            Opcodes::Trap => f.write_fmt(format_args!("{} {}", self.opcode, self.trap_vect_8)),
+            Instruction::Ld { dr, offset }
+            | Instruction::Ldi { dr, offset }
+            | Instruction::Lea { dr, offset } => {
+                f.write_fmt(format_args!("{} {}, #{}", opcode, dr, offset))
+            }
+            Instruction::St { sr, offset } | Instruction::Sti { sr, offset } => {
+                f.write_fmt(format_args!("{} {}, #{}", opcode, sr, offset))
+            }
+            Instruction::Jsr { offset } => f.write_fmt(format_args!("{} #{}", opcode, offset)),
+            Instruction::Jsrr { base_r } => f.write_fmt(format_args!("JSRR {}", base_r)),
+            Instruction::Ldr { dr, base_r, offset } => {
+                f.write_fmt(format_args!("{} {}, {}, #{}", opcode, dr, base_r, offset))
+            }
+            Instruction::Str { sr, base_r, offset } => {
+                f.write_fmt(format_args!("{} {}, {}, #{}", opcode, sr, base_r, offset))
+            }
+            Instruction::Rti | Instruction::Res => f.write_fmt(format_args!("{}", opcode)),
+            Instruction::Not { dr, sr } => f.write_fmt(format_args!("{} {}, {}", opcode, dr, sr)),
+            Instruction::Jmp { base_r: Gpr::R7 } => f.write_str("RET"),
+            Instruction::Jmp { base_r } => f.write_fmt(format_args!("{} {}", opcode, base_r)),
+            Instruction::Trap { trap_vect } => {
+                f.write_fmt(format_args!("{} x{:02X}", opcode, trap_vect))
+            }
         }
     }
 }
 
-pub(crate) fn decode_instruction(instruction: u16) -> Instruction {
+/// Decodes an instruction word, every word decodes to some instruction
+pub fn decode_instruction(instruction: u16) -> Instruction {
     let opcode: Opcodes = (instruction >> 12).into();
-    let mut res = Instruction::new(opcode.clone());
+    let dr = Gpr::field(instruction, 9);
+    let sr1 = Gpr::field(instruction, 6);
     match opcode {
-        Opcodes::Br => {
-            res.nzp = (instruction >> 9) & 0x7;
-            res.pc_offset_9 = instruction & 0x1FF;
-            res
-        }
-        Opcodes::Add => {
-            res.dr = (instruction >> 9) & 0x7;
-            res.sr1 = (instruction >> 6) & 0x7;
-            if (instruction >> 5) & 1 == 1 {
-                res.imm_or_cond_flag = 1;
-                res.imm5 = instruction & 0x1F;
-                res
+        Opcodes::Br => Instruction::Br {
+            n: (instruction >> 11) & 1 == 1,
+            z: (instruction >> 10) & 1 == 1,
+            p: (instruction >> 9) & 1 == 1,
+            offset: signed_field(instruction, 9),
+        },
+        Opcodes::Add | Opcodes::And => {
+            let operand = if (instruction >> 5) & 1 == 1 {
+                Operand::Imm(signed_field(instruction, 5))
+            } else {
+                Operand::Reg(Gpr::field(instruction, 0))
+            };
+            if let Opcodes::Add = opcode {
+                Instruction::Add { dr, sr1, operand }
             } else {
-                res.sr2 = instruction & 0x7;
-                res
+                Instruction::And { dr, sr1, operand }
             }
         }
-        Opcodes::Ld => {
-            res.dr = (instruction >> 9) & 0x7;
-            res.pc_offset_9 = instruction & 0x1FF;
-            res
-        }
-        Opcodes::St => {
-            res.sr1 = (instruction >> 9) & 0x7;
-            res.pc_offset_9 = instruction & 0x1FF;
-            res
-        }
+        Opcodes::Ld => Instruction::Ld {
+            dr,
+            offset: signed_field(instruction, 9),
+        },
+        Opcodes::St => Instruction::St {
+            sr: dr,
+            offset: signed_field(instruction, 9),
+        },
         Opcodes::Jsr => {
             if (instruction >> 11) & 1 == 1 {
-                res.imm_or_cond_flag = 1;
-                res.pc_offset_11 = instruction & 0x7FF;
-                res
-            } else {
-                res.base_r = (instruction >> 6) & 0x7;
-                res
-            }
-        }
-        Opcodes::And => {
-            res.dr = (instruction >> 9) & 0x7;
-            res.sr1 = (instruction >> 6) & 0x7;
-            if ((instruction >> 5) & 0x1) == 1 {
-                res.imm_or_cond_flag = 1;
-                res.imm5 = instruction & 0x1F;
-                res
+                Instruction::Jsr {
+                    offset: signed_field(instruction, 11),
+                }
             } else {
-                res.sr2 = instruction & 0x7;
-                res
+                Instruction::Jsrr { base_r: sr1 }
             }
         }
-        Opcodes::Ldr => {
-            res.dr = (instruction >> 9) & 0x7;
-            res.base_r = (instruction >> 6) & 0x7;
-            res.offset_6 = instruction & 0x3F;
-            res
-        }
-        Opcodes::Str => {
-            res.sr1 = (instruction >> 9) & 0x7;
-            res.base_r = (instruction >> 6) & 0x7;
-            res.offset_6 = instruction & 0x3F;
-            res
-        }
-        Opcodes::Rti => unimplemented!(),
-        Opcodes::Not => {
-            res.dr = (instruction >> 9) & 0x7;
-            res.sr1 = (instruction >> 6) & 0x7;
-            res.offset_6 = 0x3F;
-            res
-        }
-        Opcodes::Ldi => {
-            res.dr = (instruction >> 9) & 0x7;
-            res.pc_offset_9 = instruction & 0x1FF;
-            res
-        }
-        Opcodes::Sti => {
-            res.sr1 = (instruction >> 9) & 0x7;
-            res.pc_offset_9 = instruction & 0x1FF;
-            res
-        }
-        Opcodes::Jmp => {
-            res.base_r = (instruction >> 6) & 0x7;
-            res
-        }
-        Opcodes::Res => unimplemented!(),
-        Opcodes::Lea => {
-            res.dr = (instruction >> 9) & 0x7;
-            res.pc_offset_9 = instruction & 0x1FF;
-            res
-        }
-        Opcodes::Trap => {
-            res.trap_vect_8 = instruction & 0xFF;
-            res
-        }
+        Opcodes::Ldr => Instruction::Ldr {
+            dr,
+            base_r: sr1,
+            offset: signed_field(instruction, 6),
+        },
+        Opcodes::Str => Instruction::Str {
+            sr: dr,
+            base_r: sr1,
+            offset: signed_field(instruction, 6),
+        },
+        Opcodes::Rti => Instruction::Rti,
+        Opcodes::Not => Instruction::Not { dr, sr: sr1 },
+        Opcodes::Ldi => Instruction::Ldi {
+            dr,
+            offset: signed_field(instruction, 9),
+        },
+        Opcodes::Sti => Instruction::Sti {
+            sr: dr,
+            offset: signed_field(instruction, 9),
+        },
+        Opcodes::Jmp => Instruction::Jmp { base_r: sr1 },
+        Opcodes::Res => Instruction::Res,
+        Opcodes::Lea => Instruction::Lea {
+            dr,
+            offset: signed_field(instruction, 9),
+        },
+        Opcodes::Trap => Instruction::Trap {
+            trap_vect: (instruction & 0xFF) as u8,
+        },
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::assembler::encode_instruction_string;
+    use crate::assembler::{assemble, Gpr, Instruction, Operand};
 
     use super::decode_instruction;
 
+    // Assembles a single instruction placed at x3000
+    fn assemble_instruction(instruction: &str) -> Instruction {
+        let program = assemble(&format!(".ORIG x3000\n{}\n.END", instruction)).unwrap();
+        decode_instruction(program.segments[0].words[0])
+    }
+
     #[test]
     fn test_decode_instruction() {
         let instruction = decode_instruction(0x475);
-        println!("{}", instruction);
+        assert_eq!(
+            instruction,
+            Instruction::Br {
+                n: false,
+                z: true,
+                p: false,
+                offset: 117
+            }
+        );
+        assert_eq!(instruction.to_string(), "BRz #117");
+    }
+
+    #[test]
+    fn test_decode_negative_offset() {
+        // 0FFE -> 0000 111 111111110
+        let instruction = decode_instruction(0x0FFE);
+        assert_eq!(
+            instruction,
+            Instruction::Br {
+                n: true,
+                z: true,
+                p: true,
+                offset: -2
+            }
+        );
+
+        // 1E3F -> 0001 111 000 1 11111
+        let instruction = decode_instruction(0x1E3F);
+        assert_eq!(
+            instruction,
+            Instruction::Add {
+                dr: Gpr::R7,
+                sr1: Gpr::R0,
+                operand: Operand::Imm(-1)
+            }
+        );
+    }
+
+    #[test]
+    fn test_general_purpose_register() {
+        assert_eq!(Gpr::new(3), Some(Gpr::R3));
+        assert_eq!(Gpr::new(7).map(Gpr::number), Some(7));
+        assert_eq!(Gpr::new(8), None);
+        assert_eq!(Gpr::R5.to_string(), "R5");
     }
 
     #[test]
     fn test_encode_instruction() {
         let instruction = decode_instruction(0x475);
-        println!("{:0x}", instruction.encode());
-        println!("{}", instruction);
-        dbg!(format!(
-            "{:0x}",
-            encode_instruction_string("BR 2 117".to_string()).encode()
-        ));
+        assert_eq!(instruction.encode(), 0x475);
+        assert_eq!(instruction.to_string(), "BRz #117");
+        assert_eq!(assemble_instruction("BRz #117").encode(), 0x475);
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        for word in [
+            0x0000, 0x0048, 0x0FFE, 0x1EAA, 0x1283, 0x2A05, 0x3BFF, 0x4FFF, 0x4080, 0x5EAA, 0x5283,
+            0x6A7F, 0x7A41, 0x8000, 0x927F, 0xA6BB, 0xB1F0, 0xC1C0, 0xD000, 0xE1FE, 0xF025,
+        ] {
+            let instruction = decode_instruction(word);
+            assert_eq!(instruction.encode(), word);
+            assert_eq!(assemble_instruction(&instruction.to_string()), instruction);
+        }
     }
 }
@@ -0,0 +1,376 @@
+use std::fmt::Display;
+
+use super::{
+    expression::{parse_expression, Expression, OPERATOR_CHARS},
+    pseudo::is_pseudo,
+    AssemblerError, Gpr,
+};
+
+// A single operand as written in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Token {
+    Register(Gpr),
+    Number(i32),
+    Label(String),
+    String(String),
+    // an expression using labels, constant ones are folded into numbers
+    Expression(Expression),
+}
+
+// As it would be written back, e.g. `R1`, `#-2` or `LOOP+1`
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Register(register) => register.fmt(f),
+            Token::Number(value) => write!(f, "#{}", value),
+            Token::Label(label) => f.write_str(label),
+            Token::String(string) => write!(f, "{:?}", string),
+            Token::Expression(expression) => expression.fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Statement {
+    // an opcode or trap alias, stored upper case e.g. `BRNZ`, `HALT`
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Token>,
+    },
+    // an assembler directive, stored upper case without the dot e.g. `FILL`
+    Directive {
+        name: String,
+        operands: Vec<Token>,
+    },
+}
+
+// A parsed source line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Line {
+    pub(crate) number: usize,
+    pub(crate) label: Option<String>,
+    pub(crate) statement: Option<Statement>,
+    // the included file the line comes from, None for the main source
+    pub(crate) file: Option<String>,
+    // macro expansions and includes the line comes from, innermost first
+    pub(crate) context: Vec<String>,
+    // line of the main source holding the outermost macro call or include the line comes from,
+    // the line itself otherwise
+    pub(crate) origin: usize,
+    // 1-based column of the statement, or of the label when there is no statement
+    pub(crate) column: usize,
+}
+
+const MNEMONICS: [&str; 24] = [
+    "ADD", "AND", "NOT", "BR", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI",
+    "STR", "TRAP", "RTI", "RES", "NOP", "GETC", "OUT", "PUTS", "IN", "PUTSP",
+];
+
+pub(crate) fn is_mnemonic(word: &str) -> bool {
+    let word = word.to_uppercase();
+    if word == "HALT" || MNEMONICS.contains(&word.as_str()) || is_pseudo(&word) {
+        return true;
+    }
+
+    // BR followed by the condition flags in n, z, p order
+    match word.strip_prefix("BR") {
+        Some(flags) => ["N", "Z", "P", "NZ", "NP", "ZP", "NZP"].contains(&flags),
+        None => false,
+    }
+}
+
+pub(crate) fn parse_line(text: &str, number: usize) -> Result<Line, AssemblerError> {
+    let error = |message: String| AssemblerError::new(number, message);
+    let mut words = tokenize(text).map_err(error)?.into_iter();
+
+    let mut line = Line {
+        number,
+        label: None,
+        statement: None,
+        file: None,
+        context: vec![],
+        origin: number,
+        column: 1,
+    };
+
+    let mut head = match words.next() {
+        Some(head) => head,
+        None => return Ok(line),
+    };
+    line.column = column(text, 0, &head);
+
+    if !head.starts_with('.') && !is_mnemonic(&head) {
+        if !is_label(&head) {
+            return Err(error(format!("Invalid label `{}`", head)));
+        }
+        let end = text.find(&head).unwrap_or(0) + head.len();
+        line.label = Some(head);
+        head = match words.next() {
+            Some(head) => head,
+            None => return Ok(line),
+        };
+        line.column = column(text, end, &head);
+    }
+
+    let operands = words
+        .map(|word| parse_token(&word))
+        .collect::<Result<Vec<Token>, String>>()
+        .map_err(error)?;
+
+    line.statement = Some(match head.strip_prefix('.') {
+        Some(name) => Statement::Directive {
+            name: name.to_uppercase(),
+            operands,
+        },
+        None if is_mnemonic(&head) => Statement::Instruction {
+            mnemonic: head.to_uppercase(),
+            operands,
+        },
+        None => return Err(error(format!("Unknown instruction `{}`", head))),
+    });
+
+    Ok(line)
+}
+
+// 1-based column of the first occurrence of `word` at or after byte `start`
+fn column(text: &str, start: usize, word: &str) -> usize {
+    let index = text[start..]
+        .find(word)
+        .map_or(start, |index| start + index);
+    text[..index].chars().count() + 1
+}
+
+// Splits a line into words on whitespace and commas, dropping comments
+// String and character literals are kept whole, including their quotes
+// Words separated by whitespace around an operator are joined into one, e.g. `x10 << 2`
+pub(crate) fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut words: Vec<String> = vec![];
+    let mut chars = text.chars().peekable();
+    // whether a comma was seen since the last word
+    let mut comma = false;
+
+    while let Some(&c) = chars.peek() {
+        if c == ';' {
+            break;
+        } else if c == ',' {
+            comma = true;
+            chars.next();
+        } else if c.is_whitespace() {
+            chars.next();
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c == '"' || c == '\'' {
+                    word.push_str(&quoted(&mut chars)?);
+                    continue;
+                }
+                if c.is_whitespace() || c == ',' || c == ';' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+
+            let joins = !comma
+                && words
+                    .last()
+                    .is_some_and(|last| ends_with_operator(last) || starts_with_operator(&word));
+            match words.last_mut() {
+                Some(last) if joins => last.push_str(&word),
+                _ => words.push(word),
+            }
+            comma = false;
+        }
+    }
+
+    Ok(words)
+}
+
+// Reads a string or character literal, escapes included
+fn quoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    let quote = chars.next().unwrap();
+    let mut word = String::from(quote);
+    loop {
+        match chars.next() {
+            Some('\\') => {
+                word.push('\\');
+                word.push(chars.next().ok_or("Unterminated string")?);
+            }
+            Some(c) if c == quote => {
+                word.push(c);
+                return Ok(word);
+            }
+            Some(c) => word.push(c),
+            None => return Err("Unterminated string".to_string()),
+        }
+    }
+}
+
+fn ends_with_operator(word: &str) -> bool {
+    !word.starts_with('"') && word.ends_with(|c| OPERATOR_CHARS.contains(c))
+}
+
+// A binary operator, `-` and `~` only count on their own as they also start negative operands
+fn starts_with_operator(word: &str) -> bool {
+    word == "-" || word.starts_with(|c| c != '-' && c != '~' && OPERATOR_CHARS.contains(c))
+}
+
+pub(crate) fn parse_token(word: &str) -> Result<Token, String> {
+    if let Some(literal) = word.strip_prefix('"') {
+        return parse_string(literal).map(Token::String);
+    }
+    if let Some(register) = parse_register(word) {
+        return Ok(Token::Register(register));
+    }
+    if let Some(number) = parse_number(word) {
+        return Ok(Token::Number(number));
+    }
+    if is_label(word) {
+        return Ok(Token::Label(word.to_string()));
+    }
+    if word.contains(|c| OPERATOR_CHARS.contains(c) || c == '\'' || c == '(') {
+        return match parse_expression(word) {
+            Ok(Expression::Number(value)) => Ok(Token::Number(value)),
+            Ok(expression) => Ok(Token::Expression(expression)),
+            Err(error) => Err(format!("Invalid operand `{}`: {}", word, error)),
+        };
+    }
+    Err(format!("Invalid operand `{}`", word))
+}
+
+fn parse_register(word: &str) -> Option<Gpr> {
+    match word.strip_prefix(['R', 'r'])?.as_bytes() {
+        [digit @ b'0'..=b'7'] => Gpr::new((digit - b'0') as u16),
+        _ => None,
+    }
+}
+
+/// Parses a number as written in LC-3 source: `#10`, `#-3`, `10`, `x1F`, `0x1F` and `-x1F`
+pub fn parse_number(word: &str) -> Option<i32> {
+    let word = word.strip_prefix('#').unwrap_or(word);
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word),
+    };
+
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix(['x', 'X']))
+    {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if digits.chars().all(|c| c.is_ascii_digit()) && !digits.is_empty() {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+/// Parses a number like [`parse_number`], None when it is not an address from x0000 to xFFFF
+pub fn parse_address(word: &str) -> Option<u16> {
+    parse_number(word).and_then(|address| u16::try_from(address).ok())
+}
+
+// Unescapes the body of a string literal; `literal` starts after the opening quote
+pub(crate) fn parse_string(literal: &str) -> Result<String, String> {
+    let body = literal
+        .strip_suffix('"')
+        .ok_or("Unterminated string".to_string())?;
+    let mut value = String::new();
+    let mut chars = body.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        value.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('e') => '\x1B',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some(c) => return Err(format!("Unknown escape sequence `\\{}`", c)),
+            None => return Err("Unterminated string".to_string()),
+        });
+    }
+
+    Ok(value)
+}
+
+pub(crate) fn is_label(word: &str) -> bool {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && parse_register(word).is_none()
+        && parse_number(word).is_none()
+        && !is_mnemonic(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_address, parse_line, Statement, Token};
+    use crate::assembler::Gpr;
+
+    #[test]
+    fn test_parse_line() {
+        let line = parse_line("LOOP  ADD R1, R1, #-1 ; decrement", 3).unwrap();
+        assert_eq!(line.number, 3);
+        assert_eq!(line.label, Some("LOOP".to_string()));
+        assert_eq!(
+            line.statement,
+            Some(Statement::Instruction {
+                mnemonic: "ADD".to_string(),
+                operands: vec![
+                    Token::Register(Gpr::R1),
+                    Token::Register(Gpr::R1),
+                    Token::Number(-1)
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_directive() {
+        let line = parse_line("MSG .STRINGZ \"a; b\\n\"", 1).unwrap();
+        assert_eq!(
+            line.statement,
+            Some(Statement::Directive {
+                name: "STRINGZ".to_string(),
+                operands: vec![Token::String("a; b\n".to_string())]
+            })
+        );
+
+        let line = parse_line(".fill xFFFF", 1).unwrap();
+        assert_eq!(
+            line.statement,
+            Some(Statement::Directive {
+                name: "FILL".to_string(),
+                operands: vec![Token::Number(0xFFFF)]
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_line("1ABC ADD R1, R1, R1", 1).is_err());
+        assert!(parse_line("ADD R1, R1, $", 1).is_err());
+        assert!(parse_line(".STRINGZ \"abc", 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("x3000"), Some(0x3000));
+        assert_eq!(parse_address("0xFFFF"), Some(0xFFFF));
+        assert_eq!(parse_address("#12"), Some(12));
+        assert_eq!(parse_address("x10000"), None);
+        assert_eq!(parse_address("#-1"), None);
+        assert_eq!(parse_address("LOOP"), None);
+    }
+}
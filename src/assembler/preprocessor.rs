@@ -0,0 +1,388 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    expression::Expression,
+    macros::{is_definition, Macros},
+    parser::{is_label, parse_token, tokenize, Line, Statement, Token},
+    AssemblerError,
+};
+
+// Bounds the nesting of include files
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// An open `.IF` block
+struct Condition {
+    line: usize,
+    // whether the lines currently read are assembled
+    active: bool,
+    // whether a branch of the block was already assembled
+    taken: bool,
+    in_else: bool,
+}
+
+// A file being read, used to report and detect include cycles
+struct Include {
+    path: PathBuf,
+    name: String,
+}
+
+// Handles includes, named constants, conditional blocks and macros before the source is assembled
+struct Preprocessor {
+    macros: Macros,
+    defines: HashMap<String, Token>,
+    // files being read, outermost first
+    includes: Vec<Include>,
+}
+
+// Parses the whole source, skipping blank and comment-only lines
+// `path` is the file the source was read from, includes are relative to its directory
+pub(crate) fn preprocess(source: &str, path: Option<&Path>) -> Result<Vec<Line>, AssemblerError> {
+    let mut preprocessor = Preprocessor {
+        macros: Macros::default(),
+        defines: HashMap::new(),
+        includes: vec![],
+    };
+    if let Some(path) = path {
+        preprocessor.includes.push(Include {
+            path: fs::canonicalize(path).unwrap_or(path.to_path_buf()),
+            name: path.display().to_string(),
+        });
+    }
+
+    let directory = path
+        .and_then(Path::parent)
+        .unwrap_or(Path::new(""))
+        .to_path_buf();
+    let mut lines = vec![];
+    preprocessor.process(source, &directory, None, &[], &mut lines)?;
+    Ok(lines)
+}
+
+impl Preprocessor {
+    // `file` is the name of an included file, None for the main source
+    fn process(
+        &mut self,
+        source: &str,
+        directory: &Path,
+        file: Option<&str>,
+        context: &[String],
+        lines: &mut Vec<Line>,
+    ) -> Result<(), AssemblerError> {
+        let error = |number: usize, message: String| {
+            AssemblerError::new(number, message).with_context(context.to_vec())
+        };
+        let mut conditions: Vec<Condition> = vec![];
+        let mut texts = source
+            .lines()
+            .enumerate()
+            .map(|(index, text)| (index + 1, text));
+
+        while let Some((number, text)) = texts.next() {
+            let words = tokenize(text).map_err(|message| error(number, message))?;
+            let active = conditions.iter().all(|condition| condition.active);
+            let directive = words
+                .first()
+                .and_then(|word| word.strip_prefix('.'))
+                .map(str::to_uppercase);
+
+            match directive.as_deref() {
+                Some("IF") => {
+                    let value = active
+                        && self
+                            .condition(&words[1..])
+                            .map_err(|message| error(number, message))?;
+                    conditions.push(Condition {
+                        line: number,
+                        active: value,
+                        // a block inside a skipped one is never assembled
+                        taken: value || !active,
+                        in_else: false,
+                    });
+                    continue;
+                }
+                Some("ELSE") => {
+                    let condition = match conditions.last_mut() {
+                        Some(condition) if !condition.in_else => condition,
+                        Some(_) => return Err(error(number, "Duplicate .ELSE".to_string())),
+                        None => return Err(error(number, ".ELSE without .IF".to_string())),
+                    };
+                    condition.in_else = true;
+                    condition.active = !condition.taken;
+                    condition.taken = true;
+                    continue;
+                }
+                Some("ENDIF") => {
+                    if conditions.pop().is_none() {
+                        return Err(error(number, ".ENDIF without .IF".to_string()));
+                    }
+                    continue;
+                }
+                _ if !active => continue,
+                Some("DEFINE") => {
+                    match words.as_slice() {
+                        [_, name, value] => self
+                            .define(name, value)
+                            .map_err(|message| error(number, message))?,
+                        _ => {
+                            return Err(error(number, ".DEFINE expects a name and a value".into()))
+                        }
+                    }
+                    continue;
+                }
+                Some("INCLUDE") => {
+                    self.include(&words[1..], number, directory, file, context, lines)?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if is_definition(&words) {
+                self.macros
+                    .define(&words, number, file, &mut texts)
+                    .map_err(|error| error.with_context(context.to_vec()))?;
+                continue;
+            }
+            if let [name, equ, value] = words.as_slice() {
+                if equ.eq_ignore_ascii_case(".EQU") {
+                    self.define(name, value)
+                        .map_err(|message| error(number, message))?;
+                    continue;
+                }
+            }
+
+            for mut line in self.macros.parse(text, number, file, context)? {
+                if line.label.is_none() && line.statement.is_none() {
+                    continue;
+                }
+                self.substitute(&mut line)
+                    .map_err(|message| AssemblerError::at(&line, message))?;
+                lines.push(line);
+            }
+        }
+
+        match conditions.last() {
+            Some(condition) => Err(error(
+                condition.line,
+                "Missing .ENDIF for this .IF".to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn define(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if !is_label(name) {
+            return Err(format!("Invalid constant name `{}`", name));
+        }
+        let value = self.value(parse_token(value)?)?;
+        self.defines.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    // Replaces the operands naming a constant with its value
+    fn substitute(&self, line: &mut Line) -> Result<(), String> {
+        if let Some(
+            Statement::Instruction { operands, .. } | Statement::Directive { operands, .. },
+        ) = &mut line.statement
+        {
+            for operand in operands {
+                *operand = self.value(operand.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    // A token with the constants it names replaced, numeric constants are folded into expressions
+    fn value(&self, token: Token) -> Result<Token, String> {
+        match token {
+            Token::Label(label) => Ok(self
+                .defines
+                .get(&label)
+                .cloned()
+                .unwrap_or(Token::Label(label))),
+            Token::Expression(expression) => {
+                let numbers = |label: &str| match self.defines.get(label) {
+                    Some(Token::Number(value)) => Some(*value),
+                    _ => None,
+                };
+                match expression.substitute(&numbers)? {
+                    Expression::Number(value) => Ok(Token::Number(value)),
+                    expression => Ok(Token::Expression(expression)),
+                }
+            }
+            token => Ok(token),
+        }
+    }
+
+    // `.IF value` is true when the value isn't zero, comparisons such as `.IF SECTION == 2`
+    // evaluate to 1 or 0
+    fn condition(&self, words: &[String]) -> Result<bool, String> {
+        let word = match words {
+            [word] => word,
+            _ => return Err(".IF expects a value or a comparison".to_string()),
+        };
+
+        match self.value(parse_token(word)?)? {
+            Token::Number(value) => Ok(value != 0),
+            Token::Label(label) => Err(format!("Undefined constant `{}`", label)),
+            Token::Expression(expression) => {
+                Err(format!("Undefined constant `{}`", expression.labels()[0]))
+            }
+            _ => Err(format!("Expected a number, found `{}`", word)),
+        }
+    }
+
+    fn include(
+        &mut self,
+        operands: &[String],
+        number: usize,
+        directory: &Path,
+        file: Option<&str>,
+        context: &[String],
+        lines: &mut Vec<Line>,
+    ) -> Result<(), AssemblerError> {
+        let error =
+            |message: String| AssemblerError::new(number, message).with_context(context.to_vec());
+
+        let name = match operands {
+            [operand] => match parse_token(operand) {
+                Ok(Token::String(name)) => name,
+                _ => return Err(error(".INCLUDE expects a file name in quotes".to_string())),
+            },
+            _ => return Err(error(".INCLUDE expects a file name in quotes".to_string())),
+        };
+        let path = directory.join(&name);
+        let source = fs::read_to_string(&path)
+            .map_err(|reason| error(format!("Can't read `{}`: {}", path.display(), reason)))?;
+        let canonical = fs::canonicalize(&path).unwrap_or(path.clone());
+
+        if let Some(start) = self
+            .includes
+            .iter()
+            .position(|include| include.path == canonical)
+        {
+            let mut chain = self.includes[start..]
+                .iter()
+                .map(|include| include.name.clone())
+                .collect::<Vec<String>>();
+            chain.push(name);
+            return Err(error(format!("Include cycle: {}", chain.join(" -> "))));
+        }
+        if self.includes.len() >= MAX_INCLUDE_DEPTH {
+            return Err(error("Includes are nested too deeply".to_string()));
+        }
+
+        let mut inner_context = vec![match file {
+            Some(file) => format!("in {} included from {} line {}", name, file, number),
+            None => format!("in {} included from line {}", name, number),
+        }];
+        inner_context.extend_from_slice(context);
+
+        self.includes.push(Include {
+            path: canonical,
+            name: name.clone(),
+        });
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let start = lines.len();
+        self.process(&source, &directory, Some(&name), &inner_context, lines)?;
+        self.includes.pop();
+        for line in &mut lines[start..] {
+            line.origin = number;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use crate::assembler::{assemble, assemble_file};
+
+    // A scratch directory for the files of a test
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("lc3_vm_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_defines_and_conditions() {
+        let source = "
+            .DEFINE SECTION 2
+            COUNT .EQU #5
+            .ORIG x3000
+            .IF SECTION == 1
+                    ADD R1, R1, #1
+            .ELSE
+                .IF COUNT
+                    ADD R1, R1, COUNT
+                .ENDIF
+                .IF SECTION * 2 > COUNT | 0
+                .ELSE
+                    ADD R1, R1, COUNT - SECTION
+                .ENDIF
+            .ENDIF
+            .IF 0
+                    .NOT_A_DIRECTIVE
+            .ENDIF
+                    HALT
+            .END";
+        let program = assemble(source).unwrap();
+
+        assert_eq!(program.segments[0].words, vec![0x1265, 0x1263, 0xF025]);
+        assert!(!program.symbols.contains_key("COUNT"));
+
+        assert!(assemble(".ORIG x3000\n.IF 1\nHALT\n.END").is_err());
+        assert!(assemble(".ORIG x3000\n.ENDIF\n.END").is_err());
+        assert_eq!(
+            assemble(".IF MISSING\n.ENDIF").unwrap_err().message,
+            "Undefined constant `MISSING`"
+        );
+        assert_eq!(
+            assemble(".IF 2 + MISSING\n.ENDIF").unwrap_err().message,
+            "Undefined constant `MISSING`"
+        );
+    }
+
+    #[test]
+    fn test_include_files() {
+        let directory = directory("include");
+        fs::create_dir_all(directory.join("lib")).unwrap();
+        fs::write(
+            directory.join("lib/io.asm"),
+            ".INCLUDE \"consts.asm\"\n.MACRO PRINT\nPUTS\n.ENDM\n",
+        )
+        .unwrap();
+        fs::write(directory.join("lib/consts.asm"), ".DEFINE ONE #1\n").unwrap();
+        let main = directory.join("main.asm");
+        let source = ".ORIG x3000\n.INCLUDE \"lib/io.asm\"\nADD R0, R0, ONE\nPRINT\n.END";
+
+        let program = assemble_file(source, &main).unwrap();
+        assert_eq!(program.segments[0].words, vec![0x1021, 0xF022]);
+        // the body of PRINT comes from an included file
+        assert!(!program.lines.contains_key(&0x3001));
+
+        fs::write(directory.join("lib/consts.asm"), "ADD R0, R0, #99\n").unwrap();
+        let error = assemble_file(source, &main).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 1: Value 99 does not fit in 5 bits (-16..15) \
+             (in consts.asm included from lib/io.asm line 1, in lib/io.asm included from line 2)"
+        );
+
+        fs::write(directory.join("lib/consts.asm"), ".INCLUDE \"io.asm\"\n").unwrap();
+        let error = assemble_file(source, &main).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Include cycle: lib/io.asm -> consts.asm -> io.asm"
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt::Display;
+
+use super::{decode_instruction, parser::Token, Gpr, Instruction, Operand};
+
+// Mnemonics the assembler expands into sequences of real instructions
+const PSEUDO_MNEMONICS: [&str; 9] = [
+    "MOV", "CLR", "SUB", "NEG", "PUSH", "POP", "INC", "DEC", "LD32",
+];
+
+// Stack pointer of PUSH and POP, the stack grows towards lower addresses
+const STACK_POINTER: Gpr = Gpr::R6;
+
+pub(crate) fn is_pseudo(word: &str) -> bool {
+    PSEUDO_MNEMONICS.contains(&word.to_uppercase().as_str())
+}
+
+// A word of an expanded pseudo-instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Word {
+    Instruction(Instruction),
+    // a constant stored inline, skipped over by the instructions before it
+    Data(u16),
+}
+
+impl Word {
+    pub(crate) fn encode(&self) -> u16 {
+        match self {
+            Word::Instruction(instruction) => instruction.encode(),
+            Word::Data(word) => *word,
+        }
+    }
+}
+
+impl Display for Word {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Word::Instruction(instruction) => instruction.fmt(f),
+            Word::Data(word) => write!(f, ".FILL x{:04X}", word),
+        }
+    }
+}
+
+// The words a pseudo-instruction stands for, `constant` evaluates the value loaded by LD32
+//
+//   MOV DR, SR          ADD DR, SR, #0
+//   CLR DR              AND DR, DR, #0
+//   INC DR / DEC DR     ADD DR, DR, #1 / #-1
+//   NEG DR[, SR]        NOT DR, SR; ADD DR, DR, #1
+//   SUB DR, SR1, SR2    NOT DR, SR1; ADD DR, DR, SR2; NOT DR, DR
+//   PUSH SR             ADD R6, R6, #-1; STR SR, R6, #0
+//   POP DR              LDR DR, R6, #0; ADD R6, R6, #1
+//   LD32 HIGH, LOW, n   LD HIGH, #2; LD LOW, #2; BR #2; .FILL n >> 16; .FILL n
+pub(crate) fn expand(
+    mnemonic: &str,
+    operands: &[Token],
+    constant: &mut dyn FnMut(&Token) -> Result<i32, String>,
+) -> Result<Vec<Word>, String> {
+    let instructions = match (mnemonic, operands) {
+        ("MOV", [Token::Register(dr), Token::Register(sr)]) => vec![add(*dr, *sr, Operand::Imm(0))],
+        ("CLR", [Token::Register(dr)]) => vec![Instruction::And {
+            dr: *dr,
+            sr1: *dr,
+            operand: Operand::Imm(0),
+        }],
+        ("INC", [Token::Register(dr)]) => vec![add(*dr, *dr, Operand::Imm(1))],
+        ("DEC", [Token::Register(dr)]) => vec![add(*dr, *dr, Operand::Imm(-1))],
+        ("NEG", [Token::Register(dr)]) => negate(*dr, *dr),
+        ("NEG", [Token::Register(dr), Token::Register(sr)]) => negate(*dr, *sr),
+        ("SUB", [Token::Register(dr), Token::Register(sr1), Token::Register(sr2)]) => {
+            subtract(*dr, *sr1, *sr2)
+        }
+        ("PUSH", [Token::Register(sr)]) => vec![
+            add(STACK_POINTER, STACK_POINTER, Operand::Imm(-1)),
+            Instruction::Str {
+                sr: *sr,
+                base_r: STACK_POINTER,
+                offset: 0,
+            },
+        ],
+        ("POP", [Token::Register(dr)]) => vec![
+            Instruction::Ldr {
+                dr: *dr,
+                base_r: STACK_POINTER,
+                offset: 0,
+            },
+            add(STACK_POINTER, STACK_POINTER, Operand::Imm(1)),
+        ],
+        ("LD32", [Token::Register(high), Token::Register(low), value]) => {
+            if high == low {
+                return Err("LD32 expects two different registers".to_string());
+            }
+            let value = constant(value)?;
+            return Ok(vec![
+                Word::Instruction(Instruction::Ld {
+                    dr: *high,
+                    offset: 2,
+                }),
+                Word::Instruction(Instruction::Ld {
+                    dr: *low,
+                    offset: 2,
+                }),
+                Word::Instruction(Instruction::Br {
+                    n: true,
+                    z: true,
+                    p: true,
+                    offset: 2,
+                }),
+                Word::Data((value >> 16) as u16),
+                Word::Data(value as u16),
+            ]);
+        }
+        _ => return Err(format!("Invalid operands for {}", mnemonic)),
+    };
+
+    Ok(instructions.into_iter().map(Word::Instruction).collect())
+}
+
+fn add(dr: Gpr, sr1: Gpr, operand: Operand) -> Instruction {
+    Instruction::Add { dr, sr1, operand }
+}
+
+fn negate(dr: Gpr, sr: Gpr) -> Vec<Instruction> {
+    vec![Instruction::Not { dr, sr }, add(dr, dr, Operand::Imm(1))]
+}
+
+// SR1 - SR2 is computed as NOT(NOT SR1 + SR2), which only reads SR2 after DR is written
+fn subtract(dr: Gpr, sr1: Gpr, sr2: Gpr) -> Vec<Instruction> {
+    match (dr == sr1, dr == sr2) {
+        (true, true) => vec![Instruction::And {
+            dr,
+            sr1: dr,
+            operand: Operand::Imm(0),
+        }],
+        // DR = -SR2 + SR1 instead
+        (false, true) => {
+            let mut instructions = negate(dr, sr2);
+            instructions.push(add(dr, dr, Operand::Reg(sr1)));
+            instructions
+        }
+        _ => vec![
+            Instruction::Not { dr, sr: sr1 },
+            add(dr, dr, Operand::Reg(sr2)),
+            Instruction::Not { dr, sr: dr },
+        ],
+    }
+}
+
+// The words an expansion was assembled into, LD32 ends with the two halves of its constant
+pub(crate) fn expansion(mnemonic: &str, words: &[u16]) -> Vec<Word> {
+    let data = match mnemonic {
+        "LD32" => 2,
+        _ => 0,
+    };
+    words
+        .iter()
+        .enumerate()
+        .map(|(index, word)| match index + data >= words.len() {
+            true => Word::Data(*word),
+            false => Word::Instruction(decode_instruction(*word)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assembler::assemble, vm::Vm};
+
+    use super::expansion;
+
+    #[test]
+    fn test_expand_pseudo_instructions() {
+        let program = assemble(
+            ".ORIG x3000
+            START   MOV R1, R2
+                    CLR R3
+                    INC R1
+                    DEC R1
+                    NEG R4
+                    SUB R1, R2, R3
+                    SUB R3, R2, R3
+                    PUSH R7
+                    POP R7
+                    LD32 R0, R1, x12345678
+                    BR START
+                    .END",
+        )
+        .unwrap();
+
+        assert_eq!(
+            program.segments[0].words,
+            vec![
+                0x12A0, 0x56E0, 0x1261, 0x127F, 0x993F, 0x1921, 0x92BF, 0x1243, 0x927F, 0x96FF,
+                0x16E1, 0x16C2, 0x1DBF, 0x7F80, 0x6F80, 0x1DA1, 0x2002, 0x2202, 0x0E02, 0x1234,
+                0x5678, 0x0FEA
+            ]
+        );
+
+        let error = assemble(".ORIG x3000\nPUSH #1\n.END").unwrap_err();
+        assert_eq!(error.message, "Invalid operands for PUSH");
+    }
+
+    #[test]
+    fn test_run_pseudo_instructions() {
+        let program = assemble(
+            ".ORIG x3000
+                    LD R6, STACK
+                    LD32 R1, R2, #-70000
+                    AND R3, R3, #0
+                    ADD R3, R3, #5
+                    ADD R4, R3, #7
+                    SUB R5, R3, R4
+                    SUB R4, R3, R4
+                    SUB R3, R3, R3
+                    PUSH R5
+                    NEG R5
+                    POP R0
+                    HALT
+            STACK   .FILL x4000
+                    .END",
+        )
+        .unwrap();
+        let mut vm = Vm::initialize();
+        vm.load_program(program.to_image().unwrap()).unwrap();
+        vm.run();
+
+        assert_eq!(vm.get_register(1), (-70000i32 >> 16) as u16);
+        assert_eq!(vm.get_register(2), -70000i32 as u16);
+        assert_eq!(vm.get_register(5), 7);
+        assert_eq!(vm.get_register(4), -7i16 as u16);
+        assert_eq!(vm.get_register(3), 0);
+        assert_eq!(vm.get_register(0), -7i16 as u16);
+        assert_eq!(vm.get_register(6), 0x4000);
+    }
+
+    #[test]
+    fn test_expansion() {
+        let program = assemble(
+            ".ORIG x3000
+            PUSH R1
+            LD32 R0, R1, #-2
+            .END",
+        )
+        .unwrap();
+        let words = &program.segments[0].words;
+
+        let push = expansion("PUSH", &words[..2]);
+        assert_eq!(push[1].to_string(), "STR R1, R6, #0");
+        let ld32 = expansion("LD32", &words[2..]);
+        assert_eq!(ld32[2].to_string(), "BRnzp #2");
+        assert_eq!(ld32[3].to_string(), ".FILL xFFFF");
+        assert_eq!(ld32[4].to_string(), ".FILL xFFFE");
+    }
+}
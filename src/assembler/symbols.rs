@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+
+use super::parser::parse_address;
+
+/// Symbol files list one label per line followed by its address, e.g. `LOOP x3002`
+pub fn symbols_to_string(symbols: &BTreeMap<String, u16>) -> String {
+    symbols
+        .iter()
+        .map(|(label, address)| format!("{} x{:04X}\n", label, address))
+        .collect()
+}
+
+/// Reads a symbol file written by [`symbols_to_string`]
+pub fn parse_symbols(text: &str) -> Result<BTreeMap<String, u16>, String> {
+    let mut symbols = BTreeMap::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        match words.as_slice() {
+            [] => {}
+            [label, address] => {
+                let address = parse_address(address).ok_or(format!(
+                    "line {}: invalid address `{}`",
+                    index + 1,
+                    address
+                ))?;
+                symbols.insert(label.to_string(), address);
+            }
+            _ => return Err(format!("line {}: expected `LABEL address`", index + 1)),
+        }
+    }
+
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{parse_symbols, symbols_to_string};
+
+    #[test]
+    fn test_symbols_roundtrip() {
+        let symbols = BTreeMap::from([("LOOP".to_string(), 0x3002), ("MSG".to_string(), 0x3010)]);
+        let text = symbols_to_string(&symbols);
+
+        assert_eq!(text, "LOOP x3002\nMSG x3010\n");
+        assert_eq!(parse_symbols(&text).unwrap(), symbols);
+        assert!(parse_symbols("LOOP").is_err());
+        assert!(parse_symbols("LOOP x10000").is_err());
+    }
+}
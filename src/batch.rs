@@ -0,0 +1,191 @@
+use std::fmt::{Display, Write};
+
+use crate::{
+    assembler::parse_string,
+    terminal::Terminal,
+    vm::{Console, Register, RunOutcome, Vm},
+};
+
+/// How a run without a terminal ended, the result file written by `batch`:
+///
+/// ```text
+/// OUTCOME halted
+/// INSTRUCTIONS 27
+/// REGISTER R0 x0041
+/// ...
+/// REGISTER COND x0001
+/// OUTPUT "Hello\n"
+/// ```
+///
+/// The output is quoted with the escapes of `.STRINGZ`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchResult {
+    /// why the run stopped
+    pub outcome: RunOutcome,
+    /// instructions executed
+    pub instructions: u64,
+    /// R0 to R7, the PC and the condition codes
+    pub registers: Vec<(Register, u16)>,
+    /// Everything the traps and the display data register wrote
+    pub output: Vec<u8>,
+}
+
+/// Runs `vm` reading `input` as the keyboard and capturing the console output, the vm is left
+/// as the program ended
+///
+/// A program waiting for input once `input` is exhausted reads NUL characters from the traps
+/// and finds no key ready in the keyboard status register, give `vm` an instruction limit.
+pub fn run(vm: &mut Vm, input: &[u8]) -> BatchResult {
+    vm.set_console(Console::scripted(input));
+    let outcome = vm.run();
+
+    let registers = Register::GENERAL
+        .into_iter()
+        .chain([Register::Pc, Register::Cond])
+        .map(|register| (register, vm.register(register)))
+        .collect();
+    BatchResult {
+        outcome,
+        instructions: vm.counters().instructions,
+        registers,
+        output: vm.console().output().to_vec(),
+    }
+}
+
+/// Input given on the command line, with the escapes of `.STRINGZ` such as `\n`
+pub fn parse_input(text: &str) -> Result<Vec<u8>, String> {
+    Ok(parse_string(&format!("{}\"", text))?.into_bytes())
+}
+
+/// What a terminal of `columns` by `rows` shows once it rendered `output`: a record per row up
+/// to the last one that isn't blank, then the cursor counted from 1 as `ESC[row;colH` does
+///
+/// ```text
+/// SCREEN "+--------------------------+"
+/// SCREEN "|         2                |"
+/// ...
+/// CURSOR 12 1
+/// ```
+pub fn screen(output: &[u8], columns: usize, rows: usize) -> String {
+    let mut terminal = Terminal::new(columns, rows);
+    terminal.feed(output);
+    let mut text = String::new();
+    for line in terminal.lines() {
+        writeln!(text, "SCREEN {}", quote(&line)).unwrap();
+    }
+    let (row, column) = terminal.cursor();
+    writeln!(text, "CURSOR {} {}", row + 1, column + 1).unwrap();
+    text
+}
+
+impl Display for BatchResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "OUTCOME {}", self.outcome)?;
+        writeln!(f, "INSTRUCTIONS {}", self.instructions)?;
+        for (register, value) in &self.registers {
+            writeln!(f, "REGISTER {} x{:04X}", register, value)?;
+        }
+        writeln!(
+            f,
+            "OUTPUT {}",
+            quote(&String::from_utf8_lossy(&self.output))
+        )
+    }
+}
+
+// The inverse of the string literals of the assembler
+fn quote(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '\0' => quoted.push_str("\\0"),
+            '\x1B' => quoted.push_str("\\e"),
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembler::{assemble, parse_string},
+        vm::{Register, RunOutcome, Vm},
+    };
+
+    use super::{parse_input, quote, run, screen};
+
+    #[test]
+    fn test_run() {
+        let program = assemble(
+            ".ORIG x3000
+            LEA R0, PROMPT
+            PUTS
+            IN
+            ADD R1, R0, #0
+            GETC
+            OUT
+            HALT
+PROMPT      .STRINGZ \"Say \\\"hi\\\"\\n\"
+            .END",
+        )
+        .unwrap();
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .build()
+            .unwrap();
+
+        let result = run(&mut vm, b"hi");
+        assert_eq!(result.outcome, RunOutcome::Halted);
+        assert_eq!(result.instructions, 7);
+        assert_eq!(result.registers[1], (Register::R1, 'h' as u16));
+        assert_eq!(result.registers[8], (Register::Pc, 0x3007));
+        assert_eq!(
+            result.output,
+            b"Say \"hi\"\nPlease pass in a value!\nhi".to_vec()
+        );
+        assert_eq!(
+            result.to_string(),
+            "OUTCOME halted
+INSTRUCTIONS 7
+REGISTER R0 x0069
+REGISTER R1 x0068
+REGISTER R2 x0000
+REGISTER R3 x0000
+REGISTER R4 x0000
+REGISTER R5 x0000
+REGISTER R6 x0000
+REGISTER R7 x0000
+REGISTER PC x3007
+REGISTER COND x0001
+OUTPUT \"Say \\\"hi\\\"\\nPlease pass in a value!\\nhi\"
+"
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        let text = "tab\there\r\n\x1B[2J\0 \\ \"";
+        let quoted = quote(text);
+        assert_eq!(quoted, "\"tab\\there\\r\\n\\e[2J\\0 \\\\ \\\"\"");
+        assert_eq!(parse_string(&quoted[1..]), Ok(text.to_string()));
+
+        assert_eq!(parse_input("w\\na"), Ok(b"w\na".to_vec()));
+        assert!(parse_input("\\q").is_err());
+    }
+
+    #[test]
+    fn test_screen() {
+        assert_eq!(
+            screen(b"hidden\x1B[2J\x1B[H\"a\"\n\n\x1B[32mb\x1B[0m\n", 10, 5),
+            "SCREEN \"\\\"a\\\"\"\nSCREEN \"\"\nSCREEN \"b\"\nCURSOR 4 1\n"
+        );
+        assert_eq!(screen(b"", 10, 5), "CURSOR 1 1\n");
+    }
+}
@@ -0,0 +1,202 @@
+use std::time::{Duration, Instant};
+
+use lc3_vm::{assemble, Program, Register, RunOutcome, Vm};
+
+// CPU bound programs, each one stops at its `DONE` label
+const BENCHMARKS: [(&str, &str); 4] = [
+    ("fib", include_str!("examples/bench/fib.asm")),
+    ("sieve", include_str!("examples/bench/sieve.asm")),
+    ("sort", include_str!("examples/bench/sort.asm")),
+    ("strings", include_str!("examples/bench/strings.asm")),
+];
+
+// A benchmark that hasn't reached `DONE` by then is stuck, each one takes less than 200000
+const MAX_INSTRUCTIONS: u64 = 10_000_000;
+
+// The ways of executing a program that are compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Engine {
+    // `Vm::run` until the breakpoint at `DONE`
+    Run,
+    // `Vm::step` in a loop, as a debugger or a test harness does
+    Step,
+}
+
+const ENGINES: [(Engine, &str); 2] = [(Engine::Run, "run"), (Engine::Step, "step")];
+
+// Loads a program into a fresh vm stopping at `DONE`
+fn load(program: &Program) -> Vm {
+    let mut vm = Vm::builder()
+        .image(program.to_image().expect("benchmarks are flat programs"))
+        .max_instructions(MAX_INSTRUCTIONS)
+        .build()
+        .expect("benchmarks fit in memory");
+    vm.add_breakpoint(program.symbols["DONE"]);
+    vm
+}
+
+// Executes the program until it reaches `DONE`, returning the number of instructions executed,
+// or how the program stopped before
+fn run_to_done(vm: &mut Vm, program: &Program, engine: Engine) -> Result<u64, RunOutcome> {
+    let done = program.symbols["DONE"];
+
+    match engine {
+        Engine::Run => match vm.run() {
+            RunOutcome::Breakpoint(address) if address == done => {}
+            outcome => return Err(outcome),
+        },
+        Engine::Step => {
+            while vm.register(Register::Pc) != done {
+                if let Some(outcome) = vm.step() {
+                    return Err(outcome);
+                }
+                if vm.counters().instructions >= MAX_INSTRUCTIONS {
+                    return Err(RunOutcome::StepLimitExceeded);
+                }
+            }
+        }
+    }
+
+    Ok(vm.counters().instructions)
+}
+
+// Times every benchmark with every engine, returns false when a benchmark didn't reach `DONE`
+pub(crate) fn run_benchmarks(iterations: u32) -> bool {
+    println!(
+        "{:<10} {:<6} {:>14} {:>12} {:>14}",
+        "program", "engine", "instructions", "time (ms)", "instr/sec"
+    );
+
+    for (name, source) in BENCHMARKS {
+        let program = assemble(source).unwrap();
+        for (engine, engine_name) in ENGINES {
+            if !run_benchmark(name, &program, engine, engine_name, iterations) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// Prints the row of a benchmark and an engine, returns false when it didn't reach `DONE`
+fn run_benchmark(
+    name: &str,
+    program: &Program,
+    engine: Engine,
+    engine_name: &str,
+    iterations: u32,
+) -> bool {
+    let mut instructions = 0;
+    let mut elapsed = Duration::ZERO;
+
+    for _ in 0..iterations {
+        // only the execution itself is timed, not the vm setup
+        let mut vm = load(program);
+        let start = Instant::now();
+        let outcome = run_to_done(&mut vm, program, engine);
+        elapsed += start.elapsed();
+        match outcome {
+            Ok(count) => instructions += count,
+            Err(outcome) => {
+                eprintln!(
+                    "{} stopped before DONE at x{:04X} with {}: {}",
+                    name,
+                    vm.register(Register::Pc),
+                    engine_name,
+                    outcome
+                );
+                return false;
+            }
+        }
+    }
+
+    println!(
+        "{:<10} {:<6} {:>14} {:>12.2} {:>14.0}",
+        name,
+        engine_name,
+        instructions,
+        elapsed.as_secs_f64() * 1000.0,
+        instructions as f64 / elapsed.as_secs_f64()
+    );
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use lc3_vm::{assemble, Program, RunOutcome, Vm};
+
+    use super::{load, run_to_done, Engine, BENCHMARKS, ENGINES};
+
+    // Both engines execute the same instructions, the vm run by the first one is returned
+    fn run_benchmark(name: &str) -> (Program, Vm) {
+        let (_, source) = BENCHMARKS.iter().find(|(n, _)| *n == name).unwrap();
+        let program = assemble(source).unwrap();
+        let mut vm = load(&program);
+        let count = run_to_done(&mut vm, &program, Engine::Run).unwrap();
+        assert!(count > 0);
+
+        let mut stepped = load(&program);
+        assert_eq!(run_to_done(&mut stepped, &program, Engine::Step), Ok(count));
+        (program, vm)
+    }
+
+    #[test]
+    fn test_stops_before_done() {
+        for (engine, _) in ENGINES {
+            let program = assemble(".ORIG x3000\nHALT\nDONE BR DONE\n.END").unwrap();
+            let mut vm = load(&program);
+            assert_eq!(
+                run_to_done(&mut vm, &program, engine),
+                Err(RunOutcome::Halted)
+            );
+
+            let program = assemble(".ORIG x3000\nRTI\nDONE BR DONE\n.END").unwrap();
+            let mut vm = load(&program);
+            assert_eq!(
+                run_to_done(&mut vm, &program, engine),
+                Err(RunOutcome::IllegalInstruction(0x3000))
+            );
+
+            let program = assemble(".ORIG x3000\nLOOP BR LOOP\nDONE BR DONE\n.END").unwrap();
+            let mut vm = load(&program);
+            assert_eq!(
+                run_to_done(&mut vm, &program, engine),
+                Err(RunOutcome::StepLimitExceeded)
+            );
+        }
+    }
+
+    #[test]
+    fn test_fib_benchmark() {
+        let (program, vm) = run_benchmark("fib");
+        assert_eq!(vm.read_memory(program.symbols["RESULT"]), 2584);
+    }
+
+    #[test]
+    fn test_sieve_benchmark() {
+        let (program, vm) = run_benchmark("sieve");
+        assert_eq!(vm.read_memory(program.symbols["RESULT"]), 669);
+    }
+
+    #[test]
+    fn test_sort_benchmark() {
+        let (program, vm) = run_benchmark("sort");
+        let array = program.symbols["ARRAY"];
+        for i in 0..256 {
+            assert_eq!(vm.read_memory(array + i), i);
+        }
+    }
+
+    #[test]
+    fn test_strings_benchmark() {
+        let (program, vm) = run_benchmark("strings");
+        assert_eq!(vm.read_memory(program.symbols["RESULT"]), 800);
+
+        let buffer = program.symbols["BUFFER"];
+        let reversed = (0..43)
+            .map(|i| vm.read_memory(buffer + i) as u8 as char)
+            .collect::<String>();
+        assert_eq!(reversed, "GOD YZAL EHT REVO SPMUJ XOF NWORB KCIUQ EHT");
+        assert_eq!(vm.read_memory(buffer + 43), 0);
+    }
+}
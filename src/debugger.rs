@@ -0,0 +1,329 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    assembler::{decode_instruction, parse_address, DebugInfo},
+    vm::{Register, RunOutcome, Vm},
+};
+
+/// An interactive session over a loaded program, driven one command at a time
+pub struct Debugger {
+    vm: Vm,
+    debug_info: Option<DebugInfo>,
+    symbols: BTreeMap<String, u16>,
+    // how the program stopped for good, it can't go on afterwards
+    finished: Option<RunOutcome>,
+}
+
+/// The commands understood by [`Debugger::execute`]
+pub const HELP: &str = "\
+break <position>     stop before the instruction at a position (b)
+break                list the breakpoints
+delete <position>    remove a breakpoint (d)
+continue             run until a breakpoint or the end of the program (c)
+step [n]             execute one or n instructions (s)
+registers            show the registers (r)
+where                show the next instruction (w)
+backtrace            show the calls the program is in (bt)
+quit                 leave the debugger (q)
+A position is `file:line`, a line of the main source, a label or an address such as x3000";
+
+impl Debugger {
+    /// A debugger for `vm`, positions and addresses are resolved with the debug info when given,
+    /// and with `symbols` otherwise
+    pub fn new(vm: Vm, debug_info: Option<DebugInfo>, symbols: BTreeMap<String, u16>) -> Self {
+        Debugger {
+            vm,
+            debug_info,
+            symbols,
+            finished: None,
+        }
+    }
+
+    /// Runs a single command and returns what to show for it, see [`HELP`]
+    pub fn execute(&mut self, command: &str) -> Result<String, String> {
+        let words = command.split_whitespace().collect::<Vec<&str>>();
+
+        match words.as_slice() {
+            ["break" | "b", position] => {
+                let address = self.address(position)?;
+                self.vm.add_breakpoint(address);
+                Ok(format!("Breakpoint at {}", self.describe(address)))
+            }
+            ["break" | "b"] => {
+                let breakpoints = self
+                    .vm
+                    .breakpoints()
+                    .iter()
+                    .map(|address| self.describe(*address))
+                    .collect::<Vec<String>>();
+                match breakpoints.is_empty() {
+                    true => Ok("No breakpoints".to_string()),
+                    false => Ok(breakpoints.join("\n")),
+                }
+            }
+            ["delete" | "d", position] => {
+                let address = self.address(position)?;
+                match self.vm.remove_breakpoint(address) {
+                    true => Ok(format!("Deleted breakpoint at {}", self.describe(address))),
+                    false => Err(format!("No breakpoint at {}", self.describe(address))),
+                }
+            }
+            ["continue" | "c"] => {
+                self.check_running()?;
+                let outcome = self.vm.resume();
+                Ok(self.report(outcome))
+            }
+            ["step" | "s", count @ ..] if count.len() <= 1 => {
+                let count = match count.first() {
+                    Some(count) => count
+                        .parse::<u64>()
+                        .map_err(|_| format!("Invalid step count `{}`", count))?,
+                    None => 1,
+                };
+                self.check_running()?;
+                for _ in 0..count {
+                    if let Some(outcome) = self.vm.step() {
+                        return Ok(self.report(outcome));
+                    }
+                }
+                Ok(self.describe(self.pc()))
+            }
+            ["registers" | "r"] => Ok(self.registers()),
+            ["where" | "w"] => Ok(self.describe(self.pc())),
+            ["backtrace" | "bt"] => Ok(self
+                .vm
+                .backtrace(self.debug_info.as_ref(), &self.symbols)
+                .trim_end()
+                .to_string()),
+            ["help" | "h"] => Ok(HELP.to_string()),
+            [] => Ok(String::new()),
+            _ => Err(format!("Unknown command `{}`, try `help`", command.trim())),
+        }
+    }
+
+    fn check_running(&self) -> Result<(), String> {
+        match self.finished {
+            Some(outcome) => Err(format!("The program is no longer running ({})", outcome)),
+            None => Ok(()),
+        }
+    }
+
+    fn report(&mut self, outcome: RunOutcome) -> String {
+        match outcome {
+            RunOutcome::Breakpoint(address) => {
+                format!("Breakpoint at {}", self.describe(address))
+            }
+            RunOutcome::StepLimitExceeded => {
+                format!("Step limit exceeded at {}", self.describe(self.pc()))
+            }
+            RunOutcome::Halted | RunOutcome::Returned => {
+                self.finished = Some(outcome);
+                let verb = match outcome {
+                    RunOutcome::Returned => "Returned",
+                    _ => "Halted",
+                };
+                format!(
+                    "{} after {} instructions",
+                    verb,
+                    self.vm.counters().instructions
+                )
+            }
+            RunOutcome::IllegalInstruction(address) => {
+                self.finished = Some(outcome);
+                format!(
+                    "Illegal instruction x{:04X} at {}",
+                    self.vm.read_memory(address),
+                    self.describe(address)
+                )
+            }
+            RunOutcome::AccessViolation(violation) => {
+                self.finished = Some(outcome);
+                format!(
+                    "Access violation, {} at {}",
+                    violation.operation(),
+                    self.describe(violation.pc)
+                )
+            }
+        }
+    }
+
+    fn pc(&self) -> u16 {
+        self.vm.get_register(Register::Pc as u16)
+    }
+
+    // An address with its label and source location, followed by the instruction stored there
+    fn describe(&self, address: u16) -> String {
+        let mut text = describe_address(self.debug_info.as_ref(), address);
+        if self.debug_info.is_none() {
+            if let Some((label, _)) = self.symbols.iter().find(|(_, value)| **value == address) {
+                text.push_str(&format!(" {}", label));
+            }
+        }
+        let instruction = decode_instruction(self.vm.read_memory(address));
+        format!("{}: {}", text, instruction)
+    }
+
+    // A position given as `file:line`, a line of the main source, an address or a label
+    fn address(&self, position: &str) -> Result<u16, String> {
+        let is_address = position.starts_with(['x', 'X']) || position.starts_with("0x");
+        if is_address {
+            if let Some(address) = parse_address(position) {
+                return Ok(address);
+            }
+        }
+        if position.contains(':') || position.chars().all(|c| c.is_ascii_digit()) {
+            return match &self.debug_info {
+                Some(debug_info) => debug_info.address_of(position),
+                None => Err("Source positions need debug info (--debug-info)".to_string()),
+            };
+        }
+
+        let label = self
+            .debug_info
+            .as_ref()
+            .and_then(|debug_info| debug_info.labels.get(position))
+            .map(|(start, _)| *start);
+        label
+            .or(self.symbols.get(position).copied())
+            .ok_or(format!("Unknown label `{}`", position))
+    }
+
+    fn registers(&self) -> String {
+        let mut registers = (0..8)
+            .map(|register| format!("R{} x{:04X}", register, self.vm.get_register(register)))
+            .collect::<Vec<String>>();
+        let cond = self.vm.get_register(Register::Cond as u16);
+        let flags = ["p", "z", "n"]
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(bit, _)| cond & (1 << bit) != 0)
+            .map(|(_, flag)| *flag)
+            .collect::<String>();
+        registers.push(format!("PC x{:04X}", self.pc()));
+        registers.push(format!("COND {}", flags));
+        registers.join("  ")
+    }
+}
+
+/// An address with its label and source location when the debug info knows them
+pub fn describe_address(debug_info: Option<&DebugInfo>, address: u16) -> String {
+    match debug_info {
+        Some(debug_info) => debug_info.describe(address),
+        None => format!("x{:04X}", address),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembler::{assemble, DebugInfo},
+        vm::Vm,
+    };
+
+    use super::Debugger;
+
+    fn debugger(source: &str) -> Debugger {
+        let program = assemble(source).unwrap();
+        let mut vm = Vm::initialize();
+        vm.load_program(program.to_image().unwrap()).unwrap();
+        let debug_info = DebugInfo::new("main.asm", &program);
+        Debugger::new(vm, Some(debug_info), program.symbols)
+    }
+
+    #[test]
+    fn test_debugger() {
+        let mut debugger = debugger(
+            ".ORIG x3000
+        AND R1, R1, #0
+LOOP    ADD R1, R1, #1
+        ADD R2, R1, #-2
+        BRn LOOP
+        HALT
+.END",
+        );
+
+        assert_eq!(
+            debugger.execute("break 4"),
+            Ok("Breakpoint at x3002 LOOP+1 (main.asm:4:9): ADD R2, R1, #-2".to_string())
+        );
+        assert_eq!(
+            debugger.execute("c"),
+            Ok("Breakpoint at x3002 LOOP+1 (main.asm:4:9): ADD R2, R1, #-2".to_string())
+        );
+        assert_eq!(
+            debugger.execute("registers").unwrap(),
+            "R0 x0000  R1 x0001  R2 x0000  R3 x0000  R4 x0000  R5 x0000  R6 x0000  R7 x0000  \
+             PC x3002  COND p"
+        );
+        assert_eq!(
+            debugger.execute("step 2"),
+            Ok("x3001 LOOP (main.asm:3:9): ADD R1, R1, #1".to_string())
+        );
+
+        assert_eq!(
+            debugger.execute("b"),
+            Ok("x3002 LOOP+1 (main.asm:4:9): ADD R2, R1, #-2".to_string())
+        );
+        assert!(debugger.execute("delete main.asm:4").is_ok());
+        assert_eq!(debugger.execute("b"), Ok("No breakpoints".to_string()));
+        assert!(debugger.execute("delete x3002").is_err());
+        assert!(debugger.execute("break x3004").is_ok());
+        assert_eq!(
+            debugger.execute("continue"),
+            Ok("Breakpoint at x3004 LOOP+3 (main.asm:6:9): TRAP x25".to_string())
+        );
+        assert_eq!(
+            debugger.execute("s"),
+            Ok("Halted after 8 instructions".to_string())
+        );
+        assert_eq!(
+            debugger.execute("c"),
+            Err("The program is no longer running (halted)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let mut debugger = debugger(
+            ".ORIG x3000
+        JSR TWICE
+        HALT
+TWICE   ADD R1, R1, #1
+        ADD R1, R1, #1
+        RET
+.END",
+        );
+
+        assert_eq!(
+            debugger.execute("bt"),
+            Ok("#0 x3000 (main.asm:2:9)".to_string())
+        );
+        assert!(debugger.execute("break 5").is_ok());
+        assert!(debugger.execute("c").is_ok());
+        assert_eq!(
+            debugger.execute("backtrace"),
+            Ok("#0 x3003 TWICE+1 (main.asm:5:9) in TWICE\n#1 x3000 (main.asm:2:9)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_debugger_errors() {
+        let mut debugger = debugger(".ORIG x3000\nADD R1, R1, #1\nRTI\n.END");
+
+        assert_eq!(
+            debugger.execute("break 5"),
+            Err("No instruction at main.asm:5".to_string())
+        );
+        assert_eq!(
+            debugger.execute("break NOWHERE"),
+            Err("Unknown label `NOWHERE`".to_string())
+        );
+        assert!(debugger.execute("jump").is_err());
+        assert_eq!(
+            debugger.execute("c"),
+            Ok("Illegal instruction x8000 at x3001 (main.asm:3:1): RTI".to_string())
+        );
+        assert!(debugger.execute("step").is_err());
+    }
+}
@@ -0,0 +1,33 @@
+; Recursive Fibonacci
+; Computes FIB(N) with one call per term, saving registers on the R6 stack
+        .ORIG x3000
+        LD R6, STACK
+        LD R0, N
+        JSR FIB
+        ST R0, RESULT
+DONE    HALT
+
+; R0 = FIB(R0), all other registers are preserved
+FIB     ADD R6, R6, #-3
+        STR R7, R6, #0
+        STR R1, R6, #1
+        STR R2, R6, #2
+        ADD R1, R0, #-2
+        BRn FIB_END             ; FIB(0) = 0 and FIB(1) = 1
+        ADD R1, R0, #0
+        ADD R0, R1, #-1
+        JSR FIB
+        ADD R2, R0, #0          ; R2 = FIB(n - 1)
+        ADD R0, R1, #-2
+        JSR FIB
+        ADD R0, R0, R2          ; R0 = FIB(n - 2) + FIB(n - 1)
+FIB_END LDR R2, R6, #2
+        LDR R1, R6, #1
+        LDR R7, R6, #0
+        ADD R6, R6, #3
+        RET
+
+N       .FILL #18
+RESULT  .BLKW 1
+STACK   .FILL xF000
+        .END
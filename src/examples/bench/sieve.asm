@@ -0,0 +1,39 @@
+; Sieve of Eratosthenes
+; Counts the primes below SIZE, FLAGS[i] is set once i is known to be composite
+        .ORIG x3000
+        LEA R1, FLAGS
+        LD R2, SIZE
+        AND R0, R0, #0
+CLEAR   STR R0, R1, #0
+        ADD R1, R1, #1
+        ADD R2, R2, #-1
+        BRp CLEAR
+
+        LD R6, NSIZE            ; R6 = -SIZE
+        LEA R0, FLAGS           ; R0 = base of the flags
+        AND R5, R5, #0          ; R5 = number of primes
+        AND R2, R2, #0
+        ADD R2, R2, #2          ; R2 = candidate
+OUTER   ADD R4, R2, R6
+        BRzp FINISH
+        ADD R1, R0, R2
+        LDR R4, R1, #0
+        BRnp NEXT               ; already marked as composite
+        ADD R5, R5, #1
+        ADD R3, R2, R2          ; R3 = first multiple to mark
+MARK    ADD R4, R3, R6
+        BRzp NEXT
+        ADD R1, R0, R3
+        STR R2, R1, #0
+        ADD R3, R3, R2
+        BR MARK
+NEXT    ADD R2, R2, #1
+        BR OUTER
+FINISH  ST R5, RESULT
+DONE    HALT
+
+SIZE    .FILL #5000
+NSIZE   .FILL #-5000
+RESULT  .BLKW 1
+FLAGS   .BLKW #5000
+        .END
@@ -0,0 +1,47 @@
+; Insertion sort
+; Fills ARRAY with a permutation of 0..N-1 then sorts it in place
+        .ORIG x3000
+        LEA R1, ARRAY
+        LD R2, N
+        LD R4, STEP
+        LD R5, MASK
+        AND R3, R3, #0
+FILL    STR R3, R1, #0
+        ADD R3, R3, R4          ; next value is (value + STEP) mod N
+        AND R3, R3, R5
+        ADD R1, R1, #1
+        ADD R2, R2, #-1
+        BRp FILL
+
+        LEA R0, ARRAY           ; R0 = base of the array
+        NOT R6, R0
+        ADD R6, R6, #1          ; R6 = -base
+        AND R1, R1, #0
+        ADD R1, R1, #1          ; R1 = index of the next key
+OUTER   LD R2, N
+        NOT R2, R2
+        ADD R2, R2, #1
+        ADD R2, R1, R2
+        BRzp DONE
+        ADD R2, R0, R1          ; R2 = slot being filled
+        LDR R3, R2, #0          ; R3 = key
+        NOT R7, R3
+        ADD R7, R7, #1          ; R7 = -key
+INNER   ADD R4, R2, R6
+        BRnz PLACE              ; reached the start of the array
+        LDR R5, R2, #-1
+        ADD R4, R5, R7
+        BRnz PLACE              ; previous element is not greater than the key
+        STR R5, R2, #0
+        ADD R2, R2, #-1
+        BR INNER
+PLACE   STR R3, R2, #0
+        ADD R1, R1, #1
+        BR OUTER
+DONE    HALT
+
+N       .FILL #256
+STEP    .FILL #37
+MASK    .FILL xFF
+ARRAY   .BLKW #256
+        .END
@@ -0,0 +1,48 @@
+; String processing
+; Copies TEXT reversed and upper-cased into BUFFER ROUNDS times, counting the `O`s
+        .ORIG x3000
+        AND R5, R5, #0          ; R5 = number of `O`s seen
+        LD R6, ROUNDS
+ROUND   LEA R0, TEXT
+        AND R1, R1, #0          ; R1 = length of TEXT
+LENGTH  LDR R2, R0, #0
+        BRz REVERSE
+        ADD R0, R0, #1
+        ADD R1, R1, #1
+        BR LENGTH
+REVERSE LEA R3, BUFFER
+COPY    ADD R1, R1, #-1
+        BRn TERMINATE
+        ADD R0, R0, #-1
+        LDR R2, R0, #0
+        LD R4, LOWER_A
+        ADD R4, R2, R4
+        BRn STORE               ; below `a`
+        LD R4, LOWER_Z
+        ADD R4, R2, R4
+        BRp STORE               ; above `z`
+        LD R4, TO_UPPER
+        ADD R2, R2, R4
+STORE   STR R2, R3, #0
+        ADD R3, R3, #1
+        LD R4, UPPER_O
+        ADD R4, R2, R4
+        BRnp COPY
+        ADD R5, R5, #1
+        BR COPY
+TERMINATE AND R2, R2, #0
+        STR R2, R3, #0
+        ADD R6, R6, #-1
+        BRp ROUND
+        ST R5, RESULT
+DONE    HALT
+
+ROUNDS  .FILL #200
+LOWER_A .FILL #-97
+LOWER_Z .FILL #-122
+TO_UPPER .FILL #-32
+UPPER_O .FILL #-79
+RESULT  .BLKW 1
+TEXT    .STRINGZ "the quick brown fox jumps over the lazy dog"
+BUFFER  .BLKW #64
+        .END
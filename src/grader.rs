@@ -0,0 +1,770 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::{
+    assembler::{parse_number, parse_string},
+    vm::{Register, RunOutcome, Vm, MAX_ADDRESSABLE_MEMORY},
+};
+
+/// Instruction limit of a case that doesn't set one, so that an endless loop still fails
+pub const DEFAULT_MAX_INSTRUCTIONS: u64 = 1_000_000;
+
+/// R6 when a subroutine is called without a `STACK` record, the stack grows down from the device
+/// registers
+pub const DEFAULT_STACK: u16 = 0xFE00;
+
+// Callee-saved registers the case doesn't set start as xC0D0 plus their number, so that a
+// subroutine clearing them is caught
+const CALLEE_SAVED_SEED: u16 = 0xC0D0;
+
+/// A number, or a label of the program under test resolved when the case runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// A number, negative ones are stored as their two's complement
+    Number(u16),
+    /// A label of the program
+    Label(String),
+}
+
+impl Value {
+    fn parse(word: &str) -> Result<Value, String> {
+        match parse_number(word) {
+            // negative numbers are stored as their 16 bit two's complement
+            Some(number) if (-0x8000..=0xFFFF).contains(&number) => {
+                Ok(Value::Number(number as u16))
+            }
+            Some(_) => Err(format!("`{}` does not fit in 16 bits", word)),
+            None => Ok(Value::Label(word.to_string())),
+        }
+    }
+
+    fn resolve(&self, symbols: &BTreeMap<String, u16>) -> Result<u16, String> {
+        match self {
+            Value::Number(number) => Ok(*number),
+            Value::Label(label) => symbols
+                .get(label)
+                .copied()
+                .ok_or(format!("Unknown label `{}`", label)),
+        }
+    }
+}
+
+/// A single run of the program, or a call of one of its subroutines: the setup and what must
+/// hold once it halts or returns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    /// shown in the report
+    pub name: String,
+    /// points awarded when every expectation holds
+    pub weight: u32,
+    /// where execution starts, the origin of the first image otherwise
+    pub entry: Option<Value>,
+    /// the run fails once it executed that many instructions
+    pub max_instructions: u64,
+    /// registers set before the run
+    pub registers: Vec<(Register, Value)>,
+    /// words written from an address on before the run
+    pub memory: Vec<(Value, Vec<Value>)>,
+    /// what the keyboard reads
+    pub input: String,
+    /// everything the program must print, when given
+    pub expected_output: Option<String>,
+    /// registers checked after the run
+    pub expected_registers: Vec<(Register, Value)>,
+    /// words checked from an address on after the run
+    pub expected_memory: Vec<(Value, Vec<Value>)>,
+    /// the subroutine to call rather than running the program from its entry point
+    pub call: Option<Value>,
+    /// R6 before the arguments are pushed, [`DEFAULT_STACK`] otherwise
+    pub stack: Option<Value>,
+    /// words pushed on the stack before the call, the first one ends up at the top
+    pub arguments: Vec<Value>,
+    /// registers a called subroutine must restore
+    pub callee_saved: Vec<Register>,
+}
+
+impl Default for TestCase {
+    fn default() -> Self {
+        TestCase {
+            name: String::new(),
+            weight: 1,
+            entry: None,
+            max_instructions: DEFAULT_MAX_INSTRUCTIONS,
+            registers: vec![],
+            memory: vec![],
+            input: String::new(),
+            expected_output: None,
+            expected_registers: vec![],
+            expected_memory: vec![],
+            call: None,
+            stack: None,
+            arguments: vec![],
+            callee_saved: vec![],
+        }
+    }
+}
+
+/// The cases of a test file, one record per line:
+///
+/// ```text
+/// ; records before the first CASE apply to every case
+/// MAX-INSTRUCTIONS 10000
+///
+/// CASE adds two numbers
+/// WEIGHT 2
+/// ENTRY MAIN
+/// REGISTER R1 5
+/// MEMORY NUMBERS 1 #-2 x3
+/// INPUT "12\n"
+/// EXPECT-REGISTER R0 3
+/// EXPECT-MEMORY RESULT 3
+/// EXPECT-OUTPUT "Sum: 3\n"
+///
+/// CASE multiplies its stack arguments
+/// CALL MULTIPLY
+/// STACK xF000
+/// ARGUMENTS 6 7
+/// CALLEE-SAVED R1 R2 R3 R4 R5 R6
+/// EXPECT-REGISTER R0 42
+/// ```
+///
+/// Addresses and values are numbers or labels of the program under test.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestSuite {
+    /// in the order of the file
+    pub cases: Vec<TestCase>,
+}
+
+impl TestSuite {
+    /// Parses a test file, errors name the line, e.g. `line 3: Invalid record `EXPECT 1``
+    pub fn parse(text: &str) -> Result<TestSuite, String> {
+        let mut defaults = TestCase::default();
+        let mut cases: Vec<TestCase> = vec![];
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            let words = rest.split_whitespace().collect::<Vec<&str>>();
+
+            if keyword == "CASE" {
+                if rest.is_empty() {
+                    return Err(error("CASE expects a name".to_string()));
+                }
+                cases.push(TestCase {
+                    name: rest.to_string(),
+                    ..defaults.clone()
+                });
+                continue;
+            }
+            let case = cases.last_mut().unwrap_or(&mut defaults);
+            let register = |word: &str| word.parse::<Register>();
+            let values = |words: &[&str]| {
+                words
+                    .iter()
+                    .map(|word| Value::parse(word))
+                    .collect::<Result<Vec<Value>, String>>()
+            };
+
+            match (keyword, words.as_slice()) {
+                ("WEIGHT", [weight]) => {
+                    case.weight = weight
+                        .parse()
+                        .map_err(|_| error(format!("Invalid weight `{}`", weight)))?;
+                }
+                ("MAX-INSTRUCTIONS", [max]) => {
+                    case.max_instructions = max
+                        .parse()
+                        .map_err(|_| error(format!("Invalid instruction limit `{}`", max)))?;
+                }
+                ("ENTRY", [entry]) => case.entry = Some(Value::parse(entry).map_err(error)?),
+                ("REGISTER", [name, value]) => case.registers.push((
+                    register(name).map_err(error)?,
+                    Value::parse(value).map_err(error)?,
+                )),
+                ("EXPECT-REGISTER", [name, value]) => case.expected_registers.push((
+                    register(name).map_err(error)?,
+                    Value::parse(value).map_err(error)?,
+                )),
+                ("MEMORY", [address, words @ ..]) if !words.is_empty() => case.memory.push((
+                    Value::parse(address).map_err(error)?,
+                    values(words).map_err(error)?,
+                )),
+                ("EXPECT-MEMORY", [address, words @ ..]) if !words.is_empty() => {
+                    case.expected_memory.push((
+                        Value::parse(address).map_err(error)?,
+                        values(words).map_err(error)?,
+                    ))
+                }
+                ("CALL", [label]) => case.call = Some(Value::parse(label).map_err(error)?),
+                ("STACK", [address]) => case.stack = Some(Value::parse(address).map_err(error)?),
+                ("ARGUMENTS", words) if !words.is_empty() => {
+                    case.arguments = values(words).map_err(error)?
+                }
+                ("CALLEE-SAVED", names) if !names.is_empty() => {
+                    case.callee_saved = names
+                        .iter()
+                        .map(|name| match register(name)? {
+                            Register::R7 | Register::Pc | Register::Cond => Err(format!(
+                                "{} can't be callee-saved, R7 holds the return address",
+                                name
+                            )),
+                            register => Ok(register),
+                        })
+                        .collect::<Result<Vec<Register>, String>>()
+                        .map_err(error)?
+                }
+                ("INPUT", _) => case.input = string(rest).map_err(error)?,
+                ("EXPECT-OUTPUT", _) => case.expected_output = Some(string(rest).map_err(error)?),
+                _ => return Err(error(format!("Invalid record `{}`", line))),
+            }
+        }
+
+        if cases.is_empty() {
+            return Err("No test case".to_string());
+        }
+        Ok(TestSuite { cases })
+    }
+}
+
+// A quoted string literal with the escapes of `.STRINGZ`
+fn string(text: &str) -> Result<String, String> {
+    match text.strip_prefix('"') {
+        Some(literal) => parse_string(literal),
+        None => Err(format!("Expected a quoted string, found `{}`", text)),
+    }
+}
+
+/// How a case went, it passed when there is no failure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseResult {
+    /// name of the case
+    pub name: String,
+    /// weight of the case
+    pub weight: u32,
+    /// None when the program couldn't be run
+    pub outcome: Option<RunOutcome>,
+    /// instructions executed
+    pub instructions: u64,
+    /// every expectation that didn't hold, or why the case couldn't run
+    pub failures: Vec<String>,
+}
+
+impl CaseResult {
+    /// Whether every expectation held
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// The weight of a passed case, 0 otherwise
+    pub fn score(&self) -> u32 {
+        if self.passed() {
+            self.weight
+        } else {
+            0
+        }
+    }
+}
+
+/// The results of every case of a suite
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// in the order of the suite
+    pub cases: Vec<CaseResult>,
+}
+
+impl Report {
+    /// Every case failed for the same reason, e.g. the program doesn't assemble
+    pub fn failed(suite: &TestSuite, reason: &str) -> Report {
+        let cases = suite.cases.iter().map(|case| CaseResult {
+            name: case.name.clone(),
+            weight: case.weight,
+            outcome: None,
+            instructions: 0,
+            failures: vec![reason.to_string()],
+        });
+        Report {
+            cases: cases.collect(),
+        }
+    }
+
+    /// The weights of the passed cases
+    pub fn score(&self) -> u32 {
+        self.cases.iter().map(CaseResult::score).sum()
+    }
+
+    /// The weights of every case
+    pub fn total(&self) -> u32 {
+        self.cases.iter().map(|case| case.weight).sum()
+    }
+
+    /// Whether every case passed
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(CaseResult::passed)
+    }
+
+    /// The report as a JSON object with the score, the total and every case
+    pub fn to_json(&self) -> String {
+        let cases = self
+            .cases
+            .iter()
+            .map(|case| {
+                let failures = case
+                    .failures
+                    .iter()
+                    .map(|failure| json_string(failure))
+                    .collect::<Vec<String>>();
+                format!(
+                    "    {{\"name\": {}, \"weight\": {}, \"score\": {}, \"passed\": {}, \
+                     \"outcome\": {}, \"instructions\": {}, \"failures\": [{}]}}",
+                    json_string(&case.name),
+                    case.weight,
+                    case.score(),
+                    case.passed(),
+                    case.outcome.map_or("null".to_string(), |outcome| {
+                        json_string(&outcome.to_string())
+                    }),
+                    case.instructions,
+                    failures.join(", ")
+                )
+            })
+            .collect::<Vec<String>>();
+
+        format!(
+            "{{\n  \"score\": {},\n  \"total\": {},\n  \"cases\": [\n{}\n  ]\n}}\n",
+            self.score(),
+            self.total(),
+            cases.join(",\n")
+        )
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for case in &self.cases {
+            let status = if case.passed() { "PASS" } else { "FAIL" };
+            writeln!(
+                f,
+                "{}  {} ({}/{})",
+                status,
+                case.name,
+                case.score(),
+                case.weight
+            )?;
+            for failure in &case.failures {
+                writeln!(f, "      {}", failure)?;
+            }
+        }
+        writeln!(f, "Score: {}/{}", self.score(), self.total())
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\t' => json.push_str("\\t"),
+            '\r' => json.push_str("\\r"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Runs every case in a fresh vm loaded with `images`, labels are resolved with `symbols`
+pub fn grade(images: &[Vec<u16>], symbols: &BTreeMap<String, u16>, suite: &TestSuite) -> Report {
+    Report {
+        cases: suite
+            .cases
+            .iter()
+            .map(|case| run_case(images, symbols, case))
+            .collect(),
+    }
+}
+
+/// Runs a single case in a fresh vm loaded with `images`, labels are resolved with `symbols`
+pub fn run_case(
+    images: &[Vec<u16>],
+    symbols: &BTreeMap<String, u16>,
+    case: &TestCase,
+) -> CaseResult {
+    let mut result = CaseResult {
+        name: case.name.clone(),
+        weight: case.weight,
+        outcome: None,
+        instructions: 0,
+        failures: vec![],
+    };
+
+    let (mut vm, call) = match setup(images, symbols, case) {
+        Ok(setup) => setup,
+        Err(error) => {
+            result.failures.push(error);
+            return result;
+        }
+    };
+    let saved = case
+        .callee_saved
+        .iter()
+        .map(|register| (*register, vm.register(*register)))
+        .collect::<Vec<(Register, u16)>>();
+
+    let (outcome, expected, missed) = match call {
+        Some(address) => (vm.call(address), RunOutcome::Returned, "did not return"),
+        None => (vm.run(), RunOutcome::Halted, "did not halt"),
+    };
+    result.outcome = Some(outcome);
+    result.instructions = vm.counters().instructions;
+    if outcome != expected {
+        result.failures.push(format!(
+            "{}: {} after {} instructions",
+            missed, outcome, result.instructions
+        ));
+    }
+
+    for (register, value) in saved {
+        let actual = vm.register(register);
+        if actual != value {
+            result.failures.push(format!(
+                "{} not preserved: was x{:04X}, now x{:04X}",
+                register, value, actual
+            ));
+        }
+    }
+    if let Err(error) = check(&vm, symbols, case, &mut result.failures) {
+        result.failures.push(error);
+    }
+    result
+}
+
+// A vm loaded with the images and the registers, memory and stack of the case, and the address
+// of the subroutine to call if any
+fn setup(
+    images: &[Vec<u16>],
+    symbols: &BTreeMap<String, u16>,
+    case: &TestCase,
+) -> Result<(Vm, Option<u16>), String> {
+    let mut builder = images
+        .iter()
+        .fold(Vm::builder(), |builder, image| builder.image(image.clone()))
+        .max_instructions(case.max_instructions)
+        .input(case.input.as_bytes());
+    if let Some(entry) = &case.entry {
+        builder = builder.entry(entry.resolve(symbols)?);
+    }
+
+    let mut vm = builder.build()?;
+    for register in &case.callee_saved {
+        vm.set_register(*register, CALLEE_SAVED_SEED + *register as u16);
+    }
+    for (register, value) in &case.registers {
+        vm.set_register(*register, value.resolve(symbols)?);
+    }
+    for (address, words) in &case.memory {
+        let address = address.resolve(symbols)?;
+        for (address, word) in addresses(address, words.len())?.zip(words) {
+            vm.write_memory(address, word.resolve(symbols)?);
+        }
+    }
+
+    let call = match &case.call {
+        Some(subroutine) => subroutine.resolve(symbols)?,
+        None => return Ok((vm, None)),
+    };
+    // an R6 set by the case is kept unless the stack is given
+    let stack = match &case.stack {
+        Some(stack) => stack.resolve(symbols)?,
+        None if case
+            .registers
+            .iter()
+            .any(|(register, _)| *register == Register::R6) =>
+        {
+            vm.register(Register::R6)
+        }
+        None => DEFAULT_STACK,
+    };
+    let top = usize::from(stack)
+        .checked_sub(case.arguments.len())
+        .ok_or_else(|| {
+            format!(
+                "{} arguments don't fit below x{:04X}",
+                case.arguments.len(),
+                stack
+            )
+        })? as u16;
+    for (address, argument) in addresses(top, case.arguments.len())?.zip(&case.arguments) {
+        vm.write_memory(address, argument.resolve(symbols)?);
+    }
+    vm.set_register(Register::R6, top);
+    Ok((vm, Some(call)))
+}
+
+// The addresses of `count` words from `start`, an error when they go past xFFFF
+fn addresses(start: u16, count: usize) -> Result<impl Iterator<Item = u16>, String> {
+    let end = usize::from(start) + count;
+    if end > MAX_ADDRESSABLE_MEMORY {
+        return Err(format!("{} words at x{:04X} go past xFFFF", count, start));
+    }
+    Ok((usize::from(start)..end).map(|address| address as u16))
+}
+
+// Adds a failure for every expectation of the case that doesn't hold
+fn check(
+    vm: &Vm,
+    symbols: &BTreeMap<String, u16>,
+    case: &TestCase,
+    failures: &mut Vec<String>,
+) -> Result<(), String> {
+    for (register, value) in &case.expected_registers {
+        let (expected, actual) = (value.resolve(symbols)?, vm.register(*register));
+        if expected != actual {
+            failures.push(format!(
+                "{}: expected x{:04X}, got x{:04X}",
+                register, expected, actual
+            ));
+        }
+    }
+
+    for (address, words) in &case.expected_memory {
+        let address = address.resolve(symbols)?;
+        for (address, word) in addresses(address, words.len())?.zip(words) {
+            let (expected, actual) = (word.resolve(symbols)?, vm.read_memory(address));
+            if expected != actual {
+                failures.push(format!(
+                    "memory x{:04X}: expected x{:04X}, got x{:04X}",
+                    address, expected, actual
+                ));
+            }
+        }
+    }
+
+    if let Some(expected) = &case.expected_output {
+        let output = String::from_utf8_lossy(vm.console().output());
+        if *expected != output {
+            failures.push(format!("output: expected {:?}, got {:?}", expected, output));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assembler::assemble, vm::RunOutcome};
+
+    use super::{grade, TestSuite, Value};
+
+    const PROGRAM: &str = ".ORIG x3000
+MAIN    GETC
+        ADD R0, R0, #-16
+        ADD R0, R0, #-16
+        ADD R0, R0, #-16
+        ADD R0, R0, R1
+        ST R0, RESULT
+        LEA R0, DONE
+        PUTS
+        HALT
+RESULT  .BLKW 1
+DONE    .STRINGZ \"done\\n\"
+.END";
+
+    const SUBROUTINES: &str = ".ORIG x3000
+        HALT
+; R0 = the sum of the two words on top of the stack, R1 is saved on the stack
+ADD2    ADD R6, R6, #-1
+        STR R1, R6, #0
+        LDR R0, R6, #1
+        LDR R1, R6, #2
+        ADD R0, R0, R1
+        LDR R1, R6, #0
+        ADD R6, R6, #1
+        RET
+; clobbers R1 and leaves a word on the stack
+SLOPPY  ADD R1, R1, #1
+        ADD R6, R6, #-1
+        RET
+ESCAPE  HALT
+.END";
+
+    #[test]
+    fn test_call_subroutines() {
+        let program = assemble(SUBROUTINES).unwrap();
+        let suite = TestSuite::parse(
+            "CALLEE-SAVED R1 R2 R3 R4 R5 R6
+
+CASE adds its stack arguments
+CALL ADD2
+ARGUMENTS 40 2
+EXPECT-REGISTER R0 42
+EXPECT-MEMORY xFDFE 40 2
+
+CASE keeps the stack given
+CALL ADD2
+STACK x5000
+ARGUMENTS #-1 1
+EXPECT-REGISTER R0 0
+EXPECT-REGISTER R6 x4FFE
+
+CASE sloppy
+CALL SLOPPY
+REGISTER R1 7
+
+CASE never returns
+CALL ESCAPE",
+        )
+        .unwrap();
+        assert!(TestSuite::parse("CASE a\nCALLEE-SAVED R1 R7").is_err());
+
+        let report = grade(&[program.to_image().unwrap()], &program.symbols, &suite);
+        assert!(report.cases[0].passed(), "{:?}", report.cases[0]);
+        assert!(report.cases[1].passed(), "{:?}", report.cases[1]);
+        assert_eq!(report.cases[0].outcome, Some(RunOutcome::Returned));
+        assert_eq!(
+            report.cases[2].failures,
+            vec![
+                "R1 not preserved: was x0007, now x0008",
+                "R6 not preserved: was xFE00, now xFDFF"
+            ]
+        );
+        assert_eq!(
+            report.cases[3].failures,
+            vec!["did not return: halted after 1 instructions"]
+        );
+    }
+
+    #[test]
+    fn test_parse_suite() {
+        let suite = TestSuite::parse(
+            "; shared by every case
+MAX-INSTRUCTIONS 500
+CASE first
+WEIGHT 3
+REGISTER R1 #-1
+MEMORY RESULT 1 x2
+INPUT \"7\\n\"
+CASE second
+EXPECT-OUTPUT \"done\\n\"",
+        )
+        .unwrap();
+
+        assert_eq!(suite.cases.len(), 2);
+        let first = &suite.cases[0];
+        assert_eq!(first.weight, 3);
+        assert_eq!(first.max_instructions, 500);
+        assert_eq!(first.registers[0].1, Value::Number(0xFFFF));
+        assert_eq!(first.memory[0].0, Value::Label("RESULT".to_string()));
+        assert_eq!(first.input, "7\n");
+        assert_eq!(suite.cases[1].weight, 1);
+        assert_eq!(suite.cases[1].max_instructions, 500);
+        assert_eq!(suite.cases[1].expected_output.as_deref(), Some("done\n"));
+
+        assert_eq!(
+            TestSuite::parse("CASE a\nREGISTER R9 1").unwrap_err(),
+            "line 2: Unknown register `R9`"
+        );
+        assert!(TestSuite::parse("CASE a\nINPUT abc").is_err());
+        assert!(TestSuite::parse("MAX-INSTRUCTIONS 5").is_err());
+    }
+
+    #[test]
+    fn test_grade() {
+        let program = assemble(PROGRAM).unwrap();
+        let suite = TestSuite::parse(
+            "CASE adds the digit
+WEIGHT 2
+INPUT \"5\"
+REGISTER R1 3
+EXPECT-MEMORY RESULT 8
+EXPECT-OUTPUT \"done\\n\"
+
+CASE wrong expectation
+INPUT \"1\"
+EXPECT-REGISTER R1 1
+EXPECT-MEMORY RESULT 2
+
+CASE loops forever
+ENTRY RESULT
+MAX-INSTRUCTIONS 100
+MEMORY RESULT x0FFF
+
+CASE missing label
+EXPECT-MEMORY ANSWER 1",
+        )
+        .unwrap();
+
+        let report = grade(&[program.to_image().unwrap()], &program.symbols, &suite);
+        assert_eq!(report.score(), 2);
+        assert_eq!(report.total(), 5);
+        assert!(report.cases[0].passed());
+        assert_eq!(
+            report.cases[1].failures,
+            vec![
+                "R1: expected x0001, got x0000",
+                "memory x3009: expected x0002, got x0001"
+            ]
+        );
+        assert_eq!(
+            report.cases[2].failures,
+            vec!["did not halt: step limit exceeded after 100 instructions"]
+        );
+        assert_eq!(report.cases[3].failures, vec!["Unknown label `ANSWER`"]);
+
+        let text = report.to_string();
+        assert!(text.starts_with("PASS  adds the digit (2/2)\nFAIL  wrong expectation (0/1)\n"));
+        assert!(text.ends_with("Score: 2/5\n"));
+
+        let json = report.to_json();
+        assert!(json.starts_with("{\n  \"score\": 2,\n  \"total\": 5,\n  \"cases\": [\n"));
+        assert!(json.contains(
+            "{\"name\": \"adds the digit\", \"weight\": 2, \"score\": 2, \"passed\": true, \
+             \"outcome\": \"halted\", \"instructions\": 9, \"failures\": []}"
+        ));
+        assert!(json.contains("\"failures\": [\"Unknown label `ANSWER`\"]"));
+    }
+
+    #[test]
+    fn test_end_of_memory() {
+        let program = assemble(PROGRAM).unwrap();
+        let suite = TestSuite::parse(
+            "INPUT \"5\"
+
+CASE last word
+MEMORY xFFFF 1
+EXPECT-MEMORY xFFFF 1
+
+CASE past the last word
+MEMORY xFFFF 1 2
+
+CASE expected past the last word
+EXPECT-MEMORY xFFFE 0 0 0
+
+CASE arguments below x0000
+CALL MAIN
+STACK x0001
+ARGUMENTS 1 2",
+        )
+        .unwrap();
+
+        let report = grade(&[program.to_image().unwrap()], &program.symbols, &suite);
+        assert!(report.cases[0].passed());
+        assert_eq!(
+            report.cases[1].failures,
+            vec!["2 words at xFFFF go past xFFFF"]
+        );
+        assert_eq!(
+            report.cases[2].failures,
+            vec!["3 words at xFFFE go past xFFFF"]
+        );
+        assert_eq!(
+            report.cases[3].failures,
+            vec!["2 arguments don't fit below x0001"]
+        );
+    }
+}
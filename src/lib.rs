@@ -0,0 +1,50 @@
+//! An LC-3 virtual machine with its assembler, linker and debugger
+//!
+//! Assemble a source, load it into a [`Vm`] and run it:
+//!
+//! ```
+//! use lc3_vm::{assemble, disassemble, Register, RunOutcome, Vm};
+//!
+//! let program = assemble(
+//!     ".ORIG x3000
+//!      AND R1, R1, #0
+//! LOOP ADD R1, R1, #3
+//!      BRp DONE
+//!      BR LOOP
+//! DONE HALT
+//!      .END",
+//! )
+//! .unwrap();
+//!
+//! let mut vm = Vm::builder().image(program.to_image().unwrap()).build().unwrap();
+//! assert_eq!(vm.step(), None);
+//! assert_eq!(vm.register(Register::R1), 0);
+//! assert_eq!(vm.run(), RunOutcome::Halted);
+//! assert_eq!(vm.register(Register::R1), 3);
+//!
+//! assert!(disassemble(&program).starts_with(".ORIG x3000\nAND R1, R1, #0"));
+//! ```
+
+#![warn(missing_docs)]
+
+/// The assembler, the disassembler, listings and debug info
+pub mod assembler;
+/// Runs without a terminal, with scripted input and a result file
+pub mod batch;
+/// An interactive debugger driven by text commands
+pub mod debugger;
+/// Grades programs against test cases
+pub mod grader;
+/// Links relocatable modules into a program
+pub mod linker;
+/// Reads and writes object files in every supported format
+pub mod object;
+/// Golden-file tests of batch runs
+pub mod snapshot;
+/// A virtual ANSI terminal rendering console output
+pub mod terminal;
+/// The virtual machine
+pub mod vm;
+
+pub use assembler::{assemble, assemble_file, decode_instruction, disassemble, Program};
+pub use vm::{Register, RunOutcome, Vm, VmBuilder};
@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::assembler::{Program, Segment, SourceMap};
+
+/// A relocatable program and the name used for it in error messages and symbol maps
+pub struct Module {
+    /// usually the file name without its extension, e.g. `main`
+    pub name: String,
+    /// the assembled or loaded module
+    pub program: Program,
+}
+
+/// Combines modules into a single program without external labels
+///
+/// Every segment stays at its origin and remains a segment of its own, in the order of the
+/// modules, see [`Program::to_images`]
+pub fn link(modules: &[Module]) -> Result<Program, String> {
+    check_overlaps(modules)?;
+    let globals = collect_globals(modules)?;
+
+    let mut segments = vec![];
+    for module in modules {
+        let mut module_segments = module.program.segments.clone();
+
+        for relocation in &module.program.relocations {
+            let target = if module.program.externals.contains(&relocation.symbol) {
+                match globals.get(&relocation.symbol) {
+                    Some((_, address)) => *address,
+                    None => {
+                        return Err(format!(
+                            "{}: undefined external label `{}` referenced at x{:04X}",
+                            module.name, relocation.symbol, relocation.address
+                        ))
+                    }
+                }
+            } else {
+                *module
+                    .program
+                    .symbols
+                    .get(&relocation.symbol)
+                    .ok_or(format!(
+                        "{}: undefined label `{}` referenced at x{:04X}",
+                        module.name, relocation.symbol, relocation.address
+                    ))?
+            };
+
+            let segment = module_segments
+                .iter_mut()
+                .find(|segment| {
+                    (segment.origin as usize..segment.end())
+                        .contains(&(relocation.address as usize))
+                })
+                .ok_or(format!(
+                    "{}: relocation at x{:04X} is outside of every segment",
+                    module.name, relocation.address
+                ))?;
+            let word = &mut segment.words[(relocation.address - segment.origin) as usize];
+            *word = relocation
+                .apply(*word, target)
+                .map_err(|error| format!("{}: {}", module.name, error))?;
+        }
+
+        segments.extend(module_segments);
+    }
+
+    if segments.is_empty() {
+        return Err("Nothing to link".to_string());
+    }
+    Ok(Program {
+        segments,
+        symbols: symbol_map(modules),
+        lines: BTreeMap::new(),
+        globals: globals.keys().cloned().collect(),
+        externals: BTreeSet::new(),
+        relocations: vec![],
+        source_map: SourceMap::default(),
+    })
+}
+
+fn check_overlaps(modules: &[Module]) -> Result<(), String> {
+    let segments = modules
+        .iter()
+        .flat_map(|module| {
+            module
+                .program
+                .segments
+                .iter()
+                .map(move |segment| (module.name.as_str(), segment))
+        })
+        .collect::<Vec<(&str, &Segment)>>();
+
+    for (index, (name, segment)) in segments.iter().enumerate() {
+        for (other_name, other) in &segments[index + 1..] {
+            if segment.overlaps(other) {
+                return Err(format!(
+                    "Segment x{:04X}-x{:04X} of {} overlaps segment x{:04X}-x{:04X} of {}",
+                    segment.origin,
+                    segment.end() - 1,
+                    name,
+                    other.origin,
+                    other.end() - 1,
+                    other_name
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Address of every global label and the index of the module defining it
+fn collect_globals(modules: &[Module]) -> Result<BTreeMap<String, (usize, u16)>, String> {
+    let mut globals = BTreeMap::new();
+
+    for (index, module) in modules.iter().enumerate() {
+        for label in &module.program.globals {
+            let address = *module.program.symbols.get(label).ok_or(format!(
+                "{}: global label `{}` is not defined",
+                module.name, label
+            ))?;
+            if let Some((other, _)) = globals.insert(label.clone(), (index, address)) {
+                return Err(format!(
+                    "Global label `{}` is defined by both {} and {}",
+                    label, modules[other].name, module.name
+                ));
+            }
+        }
+    }
+
+    Ok(globals)
+}
+
+// Globals keep their name, local labels defined by several modules get prefixed with it
+fn symbol_map(modules: &[Module]) -> BTreeMap<String, u16> {
+    let mut definitions = BTreeMap::<&str, usize>::new();
+    for module in modules {
+        for label in module.program.symbols.keys() {
+            *definitions.entry(label).or_default() += 1;
+        }
+    }
+
+    let mut symbols = BTreeMap::new();
+    for module in modules {
+        for (label, address) in &module.program.symbols {
+            let shared = definitions[label.as_str()] > 1 && !module.program.globals.contains(label);
+            match shared {
+                true => symbols.insert(format!("{}.{}", module.name, label), *address),
+                false => symbols.insert(label.clone(), *address),
+            };
+        }
+    }
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assembler::assemble, vm::Vm};
+
+    use super::{link, Module};
+
+    const MAIN: &str = "
+                .EXTERNAL PRINT, GREETING
+                .ORIG x3000
+        MAIN    LD R0, POINTER
+                JSR PRINT
+        LOOP    HALT
+        POINTER .FILL GREETING
+                .END";
+
+    const LIBRARY: &str = "
+                .GLOBAL PRINT, GREETING
+                .ORIG x3100
+        PRINT   ST R7, SAVE
+                PUTS
+                LD R7, SAVE
+                RET
+        LOOP    BR LOOP
+        SAVE    .BLKW 1
+                .END
+                .ORIG x3200
+        GREETING .STRINGZ \"Hi\"
+                .END";
+
+    fn module(name: &str, source: &str) -> Module {
+        Module {
+            name: name.to_string(),
+            program: assemble(source).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_link_modules() {
+        let program = link(&[module("main", MAIN), module("lib", LIBRARY)]).unwrap();
+        let images = program.to_images().unwrap();
+
+        // one image per segment, nothing fills the gaps between them
+        assert_eq!(
+            images
+                .iter()
+                .map(|image| (image[0], image.len() - 1))
+                .collect::<Vec<_>>(),
+            vec![(0x3000, 4), (0x3100, 6), (0x3200, 3)]
+        );
+        // JSR PRINT from x3001 to x3100
+        assert_eq!(images[0][2], 0x4800 | 0xFE);
+        assert_eq!(images[0][4], 0x3200);
+        assert_eq!(program.symbols["PRINT"], 0x3100);
+        assert_eq!(program.symbols["main.LOOP"], 0x3002);
+        assert_eq!(program.symbols["lib.LOOP"], 0x3104);
+
+        let mut vm = Vm::initialize();
+        vm.load_programs(&images).unwrap();
+        vm.run();
+        assert_eq!(vm.get_register(0), 0x3200);
+    }
+
+    #[test]
+    fn test_link_errors() {
+        assert_eq!(
+            link(&[module("main", MAIN)]).unwrap_err(),
+            "main: undefined external label `PRINT` referenced at x3001"
+        );
+
+        let far = LIBRARY.replace("x3100", "x3500");
+        assert!(link(&[module("main", MAIN), module("lib", &far)])
+            .unwrap_err()
+            .contains("too far"));
+
+        let overlapping = LIBRARY.replace("x3100", "x3002");
+        assert!(link(&[module("main", MAIN), module("lib", &overlapping)])
+            .unwrap_err()
+            .contains("overlaps"));
+
+        let duplicate = ".GLOBAL PRINT\n.ORIG x4000\nPRINT RET\n.END";
+        assert!(link(&[
+            module("main", MAIN),
+            module("lib", LIBRARY),
+            module("other", duplicate)
+        ])
+        .unwrap_err()
+        .contains("defined by both lib and other"));
+    }
+}
@@ -1,6 +1,581 @@
-mod assembler;
-mod vm;
+mod bench;
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    slice::Iter,
+};
+
+use lc3_vm::{
+    assembler::{
+        assemble_file, disassemble, listing, parse_address, parse_symbols, symbols_to_string,
+        DebugInfo,
+    },
+    batch,
+    debugger::{describe_address, Debugger},
+    grader::{self, Report, TestSuite},
+    linker, object,
+    snapshot::{self, Verdict},
+    vm::MemoryMap,
+    RunOutcome, Vm, VmBuilder,
+};
+
+fn usage(program: &str) {
+    println!("Usage:");
+    println!(
+        "  {} run <program.obj>... [--entry <address|label>] [--max-instructions <n>] [--stats]",
+        program
+    );
+    println!("      [--symbols <program.sym>]");
+    println!("      [--profile <report.txt>] [--folded <stacks.folded>]");
+    println!("      [--debug-info <program.dbg> --coverage <coverage.info>]");
+    println!("      [--check] [--sanitize] [--protect] [--region <start-end:rwx>]...");
+    println!(
+        "  {} debug <program.obj>... [--debug-info <program.dbg>] [--symbols <program.sym>]",
+        program
+    );
+    println!("      [--entry <address|label>] [--max-instructions <n>]");
+    println!(
+        "  {} batch <program.obj>... [--input <text> | --input-file <input.txt>] [--result <result.txt>]",
+        program
+    );
+    println!("      [--entry <address|label>] [--symbols <program.sym>] [--max-instructions <n>]");
+    println!("      [--screen <columns>x<rows>]");
+    println!("  {} snapshot <test.snap>... [--update]", program);
+    println!(
+        "  {} asm <program.asm> <program.obj> [--listing <program.lst>]",
+        program
+    );
+    println!(
+        "  {} grade <program.asm|program.obj> <tests.txt> [--symbols <program.sym>] [--json <report.json>]",
+        program
+    );
+    println!("  {} link <program.obj> <module.rel>...", program);
+    println!("  {} disasm <program.obj|program.asm>", program);
+    println!("  {} convert <input> <output>", program);
+    println!("  {} bench [iterations]", program);
+    println!("Object files can be .obj, .bin, .hex, Intel HEX (.ihex, .ihx) or relocatable (.rel)");
+}
+
+// Reports an invalid command line and exits
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+// An address or a label of the symbol file
+fn entry_point(entry: &str, symbols: &BTreeMap<String, u16>) -> u16 {
+    let address = parse_address(entry).or(symbols.get(entry).copied());
+    address.unwrap_or_else(|| fail(&format!("Unknown entry point `{}`", entry)))
+}
+
+// The argument following `option`
+fn value<'a>(option: &str, options: &mut Iter<'a, String>) -> &'a str {
+    match options.next() {
+        Some(value) => value,
+        None => fail(&format!("Missing value for `{}`", option)),
+    }
+}
+
+fn read_text(path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+    fs::read_to_string(path).unwrap_or_else(|error| fail(&format!("{}: {}", path.display(), error)))
+}
+
+fn write_text(path: impl AsRef<Path>, text: impl AsRef<[u8]>) {
+    let path = path.as_ref();
+    fs::write(path, text).unwrap_or_else(|error| fail(&format!("{}: {}", path.display(), error)))
+}
+
+fn read_symbols(path: &str) -> BTreeMap<String, u16> {
+    fs::read_to_string(path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| parse_symbols(&text))
+        .unwrap_or_else(|error| fail(&format!("{}: {}", path, error)))
+}
+
+fn read_debug_info(path: &str) -> DebugInfo {
+    fs::read_to_string(path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| DebugInfo::parse(&text))
+        .unwrap_or_else(|error| fail(&format!("{}: {}", path, error)))
+}
+
+// The arguments of a command running images: the images to load, then the options shared by
+// every such command and those of the command itself
+struct RunArguments<'a> {
+    images: &'a [String],
+    options: Iter<'a, String>,
+    entry: Option<&'a str>,
+    symbols: BTreeMap<String, u16>,
+    max_instructions: Option<u64>,
+}
+
+impl<'a> RunArguments<'a> {
+    // Every argument before the first option is an image to load
+    fn new(args: &'a [String]) -> Self {
+        let count = args
+            .iter()
+            .position(|arg| arg.starts_with("--"))
+            .unwrap_or(args.len());
+        RunArguments {
+            images: &args[..count],
+            options: args[count..].iter(),
+            entry: None,
+            symbols: BTreeMap::new(),
+            max_instructions: None,
+        }
+    }
+
+    // The next option the command handles itself, after taking the shared ones
+    fn next_option(&mut self) -> Option<&'a str> {
+        while let Some(option) = self.options.next() {
+            match option.as_str() {
+                "--entry" => self.entry = Some(value(option, &mut self.options)),
+                "--symbols" => self.symbols = read_symbols(value(option, &mut self.options)),
+                "--max-instructions" => {
+                    let max = value(option, &mut self.options);
+                    let max = max
+                        .parse()
+                        .unwrap_or_else(|_| fail(&format!("Invalid instruction limit `{}`", max)));
+                    self.max_instructions = Some(max);
+                }
+                option => return Some(option),
+            }
+        }
+        None
+    }
+
+    // The value of the option `next_option` just returned
+    fn value(&mut self, option: &str) -> &'a str {
+        value(option, &mut self.options)
+    }
+
+    // The images, the entry point and the instruction limit, once every option is taken
+    fn builder(&self) -> VmBuilder {
+        let mut builder = self
+            .images
+            .iter()
+            .fold(Vm::builder(), |builder, path| builder.file(path));
+        if let Some(entry) = self.entry {
+            builder = builder.entry(entry_point(entry, &self.symbols));
+        }
+        if let Some(max) = self.max_instructions {
+            builder = builder.max_instructions(max);
+        }
+        builder
+    }
+}
+
+fn unknown_option(option: &str) -> ! {
+    fail(&format!("Unknown option `{}`", option))
+}
+
+// The file of the segment at `origin` when a linked program is written as flat images
+fn segment_path(path: &Path, origin: u16) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("-{:04X}", origin));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
 
 fn main() {
-    println!("Hello world!");
+    let args = env::args().collect::<Vec<String>>();
+
+    match args.get(1).map(String::as_str) {
+        Some("run") if args.len() > 2 => {
+            let mut arguments = RunArguments::new(&args[2..]);
+            let mut stats = false;
+            let mut profile = None;
+            let mut folded = None;
+            let mut debug_info = None;
+            let mut coverage = None;
+            let mut check = false;
+            let mut sanitize = false;
+            let mut protect = false;
+            let mut regions = vec![];
+            while let Some(option) = arguments.next_option() {
+                match option {
+                    "--stats" => stats = true,
+                    "--profile" => profile = Some(arguments.value(option)),
+                    "--folded" => folded = Some(arguments.value(option)),
+                    "--debug-info" => debug_info = Some(read_debug_info(arguments.value(option))),
+                    "--coverage" => coverage = Some(arguments.value(option)),
+                    "--check" => check = true,
+                    "--sanitize" => sanitize = true,
+                    "--protect" => protect = true,
+                    "--region" => regions.push(arguments.value(option)),
+                    option => unknown_option(option),
+                }
+            }
+            if coverage.is_some() && debug_info.is_none() {
+                fail("--coverage requires --debug-info");
+            }
+            let mut builder = arguments.builder();
+            let symbols = arguments.symbols;
+
+            // regions apply on top of the layout given by the debug info
+            if protect || !regions.is_empty() {
+                let mut memory_map = match (protect, &debug_info) {
+                    (true, Some(debug_info)) => MemoryMap::user(debug_info),
+                    (true, None) => fail("--protect requires --debug-info"),
+                    (false, _) => MemoryMap::default(),
+                };
+                for region in regions {
+                    if let Err(error) = memory_map.protect_region(region) {
+                        fail(&error);
+                    }
+                }
+                builder = builder.memory_map(memory_map);
+            }
+
+            let mut vm = match builder
+                .profiler(profile.is_some() || folded.is_some())
+                .coverage(coverage.is_some())
+                .checker(check)
+                .sanitizer(sanitize)
+                .build()
+            {
+                Ok(vm) => vm,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            };
+
+            let outcome = vm.run();
+            if let Some(path) = profile {
+                write_text(path, vm.profile_report(&symbols).unwrap());
+            }
+            if let Some(path) = folded {
+                let profiler = vm.profiler().unwrap();
+                write_text(path, profiler.folded_stacks(&symbols));
+            }
+            if let Some(path) = coverage {
+                let debug_info = debug_info.as_ref().unwrap();
+                let branches = vm.conditional_branches(debug_info);
+                let coverage = vm.coverage().unwrap();
+                write_text(path, coverage.lcov(debug_info, &branches));
+                eprintln!("coverage: {}", coverage.summary(debug_info, &branches));
+            }
+            if let Some(checker) = vm.checker() {
+                let violations = checker.violations();
+                eprint!("{}", checker.report_text(&symbols));
+                eprintln!("calling convention: {} violation(s)", violations.len());
+            }
+            if let Some(sanitizer) = vm.sanitizer() {
+                eprint!("{}", sanitizer.report());
+                eprintln!("uninitialized reads: {}", sanitizer.reads().len());
+            }
+            if stats {
+                let counters = vm.counters();
+                eprintln!("outcome: {}", outcome);
+                eprintln!("instructions: {}", counters.instructions);
+                eprintln!("cycles: {}", counters.cycles);
+            }
+            match outcome {
+                RunOutcome::StepLimitExceeded => {
+                    eprintln!(
+                        "Step limit exceeded after {} instructions",
+                        vm.counters().instructions
+                    );
+                    eprint!("{}", vm.backtrace(debug_info.as_ref(), &symbols));
+                    std::process::exit(2);
+                }
+                RunOutcome::IllegalInstruction(address) => {
+                    eprintln!(
+                        "Illegal instruction x{:04X} at {}",
+                        vm.read_memory(address),
+                        describe_address(debug_info.as_ref(), address)
+                    );
+                    eprint!("{}", vm.backtrace(debug_info.as_ref(), &symbols));
+                    std::process::exit(1);
+                }
+                RunOutcome::AccessViolation(violation) => {
+                    eprintln!(
+                        "Access violation, {} at {}",
+                        violation.operation(),
+                        describe_address(debug_info.as_ref(), violation.pc)
+                    );
+                    eprint!("{}", vm.backtrace(debug_info.as_ref(), &symbols));
+                    std::process::exit(1);
+                }
+                RunOutcome::Halted | RunOutcome::Breakpoint(_) | RunOutcome::Returned => {}
+            }
+        }
+        Some("debug") if args.len() > 2 => {
+            let mut arguments = RunArguments::new(&args[2..]);
+            let mut debug_info = None;
+            while let Some(option) = arguments.next_option() {
+                match option {
+                    "--debug-info" => debug_info = Some(read_debug_info(arguments.value(option))),
+                    option => unknown_option(option),
+                }
+            }
+            let vm = arguments
+                .builder()
+                .build()
+                .unwrap_or_else(|error| fail(&error));
+
+            // commands are read from stdin, one per line
+            let mut debugger = Debugger::new(vm, debug_info, arguments.symbols);
+            let mut lines = io::stdin().lock().lines();
+            loop {
+                eprint!("(lc3) ");
+                io::stderr().flush().unwrap();
+                let line = match lines.next() {
+                    Some(line) => line.unwrap(),
+                    None => break,
+                };
+                if ["quit", "q"].contains(&line.trim()) {
+                    break;
+                }
+                match debugger.execute(&line) {
+                    Ok(output) if output.is_empty() => {}
+                    Ok(output) => println!("{}", output),
+                    Err(error) => eprintln!("{}", error),
+                }
+            }
+        }
+        Some("batch") if args.len() > 2 => {
+            let mut arguments = RunArguments::new(&args[2..]);
+            arguments.max_instructions = Some(grader::DEFAULT_MAX_INSTRUCTIONS);
+            let mut input = vec![];
+            let mut result_path = None;
+            let mut screen = None;
+            while let Some(option) = arguments.next_option() {
+                match option {
+                    "--input" => {
+                        input = batch::parse_input(arguments.value(option))
+                            .unwrap_or_else(|error| fail(&error));
+                    }
+                    "--input-file" => {
+                        let path = arguments.value(option);
+                        input = fs::read(path)
+                            .unwrap_or_else(|error| fail(&format!("{}: {}", path, error)));
+                    }
+                    "--result" => result_path = Some(arguments.value(option)),
+                    "--screen" => {
+                        let size = arguments.value(option);
+                        let parsed = size.split_once('x').and_then(|(columns, rows)| {
+                            Some((columns.parse().ok()?, rows.parse().ok()?))
+                        });
+                        screen = match parsed {
+                            Some((columns, rows)) if columns > 0 && rows > 0 => {
+                                Some((columns, rows))
+                            }
+                            _ => fail(&format!(
+                                "Invalid screen size `{}`, expected e.g. `80x24`",
+                                size
+                            )),
+                        };
+                    }
+                    option => unknown_option(option),
+                }
+            }
+
+            let mut vm = arguments
+                .builder()
+                .build()
+                .unwrap_or_else(|error| fail(&error));
+            let result = batch::run(&mut vm, &input);
+            let mut text = result.to_string();
+            if let Some((columns, rows)) = screen {
+                text.push_str(&batch::screen(&result.output, columns, rows));
+            }
+            match result_path {
+                Some(path) => write_text(path, text),
+                None => print!("{}", text),
+            }
+            match result.outcome {
+                RunOutcome::StepLimitExceeded => std::process::exit(2),
+                RunOutcome::IllegalInstruction(_) | RunOutcome::AccessViolation(_) => {
+                    std::process::exit(1)
+                }
+                RunOutcome::Halted | RunOutcome::Breakpoint(_) | RunOutcome::Returned => {}
+            }
+        }
+        Some("snapshot") if args.len() > 2 => {
+            let update = args[2..].iter().any(|arg| arg == "--update");
+            let mut failed = false;
+            for path in args[2..].iter().filter(|arg| *arg != "--update") {
+                match snapshot::check(Path::new(path), update) {
+                    Ok(Verdict::Matches) => println!("ok       {}", path),
+                    Ok(Verdict::Updated) => println!("updated  {}", path),
+                    Ok(Verdict::Differs(difference)) => {
+                        failed = true;
+                        println!("FAIL     {}\n{}", path, difference);
+                    }
+                    Err(error) => {
+                        failed = true;
+                        println!("FAIL     {}\n{}", path, error);
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Some("asm") if args.len() > 3 => {
+            let mut listing_file = None;
+            let mut options = args[4..].iter();
+            while let Some(option) = options.next() {
+                match option.as_str() {
+                    "--listing" => listing_file = Some(value(option, &mut options)),
+                    option => unknown_option(option),
+                }
+            }
+
+            let source = read_text(&args[2]);
+            let program = match assemble_file(&source, Path::new(&args[2])) {
+                Ok(program) => program,
+                Err(error) => {
+                    eprintln!("{}: {}", args[2], error);
+                    std::process::exit(1);
+                }
+            };
+
+            // the output format is picked from the extension of the object file
+            let object_file = Path::new(&args[3]);
+            if let Err(error) = object::write_program(object_file, &program) {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+
+            // the symbol table and debug info are written next to the object file
+            let symbol_file = object_file.with_extension("sym");
+            write_text(symbol_file, symbols_to_string(&program.symbols));
+            let debug_info = DebugInfo::new(&args[2], &program);
+            write_text(object_file.with_extension("dbg"), debug_info.to_string());
+            if let Some(path) = listing_file {
+                write_text(path, listing(&source, &program));
+            }
+        }
+        Some("grade") if args.len() > 3 => {
+            let suite = match TestSuite::parse(&read_text(&args[3])) {
+                Ok(suite) => suite,
+                Err(error) => {
+                    eprintln!("{}: {}", args[3], error);
+                    std::process::exit(1);
+                }
+            };
+
+            let mut symbols = BTreeMap::new();
+            let mut json = None;
+            let mut options = args[4..].iter();
+            while let Some(option) = options.next() {
+                match option.as_str() {
+                    "--symbols" => symbols = read_symbols(value(option, &mut options)),
+                    "--json" => json = Some(value(option, &mut options)),
+                    option => unknown_option(option),
+                }
+            }
+
+            // a source is assembled first, a program that doesn't assemble fails every case
+            let path = Path::new(&args[2]);
+            let images = if path.extension().is_some_and(|extension| extension == "asm") {
+                let source = read_text(path);
+                assemble_file(&source, path)
+                    .map_err(|error| format!("{}: {}", args[2], error))
+                    .and_then(|program| {
+                        symbols = program.symbols.clone();
+                        program.to_images()
+                    })
+            } else {
+                object::read_images(path)
+            };
+            let report = match images {
+                Ok(images) => grader::grade(&images, &symbols, &suite),
+                Err(error) => Report::failed(&suite, &error),
+            };
+
+            print!("{}", report);
+            if let Some(path) = json {
+                write_text(path, report.to_json());
+            }
+            if !report.passed() {
+                std::process::exit(1);
+            }
+        }
+        Some("link") if args.len() > 3 => {
+            let modules = args[3..]
+                .iter()
+                .map(|path| {
+                    let path = Path::new(path);
+                    object::read_program(path).map(|program| linker::Module {
+                        name: path.file_stem().unwrap().to_string_lossy().to_string(),
+                        program,
+                    })
+                })
+                .collect::<Result<Vec<linker::Module>, String>>();
+            let program = match modules.and_then(|modules| linker::link(&modules)) {
+                Ok(program) => program,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            };
+
+            // a .rel object holds every segment, a flat format gets one file per segment named
+            // after its origin, e.g. program-3100.obj, and the merged symbol map is written next
+            // to them
+            let object_file = Path::new(&args[2]);
+            let relocatable = object_file
+                .extension()
+                .is_some_and(|extension| extension == "rel");
+            let written = if program.is_flat() || relocatable {
+                object::write_program(object_file, &program)
+            } else {
+                program.to_images().and_then(|images| {
+                    images.iter().try_for_each(|image| {
+                        object::write_file(&segment_path(object_file, image[0]), image)
+                    })
+                })
+            };
+            if let Err(error) = written {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+            let symbol_file = object_file.with_extension("sym");
+            write_text(symbol_file, symbols_to_string(&program.symbols));
+        }
+        Some("disasm") if args.len() > 2 => {
+            // a source keeps track of its pseudo-instructions, an object file doesn't
+            let path = Path::new(&args[2]);
+            let program = if path.extension().is_some_and(|extension| extension == "asm") {
+                let source = read_text(path);
+                assemble_file(&source, path).map_err(|error| format!("{}: {}", args[2], error))
+            } else {
+                object::read_program(path)
+            };
+            let program = program.unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            });
+
+            print!("{}", disassemble(&program));
+        }
+        Some("convert") if args.len() > 3 => {
+            let program =
+                object::read_program(Path::new(&args[2])).unwrap_or_else(|error| fail(&error));
+            object::write_program(Path::new(&args[3]), &program)
+                .unwrap_or_else(|error| fail(&error));
+        }
+        Some("bench") => {
+            let iterations = args.get(2).map_or(10, |count| {
+                count
+                    .parse()
+                    .unwrap_or_else(|_| fail(&format!("Invalid iteration count `{}`", count)))
+            });
+            if !bench::run_benchmarks(iterations) {
+                std::process::exit(1);
+            }
+        }
+        _ => usage(&args[0]),
+    }
 }
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+
+// Data bytes per record written
+const RECORD_LENGTH: usize = 16;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+
+// Word `n` of memory is stored big-endian at byte addresses `2n` and `2n + 1`
+pub(crate) fn write(image: &[u16]) -> String {
+    let origin = image[0] as usize * 2;
+    let bytes = image[1..]
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .collect::<Vec<u8>>();
+
+    let mut hex = String::new();
+    let mut upper = None;
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let address = origin + offset;
+
+        // records can't cross a 64KB boundary, the upper address bits need their own record
+        if upper != Some(address >> 16) {
+            upper = Some(address >> 16);
+            let upper = (address >> 16) as u16;
+            hex.push_str(&record(EXTENDED_LINEAR_ADDRESS, 0, &upper.to_be_bytes()));
+        }
+
+        let boundary = ((address >> 16) + 1) << 16;
+        let length = RECORD_LENGTH
+            .min(bytes.len() - offset)
+            .min(boundary - address);
+        hex.push_str(&record(
+            DATA,
+            address as u16,
+            &bytes[offset..offset + length],
+        ));
+        offset += length;
+    }
+
+    // an image without words still records its origin
+    if bytes.is_empty() {
+        hex.push_str(&record(
+            EXTENDED_LINEAR_ADDRESS,
+            0,
+            &((origin >> 16) as u16).to_be_bytes(),
+        ));
+        hex.push_str(&record(DATA, origin as u16, &[]));
+    }
+
+    hex.push_str(&record(END_OF_FILE, 0, &[]));
+    hex
+}
+
+fn record(kind: u8, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(address.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg();
+    bytes.push(checksum);
+
+    let digits = bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<String>();
+    format!(":{}\n", digits)
+}
+
+pub(crate) fn read(bytes: &[u8]) -> Result<Vec<u16>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "File is not text".to_string())?;
+    let mut memory = BTreeMap::new();
+    let mut first = None;
+    let mut base = 0;
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+
+        let digits = line
+            .strip_prefix(':')
+            .ok_or(error("records start with `:`"))?;
+        if !digits.len().is_multiple_of(2) || digits.len() < 10 {
+            return Err(error("record is too short"));
+        }
+        let bytes = (0..digits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| error("invalid hex digits"))?;
+
+        let length = bytes[0] as usize;
+        if bytes.len() != length + 5 {
+            return Err(error("record length does not match its data"));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("checksum mismatch"));
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        let data = &bytes[4..4 + length];
+        match bytes[3] {
+            DATA => {
+                first.get_or_insert(base + address);
+                for (i, byte) in data.iter().enumerate() {
+                    memory.insert(base + address + i, *byte);
+                }
+            }
+            END_OF_FILE => break,
+            EXTENDED_SEGMENT_ADDRESS if length == 2 => {
+                base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 4;
+            }
+            EXTENDED_LINEAR_ADDRESS if length == 2 => {
+                base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 16;
+            }
+            // start address records don't affect the image
+            0x03 | 0x05 => {}
+            _ => return Err(error("unsupported record type")),
+        }
+    }
+
+    let start = match memory.keys().next() {
+        Some(start) => *start,
+        None => match first {
+            Some(start) => start,
+            None => return Err("No data records".to_string()),
+        },
+    };
+    if !start.is_multiple_of(2) || !memory.len().is_multiple_of(2) || start / 2 > u16::MAX as usize
+    {
+        return Err("Data does not start on a word boundary".to_string());
+    }
+    if memory
+        .keys()
+        .enumerate()
+        .any(|(i, address)| *address != start + i)
+    {
+        return Err("Data records must form a single contiguous block".to_string());
+    }
+
+    let bytes = memory.into_values().collect::<Vec<u8>>();
+    let mut image = vec![(start / 2) as u16];
+    image.extend(
+        bytes
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]])),
+    );
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, record, write, DATA};
+
+    #[test]
+    fn test_write_records() {
+        let hex = write(&[0x3000, 0xE002, 0xF025]);
+        assert_eq!(hex, ":020000040000FA\n:04600000E002F025A5\n:00000001FF\n");
+    }
+
+    #[test]
+    fn test_roundtrip_across_64k_boundary() {
+        let mut image = vec![0x7FFE];
+        image.extend(0..20);
+        let hex = write(&image);
+
+        assert!(hex.contains(":020000040001F9"));
+        assert_eq!(read(hex.as_bytes()).unwrap(), image);
+    }
+
+    #[test]
+    fn test_read_errors() {
+        assert!(read(b":04600000E002F025A4\n").is_err());
+        assert!(read(b"04600000E002F025C5\n").is_err());
+        let gap = record(DATA, 0x6000, &[0x12, 0x34]) + &record(DATA, 0x6004, &[0x12, 0x34]);
+        assert_eq!(
+            read(gap.as_bytes()).unwrap_err(),
+            "Data records must form a single contiguous block"
+        );
+        assert!(read(b"").is_err());
+    }
+}
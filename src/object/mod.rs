@@ -0,0 +1,237 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use crate::assembler::{Program, Segment, SourceMap};
+
+mod intel_hex;
+mod relocatable;
+
+// Formats of a program image: the origin followed by the words to load there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Format {
+    // big-endian words, the origin is the first word
+    Obj,
+    // one word per line written as 16 `0`/`1` characters, the origin is the first line
+    Bin,
+    // one word per line written as 4 hex digits, the origin is the first line
+    Hex,
+    // Intel HEX records addressing the words as big-endian byte pairs
+    IntelHex,
+}
+
+impl Format {
+    // Picks the format from the file extension, `.hex` can hold either kind of hex file
+    pub(crate) fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "obj" => Some(Format::Obj),
+            "bin" => Some(Format::Bin),
+            "ihex" | "ihx" => Some(Format::IntelHex),
+            _ => None,
+        }
+    }
+
+    // Guesses the format from the content of a file
+    pub(crate) fn detect(bytes: &[u8]) -> Format {
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(_) => return Format::Obj,
+        };
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+
+        match lines.next() {
+            Some(line) if line.starts_with(':') => Format::IntelHex,
+            Some(line) if is_word(line, 16, 2) && lines.all(|line| is_word(line, 16, 2)) => {
+                Format::Bin
+            }
+            Some(line) if is_word(line, 4, 16) && lines.all(|line| is_word(line, 4, 16)) => {
+                Format::Hex
+            }
+            _ => Format::Obj,
+        }
+    }
+}
+
+fn is_word(line: &str, digits: usize, radix: u32) -> bool {
+    line.len() == digits && line.chars().all(|c| c.is_digit(radix))
+}
+
+pub(crate) fn read_image(bytes: &[u8], format: Format) -> Result<Vec<u16>, String> {
+    let image = match format {
+        Format::Obj => {
+            if !bytes.len().is_multiple_of(2) {
+                return Err("Object file has an odd number of bytes".to_string());
+            }
+            bytes
+                .chunks(2)
+                .map(|word| u16::from_be_bytes([word[0], word[1]]))
+                .collect()
+        }
+        Format::Bin => read_text(bytes, 16, 2)?,
+        Format::Hex => read_text(bytes, 4, 16)?,
+        Format::IntelHex => intel_hex::read(bytes)?,
+    };
+
+    if image.is_empty() {
+        return Err("Image is empty".to_string());
+    }
+    Ok(image)
+}
+
+pub(crate) fn write_image(image: &[u16], format: Format) -> Vec<u8> {
+    match format {
+        Format::Obj => image.iter().flat_map(|word| word.to_be_bytes()).collect(),
+        Format::Bin => image
+            .iter()
+            .map(|word| format!("{:016b}\n", word))
+            .collect::<String>()
+            .into_bytes(),
+        Format::Hex => image
+            .iter()
+            .map(|word| format!("{:04X}\n", word))
+            .collect::<String>()
+            .into_bytes(),
+        Format::IntelHex => intel_hex::write(image).into_bytes(),
+    }
+}
+
+/// Reads an image, using the extension of the file or else its content to pick the format
+///
+/// A relocatable object is accepted when it holds a single self-contained segment
+pub fn read_file(path: &Path) -> Result<Vec<u16>, String> {
+    read_program(path)?
+        .to_image()
+        .map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+/// Reads the images of a file, one per segment, e.g. of a linked relocatable object
+pub fn read_images(path: &Path) -> Result<Vec<Vec<u16>>, String> {
+    read_program(path)?
+        .to_images()
+        .map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+/// Reads a relocatable object, or a flat image as a program with a single segment
+pub fn read_program(path: &Path) -> Result<Program, String> {
+    let bytes = fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    let error = |error: String| format!("{}: {}", path.display(), error);
+
+    if bytes.starts_with(relocatable::MAGIC.as_bytes()) {
+        let text = std::str::from_utf8(&bytes).map_err(|_| error("File is not text".into()))?;
+        return relocatable::read(text).map_err(error);
+    }
+
+    let format = Format::from_path(path).unwrap_or(Format::detect(&bytes));
+    let image = read_image(&bytes, format).map_err(error)?;
+    Ok(Program {
+        segments: vec![Segment {
+            origin: image[0],
+            words: image[1..].to_vec(),
+        }],
+        symbols: BTreeMap::new(),
+        lines: BTreeMap::new(),
+        globals: BTreeSet::new(),
+        externals: BTreeSet::new(),
+        relocations: vec![],
+        source_map: SourceMap::default(),
+    })
+}
+
+/// Writes a relocatable object for `.rel` files, a flat image in any other format
+pub fn write_program(path: &Path, program: &Program) -> Result<(), String> {
+    if path.extension().is_some_and(|extension| extension == "rel") {
+        return fs::write(path, relocatable::write(program))
+            .map_err(|error| format!("{}: {}", path.display(), error));
+    }
+    if !program.is_flat() {
+        return Err(format!(
+            "{}: program has several segments or external labels, write a .rel object instead",
+            path.display()
+        ));
+    }
+    write_file(path, &program.to_image()?)
+}
+
+/// Writes an image in the format given by the extension, `.obj` when it is unknown
+pub fn write_file(path: &Path, image: &[u16]) -> Result<(), String> {
+    let format = match Format::from_path(path) {
+        Some(format) => format,
+        None if path.extension().is_some_and(|extension| extension == "hex") => Format::Hex,
+        None => Format::Obj,
+    };
+    fs::write(path, write_image(image, format))
+        .map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+fn read_text(bytes: &[u8], digits: usize, radix: u32) -> Result<Vec<u16>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "File is not text".to_string())?;
+
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(index, line)| {
+            if !is_word(line, digits, radix) {
+                return Err(format!(
+                    "line {}: expected {} digits in base {}",
+                    index + 1,
+                    digits,
+                    radix
+                ));
+            }
+            Ok(u16::from_str_radix(line, radix).unwrap())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{read_image, write_image, Format};
+
+    const IMAGE: [u16; 4] = [0x3000, 0xE002, 0xF022, 0x0048];
+
+    #[test]
+    fn test_formats_roundtrip() {
+        for format in [Format::Obj, Format::Bin, Format::Hex, Format::IntelHex] {
+            let bytes = write_image(&IMAGE, format);
+            assert_eq!(Format::detect(&bytes), format);
+            assert_eq!(read_image(&bytes, format).unwrap(), IMAGE);
+        }
+    }
+
+    #[test]
+    fn test_text_formats() {
+        assert_eq!(
+            String::from_utf8(write_image(&IMAGE, Format::Hex)).unwrap(),
+            "3000\nE002\nF022\n0048\n"
+        );
+        assert_eq!(
+            read_image(b"0011000000000000\n1111000000100101\n", Format::Bin).unwrap(),
+            vec![0x3000, 0xF025]
+        );
+        assert!(read_image(b"3000\n12345\n", Format::Hex).is_err());
+        assert!(read_image(b"", Format::Hex).is_err());
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path(Path::new("a.obj")), Some(Format::Obj));
+        assert_eq!(Format::from_path(Path::new("a.BIN")), Some(Format::Bin));
+        assert_eq!(
+            Format::from_path(Path::new("a.ihex")),
+            Some(Format::IntelHex)
+        );
+        assert_eq!(Format::from_path(Path::new("a.hex")), None);
+    }
+
+    #[test]
+    fn test_load_example() {
+        let image = super::read_file(Path::new("src/examples/hello-world.obj")).unwrap();
+        assert_eq!(image[0], 0x3000);
+        assert_eq!(image.len(), 17);
+    }
+}
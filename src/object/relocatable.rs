@@ -0,0 +1,260 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use crate::assembler::{parse_address, Program, Relocation, RelocationKind, Segment, SourceMap};
+
+// First line of every relocatable object
+pub(crate) const MAGIC: &str = "LC3REL";
+
+// Words written per line of a segment
+const WORDS_PER_LINE: usize = 8;
+
+// A text format listing the segments, then the symbols and relocations of a program:
+//
+//   LC3REL
+//   SEGMENT x3000 3
+//   E002 F022 0048
+//   GLOBAL MAIN
+//   EXTERNAL PRINT
+//   SYMBOL MAIN x3000
+//   RELOCATE x3001 PC11 PRINT
+//   RELOCATE x3002 ABS16 TABLE+2
+pub(crate) fn write(program: &Program) -> String {
+    let mut text = String::new();
+    writeln!(text, "{}", MAGIC).unwrap();
+
+    for segment in &program.segments {
+        writeln!(
+            text,
+            "SEGMENT x{:04X} {}",
+            segment.origin,
+            segment.words.len()
+        )
+        .unwrap();
+        for words in segment.words.chunks(WORDS_PER_LINE) {
+            let words = words
+                .iter()
+                .map(|word| format!("{:04X}", word))
+                .collect::<Vec<String>>();
+            writeln!(text, "{}", words.join(" ")).unwrap();
+        }
+    }
+    for label in &program.globals {
+        writeln!(text, "GLOBAL {}", label).unwrap();
+    }
+    for label in &program.externals {
+        writeln!(text, "EXTERNAL {}", label).unwrap();
+    }
+    for (label, address) in &program.symbols {
+        writeln!(text, "SYMBOL {} x{:04X}", label, address).unwrap();
+    }
+    for relocation in &program.relocations {
+        let kind = match relocation.kind {
+            RelocationKind::Absolute => "ABS16".to_string(),
+            RelocationKind::PcOffset(bit_count) => format!("PC{}", bit_count),
+        };
+        writeln!(
+            text,
+            "RELOCATE x{:04X} {} {}",
+            relocation.address,
+            kind,
+            relocation.reference()
+        )
+        .unwrap();
+    }
+
+    text
+}
+
+pub(crate) fn read(text: &str) -> Result<Program, String> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, line)) if line.trim() == MAGIC => {}
+        _ => return Err(format!("line 1: expected `{}`", MAGIC)),
+    }
+
+    let mut program = Program {
+        segments: vec![],
+        symbols: BTreeMap::new(),
+        lines: BTreeMap::new(),
+        globals: BTreeSet::new(),
+        externals: BTreeSet::new(),
+        relocations: vec![],
+        source_map: SourceMap::default(),
+    };
+    // words still expected by the last segment
+    let mut pending: usize = 0;
+
+    for (index, line) in lines {
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+        let address =
+            |word: &str| parse_address(word).ok_or(error(&format!("invalid address `{}`", word)));
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+
+        if pending > 0 {
+            let segment = program.segments.last_mut().unwrap();
+            for word in &words {
+                let word = u16::from_str_radix(word, 16)
+                    .map_err(|_| error(&format!("invalid word `{}`", word)))?;
+                segment.words.push(word);
+            }
+            pending = pending
+                .checked_sub(words.len())
+                .ok_or(error("more words than the segment holds"))?;
+            continue;
+        }
+
+        match words.as_slice() {
+            [] => {}
+            ["SEGMENT", origin, length] => {
+                let origin = address(origin)?;
+                pending = length
+                    .parse::<usize>()
+                    .map_err(|_| error(&format!("invalid length `{}`", length)))?;
+                if origin as usize + pending > u16::MAX as usize + 1 {
+                    return Err(error("segment does not fit in memory"));
+                }
+                program.segments.push(Segment {
+                    origin,
+                    words: Vec::with_capacity(pending),
+                });
+            }
+            ["GLOBAL", label] => {
+                program.globals.insert(label.to_string());
+            }
+            ["EXTERNAL", label] => {
+                program.externals.insert(label.to_string());
+            }
+            ["SYMBOL", label, value] => {
+                program.symbols.insert(label.to_string(), address(value)?);
+            }
+            ["RELOCATE", value, kind, reference] => {
+                let kind = match *kind {
+                    "ABS16" => RelocationKind::Absolute,
+                    "PC9" => RelocationKind::PcOffset(9),
+                    "PC11" => RelocationKind::PcOffset(11),
+                    _ => return Err(error(&format!("unknown relocation `{}`", kind))),
+                };
+                // `SYMBOL`, `SYMBOL+N` or `SYMBOL-N`
+                let (symbol, addend) = match reference.find(['+', '-']) {
+                    Some(index) => {
+                        let addend = reference[index..].parse::<i32>().map_err(|_| {
+                            error(&format!("invalid relocation target `{}`", reference))
+                        })?;
+                        (&reference[..index], addend)
+                    }
+                    None => (*reference, 0),
+                };
+                program.relocations.push(Relocation {
+                    address: address(value)?,
+                    kind,
+                    symbol: symbol.to_string(),
+                    addend,
+                });
+            }
+            _ => return Err(error("unknown record")),
+        }
+    }
+
+    if pending > 0 {
+        return Err(format!("Last segment is missing {} words", pending));
+    }
+    check(&program)?;
+    Ok(program)
+}
+
+// Every record must refer to something the object defines or declares
+fn check(program: &Program) -> Result<(), String> {
+    if let Some(label) = program
+        .globals
+        .iter()
+        .find(|label| !program.symbols.contains_key(*label))
+    {
+        return Err(format!("Global label `{}` is not defined", label));
+    }
+
+    for relocation in &program.relocations {
+        let known = program.symbols.contains_key(&relocation.symbol)
+            || program.externals.contains(&relocation.symbol);
+        if !known {
+            return Err(format!(
+                "Relocation at x{:04X} refers to unknown label `{}`",
+                relocation.address, relocation.symbol
+            ));
+        }
+        let inside = program.segments.iter().any(|segment| {
+            (segment.origin as usize..segment.end()).contains(&(relocation.address as usize))
+        });
+        if !inside {
+            return Err(format!(
+                "Relocation at x{:04X} is outside of every segment",
+                relocation.address
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::{assemble, RelocationKind};
+
+    use super::{read, write};
+
+    const MODULE: &str = "
+                .GLOBAL MAIN
+                .EXTERNAL PRINT
+                .ORIG x3000
+        MAIN    LEA R0, MSG
+                JSR PRINT
+                LD R1, COUNT
+                HALT
+                .END
+
+                .ORIG x4000
+        MSG     .STRINGZ \"Hi\"
+        COUNT   .FILL 3
+        TABLE   .FILL MSG
+                .FILL PRINT+1
+                .FILL MSG - 1
+                .END";
+
+    #[test]
+    fn test_relocatable_roundtrip() {
+        let program = assemble(MODULE).unwrap();
+        let text = write(&program);
+
+        assert!(text.starts_with("LC3REL\nSEGMENT x3000 4\n"));
+        assert!(text.contains("SEGMENT x4000 7\n0048 0069 0000 0003 4000 0000 3FFF\n"));
+        assert!(text.contains("RELOCATE x3001 PC11 PRINT\n"));
+        assert!(text.contains("RELOCATE x4005 ABS16 PRINT+1\n"));
+        assert!(text.contains("RELOCATE x4006 ABS16 MSG-1\n"));
+
+        let read_back = read(&text).unwrap();
+        assert_eq!(read_back.segments, program.segments);
+        assert_eq!(read_back.symbols, program.symbols);
+        assert_eq!(read_back.globals, program.globals);
+        assert_eq!(read_back.externals, program.externals);
+        assert_eq!(read_back.relocations, program.relocations);
+        assert_eq!(read_back.relocations[0].kind, RelocationKind::PcOffset(9));
+    }
+
+    #[test]
+    fn test_read_errors() {
+        assert!(read("").is_err());
+        assert!(read("LC3REL\nSEGMENT x3000 2\nF025\n").is_err());
+        assert!(read("LC3REL\nSEGMENT x3000 1\nF025 F025\n").is_err());
+        assert!(read("LC3REL\nGLOBAL MAIN\n").is_err());
+        assert_eq!(
+            read("LC3REL\nSEGMENT x3000 1\nF025\nRELOCATE x3000 ABS16 NOWHERE\n").unwrap_err(),
+            "Relocation at x3000 refers to unknown label `NOWHERE`"
+        );
+        assert!(
+            read("LC3REL\nSEGMENT x3000 1\nF025\nSYMBOL A x3000\nRELOCATE x3000 ABS16 A+B\n")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    assembler::{parse_address, parse_string},
+    batch,
+    grader::DEFAULT_MAX_INSTRUCTIONS,
+    vm::Vm,
+};
+
+// Words per MEMORY line of a golden file
+const WORDS_PER_LINE: usize = 8;
+
+/// A program run with scripted input, its result is compared to a golden file
+///
+/// Written as one record per line, `;` starts a comment:
+///
+/// ```text
+/// PROGRAM ../2048.obj          images to load, relative to the snapshot file
+/// INPUT "y\nwasd"              the keys, with the escapes of `.STRINGZ`
+/// MAX-INSTRUCTIONS 2000000     the run stops there at the latest
+/// MEMORY x3000 x30FF           a range of memory to record at the end, bounds included
+/// SCREEN 80 24                 the columns and rows of a terminal rendering the output
+/// ```
+///
+/// The golden file sits next to it with the `.golden` extension. It holds the result of a batch
+/// run, see [`batch::BatchResult`], then the screen, see [`batch::screen`], and the recorded
+/// memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// images loaded in order, execution starts at the origin of the first one
+    pub programs: Vec<PathBuf>,
+    /// what the keyboard reads
+    pub input: Vec<u8>,
+    /// the run stops there at the latest
+    pub max_instructions: u64,
+    /// first and last address of every range of memory recorded
+    pub memory: Vec<(u16, u16)>,
+    /// columns and rows of the terminal rendering the output, if any
+    pub screen: Option<(usize, usize)>,
+}
+
+/// How a snapshot compares to its golden file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The run gives the golden file
+    Matches,
+    /// The first difference, or that there is no golden file
+    Differs(String),
+    /// The golden file was written
+    Updated,
+}
+
+impl Snapshot {
+    /// Parses a snapshot, programs are relative to `directory`
+    pub fn parse(text: &str, directory: &Path) -> Result<Snapshot, String> {
+        let mut snapshot = Snapshot {
+            programs: vec![],
+            input: vec![],
+            max_instructions: DEFAULT_MAX_INSTRUCTIONS,
+            memory: vec![],
+            screen: None,
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            let address = |word: &str| {
+                parse_address(word).ok_or_else(|| error(format!("Invalid address `{}`", word)))
+            };
+
+            match (
+                keyword,
+                rest.split_whitespace().collect::<Vec<&str>>().as_slice(),
+            ) {
+                ("PROGRAM", [path]) => snapshot.programs.push(directory.join(path)),
+                ("INPUT", _) => {
+                    let literal = rest.strip_prefix('"').ok_or_else(|| {
+                        error(format!("Expected a quoted string, found `{}`", rest))
+                    })?;
+                    snapshot.input = parse_string(literal).map_err(error)?.into_bytes();
+                }
+                ("MAX-INSTRUCTIONS", [max]) => {
+                    snapshot.max_instructions = max
+                        .parse()
+                        .map_err(|_| error(format!("Invalid instruction limit `{}`", max)))?;
+                }
+                ("MEMORY", [start, end]) => {
+                    let (start, end) = (address(start)?, address(end)?);
+                    if start > end {
+                        return Err(error(format!("Empty range x{:04X} x{:04X}", start, end)));
+                    }
+                    snapshot.memory.push((start, end));
+                }
+                ("SCREEN", [columns, rows]) => {
+                    let size = |text: &str| {
+                        text.parse()
+                            .ok()
+                            .filter(|size| *size > 0)
+                            .ok_or_else(|| error(format!("Invalid screen size `{}`", text)))
+                    };
+                    snapshot.screen = Some((size(columns)?, size(rows)?));
+                }
+                _ => return Err(error(format!("Invalid record `{}`", line))),
+            }
+        }
+
+        if snapshot.programs.is_empty() {
+            return Err("No program".to_string());
+        }
+        Ok(snapshot)
+    }
+
+    /// Reads and parses a snapshot file, programs are relative to its directory
+    pub fn read(path: &Path) -> Result<Snapshot, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+        let directory = path.parent().unwrap_or(Path::new("."));
+        Snapshot::parse(&text, directory).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// Runs the program and renders what the golden file should hold
+    pub fn render(&self) -> Result<String, String> {
+        let mut vm = self
+            .programs
+            .iter()
+            .fold(Vm::builder(), |builder, path| builder.file(path))
+            .max_instructions(self.max_instructions)
+            .build()?;
+        let mut text = batch::run(&mut vm, &self.input).to_string();
+        if let Some((columns, rows)) = self.screen {
+            text.push_str(&batch::screen(vm.console().output(), columns, rows));
+        }
+
+        for (start, end) in &self.memory {
+            let addresses = (*start..=*end).collect::<Vec<u16>>();
+            for line in addresses.chunks(WORDS_PER_LINE) {
+                write!(text, "MEMORY x{:04X}", line[0]).unwrap();
+                for address in line {
+                    write!(text, " x{:04X}", vm.read_memory(*address)).unwrap();
+                }
+                text.push('\n');
+            }
+        }
+        Ok(text)
+    }
+}
+
+/// The golden file of a snapshot file
+pub fn golden_path(path: &Path) -> PathBuf {
+    path.with_extension("golden")
+}
+
+/// Runs the snapshot at `path` and compares it to its golden file, or rewrites the golden file
+/// when `update` is set
+pub fn check(path: &Path, update: bool) -> Result<Verdict, String> {
+    let actual = Snapshot::read(path)?.render()?;
+    let golden = golden_path(path);
+    if update {
+        fs::write(&golden, actual)
+            .map_err(|error| format!("Can't write {}: {}", golden.display(), error))?;
+        return Ok(Verdict::Updated);
+    }
+
+    let Ok(expected) = fs::read_to_string(&golden) else {
+        return Ok(Verdict::Differs(format!(
+            "no golden file {}, run with --update to write it",
+            golden.display()
+        )));
+    };
+    Ok(match first_difference(&expected, &actual) {
+        None => Verdict::Matches,
+        Some(difference) => Verdict::Differs(difference),
+    })
+}
+
+// The first line that differs, with both versions
+fn first_difference(expected: &str, actual: &str) -> Option<String> {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return None,
+            (expected, actual) if expected == actual => continue,
+            (expected, actual) => {
+                let show = |text: Option<&str>| text.unwrap_or("end of file").to_string();
+                return Some(format!(
+                    "line {}: expected\n  {}\ngot\n  {}",
+                    line,
+                    show(expected),
+                    show(actual)
+                ));
+            }
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::{check, first_difference, Snapshot, Verdict};
+
+    #[test]
+    fn test_parse() {
+        let snapshot = Snapshot::parse(
+            "; moves
+PROGRAM ../2048.obj
+INPUT \"y\\nwd\"
+MAX-INSTRUCTIONS 500
+MEMORY x3000 x3003
+SCREEN 80 24",
+            Path::new("examples/snapshots"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            snapshot.programs,
+            vec![Path::new("examples/snapshots/../2048.obj").to_path_buf()]
+        );
+        assert_eq!(snapshot.input, b"y\nwd");
+        assert_eq!(snapshot.max_instructions, 500);
+        assert_eq!(snapshot.memory, vec![(0x3000, 0x3003)]);
+        assert_eq!(snapshot.screen, Some((80, 24)));
+
+        let error = |text: &str| Snapshot::parse(text, Path::new(".")).unwrap_err();
+        assert_eq!(error("INPUT \"x\""), "No program");
+        assert_eq!(
+            error("PROGRAM a.obj\nINPUT x"),
+            "line 2: Expected a quoted string, found `x`"
+        );
+        assert_eq!(
+            error("PROGRAM a.obj\nMEMORY x3001 x3000"),
+            "line 2: Empty range x3001 x3000"
+        );
+        assert_eq!(
+            error("PROGRAM a.obj b.obj"),
+            "line 1: Invalid record `PROGRAM a.obj b.obj`"
+        );
+        assert_eq!(
+            error("PROGRAM a.obj\nSCREEN 0 24"),
+            "line 2: Invalid screen size `0`"
+        );
+    }
+
+    #[test]
+    fn test_first_difference() {
+        assert_eq!(first_difference("a\nb\n", "a\nb\n"), None);
+        assert_eq!(
+            first_difference("a\nb\n", "a\nc\nd\n"),
+            Some("line 2: expected\n  b\ngot\n  c".to_string())
+        );
+        assert_eq!(
+            first_difference("a\n", "a\nd\n"),
+            Some("line 2: expected\n  end of file\ngot\n  d".to_string())
+        );
+    }
+
+    // The snapshots of the bundled examples, update them with
+    // `cargo run -- snapshot src/examples/snapshots/*.snap --update`
+    #[test]
+    fn test_examples() {
+        let mut paths = fs::read_dir("src/examples/snapshots")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "snap")
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert!(!paths.is_empty());
+
+        for path in paths {
+            match check(&path, false).unwrap() {
+                Verdict::Matches => {}
+                verdict => panic!("{}: {:?}", path.display(), verdict),
+            }
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use std::fmt::Display;
+
+/// A color of the ANSI palette
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Color {
+    /// The color of the terminal, before any was set
+    #[default]
+    Default,
+    /// 0 to 7 for the normal colors (black, red, green, yellow, blue, magenta, cyan, white), 8 to
+    /// 15 for their bright versions, up to 255 for the 256-color palette
+    Indexed(u8),
+    /// Red, green and blue of a 24-bit color
+    Rgb(u8, u8, u8),
+}
+
+/// How a character is drawn, set by the `ESC[...m` sequences
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    /// color of the character
+    pub foreground: Color,
+    /// color of the cell behind it
+    pub background: Color,
+    /// bold or bright, `ESC[1m`
+    pub bold: bool,
+    /// underlined, `ESC[4m`
+    pub underline: bool,
+    /// foreground and background swapped, `ESC[7m`
+    pub reverse: bool,
+}
+
+/// A character of the screen with its style
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    /// a space for a blank cell
+    pub character: char,
+    /// the style the character was printed with
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            character: ' ',
+            style: Style::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    // the parameters and intermediate characters read so far
+    Csi(String),
+}
+
+/// A virtual terminal that renders console output on a grid of characters, so the screen of a
+/// program drawing with ANSI escape sequences can be checked without a real terminal
+///
+/// It follows the sequences of a VT100 as xterm understands them: cursor moves (`ESC[nA` to
+/// `ESC[nG`, `ESC[row;colH`), erasing the screen and lines (`ESC[nJ`, `ESC[nK`), colors and
+/// attributes (`ESC[...m`), saving the cursor (`ESC[s`, `ESC[u`, `ESC7`, `ESC8`), hiding it
+/// (`ESC[?25l`) and the reset `ESCc`. Other sequences are ignored. A newline also returns to the
+/// first column, as a terminal in cooked mode does, text wraps at the last column and the screen
+/// scrolls up at the bottom. There is no scrollback, `ESC[3J` does nothing.
+///
+/// ```
+/// use lc3_vm::terminal::{Color, Terminal};
+///
+/// let mut terminal = Terminal::new(20, 5);
+/// terminal.feed(b"old\x1B[2J\x1B[H\x1B[31mnew\x1B[0m\n");
+/// assert_eq!(terminal.line(0), "new");
+/// assert_eq!(terminal.cell(0, 0).style.foreground, Color::Indexed(1));
+/// assert_eq!(terminal.cursor(), (1, 0));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Terminal {
+    columns: usize,
+    rows: usize,
+    // row after row
+    cells: Vec<Cell>,
+    row: usize,
+    column: usize,
+    // the last column was written, the next character goes to the next line
+    wrap_pending: bool,
+    style: Style,
+    saved_cursor: (usize, usize),
+    cursor_visible: bool,
+    state: State,
+}
+
+impl Default for Terminal {
+    /// The usual 80 columns by 24 rows
+    fn default() -> Self {
+        Terminal::new(80, 24)
+    }
+}
+
+impl Terminal {
+    /// A blank screen, both sizes are at least 1
+    pub fn new(columns: usize, rows: usize) -> Self {
+        let (columns, rows) = (columns.max(1), rows.max(1));
+        Terminal {
+            columns,
+            rows,
+            cells: vec![Cell::default(); columns * rows],
+            row: 0,
+            column: 0,
+            wrap_pending: false,
+            style: Style::default(),
+            saved_cursor: (0, 0),
+            cursor_visible: true,
+            state: State::Ground,
+        }
+    }
+
+    /// Characters per row
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Rows of the screen
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Row and column of the cursor, from 0
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.column)
+    }
+
+    /// False once the program hid the cursor with `ESC[?25l`
+    pub fn cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    /// The cell at a row and a column, from 0, they must be on the screen
+    pub fn cell(&self, row: usize, column: usize) -> Cell {
+        self.cells[row * self.columns + column]
+    }
+
+    /// The characters of a row without the trailing spaces
+    pub fn line(&self, row: usize) -> String {
+        let cells = &self.cells[row * self.columns..(row + 1) * self.columns];
+        let line = cells.iter().map(|cell| cell.character).collect::<String>();
+        line.trim_end().to_string()
+    }
+
+    /// The rows up to the last one that isn't blank
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = (0..self.rows)
+            .map(|row| self.line(row))
+            .collect::<Vec<String>>();
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
+
+    /// Renders console output, a sequence can be split between calls
+    ///
+    /// The output is read as UTF-8, as the console writes it.
+    pub fn feed(&mut self, output: &[u8]) {
+        for c in String::from_utf8_lossy(output).chars() {
+            self.state = match std::mem::replace(&mut self.state, State::Ground) {
+                State::Ground => self.ground(c),
+                State::Escape => self.escape(c),
+                State::Csi(mut parameters) => match c {
+                    '\x40'..='\x7E' => {
+                        self.csi(&parameters, c);
+                        State::Ground
+                    }
+                    // a new sequence interrupts a broken one
+                    '\x1B' => State::Escape,
+                    c => {
+                        parameters.push(c);
+                        State::Csi(parameters)
+                    }
+                },
+            };
+        }
+    }
+
+    fn ground(&mut self, c: char) -> State {
+        match c {
+            '\x1B' => return State::Escape,
+            '\n' => {
+                self.move_to(self.row, 0);
+                self.line_feed();
+            }
+            '\r' => self.move_to(self.row, 0),
+            '\x08' => self.move_to(self.row, self.column.saturating_sub(1)),
+            '\t' => self.move_to(self.row, (self.column / 8 + 1) * 8),
+            c if c.is_control() => {}
+            c => self.print(c),
+        }
+        State::Ground
+    }
+
+    fn escape(&mut self, c: char) -> State {
+        match c {
+            '[' => return State::Csi(String::new()),
+            '7' => self.saved_cursor = (self.row, self.column),
+            '8' => self.move_to(self.saved_cursor.0, self.saved_cursor.1),
+            'c' => *self = Terminal::new(self.columns, self.rows),
+            _ => {}
+        }
+        State::Ground
+    }
+
+    fn csi(&mut self, parameters: &str, command: char) {
+        if let Some(mode) = parameters.strip_prefix('?') {
+            if mode == "25" {
+                match command {
+                    'h' => self.cursor_visible = true,
+                    'l' => self.cursor_visible = false,
+                    _ => {}
+                }
+            }
+            return;
+        }
+        let values = parameters
+            .split(';')
+            .map(|value| value.parse::<usize>().ok())
+            .collect::<Vec<Option<usize>>>();
+        // a count or a position, where 0 and a missing value mean 1
+        let count = |index: usize| values.get(index).copied().flatten().unwrap_or(1).max(1);
+        let mode = values[0].unwrap_or(0);
+
+        match command {
+            'A' => self.move_to(self.row.saturating_sub(count(0)), self.column),
+            'B' => self.move_to(self.row.saturating_add(count(0)), self.column),
+            'C' => self.move_to(self.row, self.column.saturating_add(count(0))),
+            'D' => self.move_to(self.row, self.column.saturating_sub(count(0))),
+            'E' => self.move_to(self.row.saturating_add(count(0)), 0),
+            'F' => self.move_to(self.row.saturating_sub(count(0)), 0),
+            'G' => self.move_to(self.row, count(0) - 1),
+            'H' | 'f' => self.move_to(count(0) - 1, count(1) - 1),
+            'J' => {
+                let cursor = self.row * self.columns + self.column;
+                match mode {
+                    0 => self.erase(cursor, self.cells.len()),
+                    1 => self.erase(0, cursor + 1),
+                    2 => self.erase(0, self.cells.len()),
+                    _ => {}
+                }
+            }
+            'K' => {
+                let start = self.row * self.columns;
+                let cursor = start + self.column;
+                match mode {
+                    0 => self.erase(cursor, start + self.columns),
+                    1 => self.erase(start, cursor + 1),
+                    2 => self.erase(start, start + self.columns),
+                    _ => {}
+                }
+            }
+            'm' => self.select_graphic_rendition(&values),
+            's' => self.saved_cursor = (self.row, self.column),
+            'u' => self.move_to(self.saved_cursor.0, self.saved_cursor.1),
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, values: &[Option<usize>]) {
+        let mut values = values.iter().map(|value| value.unwrap_or(0));
+        while let Some(value) = values.next() {
+            match value {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                4 => self.style.underline = true,
+                7 => self.style.reverse = true,
+                22 => self.style.bold = false,
+                24 => self.style.underline = false,
+                27 => self.style.reverse = false,
+                30..=37 => self.style.foreground = Color::Indexed(value as u8 - 30),
+                39 => self.style.foreground = Color::Default,
+                40..=47 => self.style.background = Color::Indexed(value as u8 - 40),
+                49 => self.style.background = Color::Default,
+                90..=97 => self.style.foreground = Color::Indexed(value as u8 - 90 + 8),
+                100..=107 => self.style.background = Color::Indexed(value as u8 - 100 + 8),
+                38 | 48 => {
+                    let color = match values.next() {
+                        Some(5) => values.next().map(|index| Color::Indexed(index as u8)),
+                        Some(2) => {
+                            let mut component = || values.next().unwrap_or(0) as u8;
+                            Some(Color::Rgb(component(), component(), component()))
+                        }
+                        _ => None,
+                    };
+                    if let Some(color) = color {
+                        match value {
+                            38 => self.style.foreground = color,
+                            _ => self.style.background = color,
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        if self.wrap_pending {
+            self.move_to(self.row, 0);
+            self.line_feed();
+        }
+        self.cells[self.row * self.columns + self.column] = Cell {
+            character: c,
+            style: self.style,
+        };
+        if self.column + 1 == self.columns {
+            self.wrap_pending = true;
+        } else {
+            self.column += 1;
+        }
+    }
+
+    fn line_feed(&mut self) {
+        if self.row + 1 == self.rows {
+            self.cells.drain(..self.columns);
+            self.cells
+                .extend(std::iter::repeat_n(Cell::default(), self.columns));
+        } else {
+            self.row += 1;
+        }
+    }
+
+    // Moves the cursor, clamped to the screen
+    fn move_to(&mut self, row: usize, column: usize) {
+        self.row = row.min(self.rows - 1);
+        self.column = column.min(self.columns - 1);
+        self.wrap_pending = false;
+    }
+
+    // Blanks the cells from `start` to `end` excluded
+    fn erase(&mut self, start: usize, end: usize) {
+        self.cells[start..end].fill(Cell::default());
+    }
+}
+
+// The rows up to the last one that isn't blank
+impl Display for Terminal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in self.lines() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::{RunOutcome, Vm};
+
+    use super::{Cell, Color, Style, Terminal};
+
+    #[test]
+    fn test_text() {
+        let mut terminal = Terminal::new(6, 3);
+        terminal.feed(b"ab\tc\nline\rL\x08\x08");
+        assert_eq!(terminal.lines(), vec!["ab   c", "Line"]);
+        assert_eq!(terminal.cursor(), (1, 0));
+
+        // wraps at the last column and scrolls at the bottom
+        terminal.feed(b"\n123456789\nend");
+        assert_eq!(terminal.lines(), vec!["123456", "789", "end"]);
+        assert_eq!(terminal.to_string(), "123456\n789\nend\n");
+
+        terminal.feed("é\x1B".as_bytes());
+        terminal.feed(b"[1;1H");
+        assert_eq!(terminal.line(2), "endé");
+        assert_eq!(terminal.cursor(), (0, 0));
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.feed(b"abcdefghij\x1B[3;5HX\x1B[2AY\x1B[10CZ\x1B[2GW");
+        assert_eq!(terminal.lines(), vec!["aWcdeYghiZ", "", "    X"]);
+        assert_eq!(terminal.cursor(), (0, 2));
+
+        terminal.feed(b"\x1B[s\x1B[3;1H\x1B[K\x1B[u\x1B[1K");
+        assert_eq!(terminal.lines(), vec!["   deYghiZ"]);
+        terminal.feed(b"\x1B[1;8H\x1B[0J");
+        assert_eq!(terminal.lines(), vec!["   deYg"]);
+        terminal.feed(b"\x1B[?25l\x1B[2J\x1B[3J\x1B[1;3H");
+        assert!(terminal.lines().is_empty());
+        assert!(!terminal.cursor_visible());
+
+        terminal.feed(
+            b"\x1B[1;31;44ma\x1B[22;39mb\x1B[0;92;48;5;200mc\x1B[38;2;1;2;3md\x1B[mnot\x1B[qe",
+        );
+        assert_eq!(
+            terminal.cell(0, 2).style,
+            Style {
+                foreground: Color::Indexed(1),
+                background: Color::Indexed(4),
+                bold: true,
+                ..Style::default()
+            }
+        );
+        assert_eq!(terminal.cell(0, 3).style.foreground, Color::Default);
+        assert_eq!(terminal.cell(0, 3).style.background, Color::Indexed(4));
+        assert_eq!(terminal.cell(0, 4).style.foreground, Color::Indexed(10));
+        assert_eq!(terminal.cell(0, 4).style.background, Color::Indexed(200));
+        assert_eq!(terminal.cell(0, 5).style.foreground, Color::Rgb(1, 2, 3));
+        assert_eq!(
+            terminal.cell(0, 6),
+            Cell {
+                character: 'n',
+                style: Style::default()
+            }
+        );
+        assert_eq!(terminal.line(0), "  abcdnote");
+
+        // counts larger than the screen stop at its edges
+        terminal.feed(b"\x1B[18446744073709551615C\x1B[18446744073709551615B");
+        assert_eq!(terminal.cursor(), (3, 9));
+        terminal.feed(b"\x1B[18446744073709551615E\x1B[18446744073709551615A");
+        assert_eq!(terminal.cursor(), (0, 0));
+
+        terminal.feed(b"\x1Bc");
+        assert_eq!(terminal, Terminal::new(10, 4));
+    }
+
+    #[test]
+    fn test_game_screen() {
+        let mut vm = Vm::builder()
+            .file("src/examples/2048.obj")
+            .input(b"y")
+            .max_instructions(20_000)
+            .build()
+            .unwrap();
+        assert_eq!(vm.run(), RunOutcome::StepLimitExceeded);
+
+        // the board is drawn again after clearing the screen, the question is gone
+        let mut terminal = Terminal::default();
+        terminal.feed(vm.console().output());
+        let lines = terminal.lines();
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[0], "+--------------------------+");
+        assert_eq!(lines[10], lines[0]);
+        let (row, column) = (1..10)
+            .flat_map(|row| (0..28).map(move |column| (row, column)))
+            .find(|&(row, column)| terminal.cell(row, column).character == '2')
+            .unwrap();
+        assert_eq!(
+            terminal.cell(row, column).style.foreground,
+            Color::Indexed(7)
+        );
+    }
+}
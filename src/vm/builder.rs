@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+
+use crate::object;
+
+use super::{Console, MemoryMap, Vm};
+
+/// Configures a [`Vm`] before it runs: the images to load, where to start, the instruction
+/// limit and what to record
+///
+/// ```
+/// use lc3_vm::{assemble, Register, RunOutcome, Vm};
+///
+/// let program = assemble(".ORIG x3000\nADD R1, R1, #7\nHALT\n.END").unwrap();
+/// let mut vm = Vm::builder()
+///     .image(program.to_image().unwrap())
+///     .max_instructions(1000)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(vm.run(), RunOutcome::Halted);
+/// assert_eq!(vm.register(Register::R1), 7);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct VmBuilder {
+    images: Vec<Vec<u16>>,
+    files: Vec<PathBuf>,
+    entry: Option<u16>,
+    max_instructions: Option<u64>,
+    profiler: bool,
+    coverage: bool,
+    checker: bool,
+    sanitizer: bool,
+    memory_map: Option<MemoryMap>,
+    input: Option<Vec<u8>>,
+}
+
+impl VmBuilder {
+    /// Adds an image, its origin followed by its words
+    pub fn image(mut self, image: Vec<u16>) -> Self {
+        self.images.push(image);
+        self
+    }
+
+    /// Adds an object file in any of the supported formats, it is read by [`build`](Self::build)
+    ///
+    /// A linked relocatable object adds one image per segment
+    pub fn file(mut self, path: impl AsRef<Path>) -> Self {
+        self.files.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Starts execution at `address` rather than at the origin of the first image
+    pub fn entry(mut self, address: u16) -> Self {
+        self.entry = Some(address);
+        self
+    }
+
+    /// Limits the number of instructions a single run may execute
+    pub fn max_instructions(mut self, max_instructions: u64) -> Self {
+        self.max_instructions = Some(max_instructions);
+        self
+    }
+
+    /// Counts executed instructions per address and per subroutine, see [`Vm::profiler`]
+    pub fn profiler(mut self, enabled: bool) -> Self {
+        self.profiler = enabled;
+        self
+    }
+
+    /// Records executed addresses and branch directions, see [`Vm::coverage`]
+    pub fn coverage(mut self, enabled: bool) -> Self {
+        self.coverage = enabled;
+        self
+    }
+
+    /// Checks the calling convention as the program runs, see [`Vm::checker`]
+    pub fn checker(mut self, enabled: bool) -> Self {
+        self.checker = enabled;
+        self
+    }
+
+    /// Reports reads of memory and registers that were neither loaded nor written, see
+    /// [`Vm::sanitizer`]
+    pub fn sanitizer(mut self, enabled: bool) -> Self {
+        self.sanitizer = enabled;
+        self
+    }
+
+    /// Stops the program when it accesses memory the map doesn't allow, loading the images
+    /// isn't checked, see [`Vm::set_memory_map`]
+    pub fn memory_map(mut self, memory_map: MemoryMap) -> Self {
+        self.memory_map = Some(memory_map);
+        self
+    }
+
+    /// Reads the program input from `input` and records its output rather than using the
+    /// terminal, see [`Console::Scripted`]
+    pub fn input(mut self, input: &[u8]) -> Self {
+        self.input = Some(input.to_vec());
+        self
+    }
+
+    /// Loads the images, files after the images given by [`image`](Self::image)
+    ///
+    /// Fails when a file can't be read or images overlap
+    pub fn build(self) -> Result<Vm, String> {
+        let mut images = self.images;
+        for path in &self.files {
+            images.extend(object::read_images(path)?);
+        }
+
+        let mut vm = Vm::initialize();
+        // the loaded words are initialized
+        if self.sanitizer {
+            vm.enable_sanitizer();
+        }
+        vm.load_programs(&images)?;
+        if let Some(entry) = self.entry {
+            vm.set_entry(entry);
+        }
+        vm.set_max_instructions(self.max_instructions);
+        // the profile starts at the entry point
+        if self.profiler {
+            vm.enable_profiler();
+        }
+        if self.coverage {
+            vm.enable_coverage();
+        }
+        if self.checker {
+            vm.enable_checker();
+        }
+        vm.set_memory_map(self.memory_map);
+        if let Some(input) = &self.input {
+            vm.set_console(Console::scripted(input));
+        }
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembler::assemble,
+        vm::{Register, RunOutcome, Vm},
+    };
+
+    #[test]
+    fn test_builder() {
+        let program = assemble(
+            ".ORIG x3000
+            ADD R1, R1, #1
+START       ADD R2, R2, #2
+LOOP        BR LOOP
+            .END",
+        )
+        .unwrap();
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .entry(0x3001)
+            .max_instructions(10)
+            .profiler(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(vm.register(Register::Pc), 0x3001);
+        assert_eq!(vm.run(), RunOutcome::StepLimitExceeded);
+        assert_eq!(vm.register(Register::R1), 0);
+        assert_eq!(vm.register(Register::R2), 2);
+        assert_eq!(vm.profiler().unwrap().hits(0x3002), 9);
+        assert!(vm.coverage().is_none());
+
+        assert!(Vm::builder()
+            .image(vec![0x3000, 1, 2])
+            .image(vec![0x3001, 3])
+            .build()
+            .is_err());
+        assert!(Vm::builder().file("missing.obj").build().is_err());
+    }
+
+    #[test]
+    fn test_reset() {
+        let program = assemble(".ORIG x3000\nADD R1, R1, #1\nHALT\n.END").unwrap();
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .coverage(true)
+            .build()
+            .unwrap();
+        vm.add_breakpoint(0x3001);
+        vm.set_register(Register::R5, 0x1234);
+        vm.write_memory(0x4000, 42);
+
+        assert_eq!(vm.run(), RunOutcome::Breakpoint(0x3001));
+        vm.reset();
+
+        assert_eq!(vm.register(Register::R5), 0);
+        assert_eq!(vm.register(Register::Pc), 0x3000);
+        assert_eq!(vm.read_memory(0x4000), 0);
+        assert_eq!(vm.read_memory(0x3000), 0);
+        assert_eq!(vm.counters().instructions, 0);
+        assert_eq!(vm.breakpoints().len(), 1);
+        assert_eq!(vm.coverage().unwrap().hits(0x3000), 0);
+    }
+}
@@ -0,0 +1,210 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::assembler::{DebugInfo, Gpr, Instruction};
+
+use super::profiler::{frame_name, location};
+
+// Lines of a backtrace, runaway recursion would print every frame otherwise
+const BACKTRACE_FRAMES: usize = 64;
+
+/// A subroutine call that has not returned yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Address of the subroutine
+    pub entry: u16,
+    /// Address the subroutine should return to, the one after the JSR
+    pub return_address: u16,
+}
+
+/// The subroutines the program is in, as seen from its JSR, JSRR and RET instructions
+///
+/// A RET pops the innermost call returning to its target together with the calls above it, so
+/// a subroutine leaving several levels at once is followed. Other RETs, e.g. a JMP R7 used as
+/// a plain jump, leave the stack alone. The traps run natively and RTI is illegal, so neither
+/// adds a frame.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallStack {
+    // outermost first
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    /// The calls in progress, innermost first
+    pub fn frames(&self) -> Vec<Frame> {
+        self.frames.iter().rev().copied().collect()
+    }
+
+    /// Number of calls in progress
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    // A call made from outside the program, e.g. by `Vm::call`
+    pub(crate) fn enter(&mut self, entry: u16, return_address: u16) {
+        self.frames.push(Frame {
+            entry,
+            return_address,
+        });
+    }
+
+    // Records an executed instruction, `next_pc` and `r7` are their values after it executed
+    pub(crate) fn record(&mut self, instruction: &Instruction, next_pc: u16, r7: u16) {
+        match instruction {
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. } => self.enter(next_pc, r7),
+            Instruction::Jmp { base_r: Gpr::R7 } => {
+                if let Some(index) = self
+                    .frames
+                    .iter()
+                    .rposition(|frame| frame.return_address == next_pc)
+                {
+                    self.frames.truncate(index);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// One line per frame, innermost first: the PC then every call site, each with its label,
+    /// its source location when the debug info is given and the subroutine it is in
+    ///
+    /// Deep stacks are cut after 64 lines.
+    ///
+    /// ```text
+    /// #0 x3012 SQUARE+2 (prog.asm:14:5) in SQUARE
+    /// #1 x3003 LOOP+1 (prog.asm:5:5)
+    /// ```
+    pub fn backtrace(
+        &self,
+        pc: u16,
+        debug_info: Option<&DebugInfo>,
+        symbols: &BTreeMap<String, u16>,
+    ) -> String {
+        let describe = |address: u16| match debug_info {
+            Some(debug_info) => debug_info.describe(address),
+            None => match location(address, symbols) {
+                label if label.is_empty() => format!("x{:04X}", address),
+                label => format!("x{:04X} {}", address, label),
+            },
+        };
+        let name = |entry: u16| {
+            debug_info
+                .and_then(|debug_info| debug_info.label(entry))
+                .unwrap_or_else(|| frame_name(entry, symbols))
+        };
+
+        let call_sites = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| frame.return_address.wrapping_sub(1));
+        let mut text = String::new();
+        let addresses = std::iter::once(pc).chain(call_sites);
+        for (index, address) in addresses.enumerate().take(BACKTRACE_FRAMES) {
+            write!(text, "#{} {}", index, describe(address)).unwrap();
+            // the subroutine the address is in, the outermost code isn't one
+            if let Some(frame) = self.frames.iter().rev().nth(index) {
+                write!(text, " in {}", name(frame.entry)).unwrap();
+            }
+            text.push('\n');
+        }
+        if self.frames.len() >= BACKTRACE_FRAMES {
+            let hidden = self.frames.len() + 1 - BACKTRACE_FRAMES;
+            writeln!(text, "... {} more", hidden).unwrap();
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        assembler::{assemble, DebugInfo},
+        vm::{RunOutcome, Vm},
+    };
+
+    use super::Frame;
+
+    #[test]
+    fn test_call_stack() {
+        let source = ".ORIG x3000
+            LD R6, STACK
+            JSR OUTER
+            HALT
+OUTER       ADD R6, R6, #-1
+            STR R7, R6, #0
+            JSR INNER
+            LDR R7, R6, #0
+            ADD R6, R6, #1
+            RET
+INNER       ST R7, SAVE
+            LEA R2, LEAF
+            JSRR R2
+            LD R7, SAVE
+            RET
+LEAF        RET
+SAVE        .BLKW 1
+STACK       .FILL xFE00
+            .END";
+        let program = assemble(source).unwrap();
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .build()
+            .unwrap();
+        vm.add_breakpoint(0x300E);
+
+        assert_eq!(vm.run(), RunOutcome::Breakpoint(0x300E));
+        assert_eq!(
+            vm.call_stack().frames(),
+            vec![
+                Frame {
+                    entry: 0x300E,
+                    return_address: 0x300C
+                },
+                Frame {
+                    entry: 0x3009,
+                    return_address: 0x3006
+                },
+                Frame {
+                    entry: 0x3003,
+                    return_address: 0x3002
+                },
+            ]
+        );
+
+        // LEAF returns to INNER, then INNER to OUTER
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.call_stack().depth(), 2);
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.call_stack().depth(), 1);
+
+        let debug_info = DebugInfo::new("main.asm", &program);
+        assert_eq!(
+            vm.backtrace(Some(&debug_info), &BTreeMap::new()),
+            "#0 x3006 OUTER+3 (main.asm:8:13) in OUTER
+#1 x3001 (main.asm:3:13)
+"
+        );
+        let symbols = BTreeMap::from([("MAIN".to_string(), 0x3000), ("OUTER".to_string(), 0x3003)]);
+        assert_eq!(
+            vm.backtrace(None, &symbols),
+            "#0 x3006 OUTER+3 in OUTER\n#1 x3001 MAIN+1\n"
+        );
+
+        assert_eq!(vm.resume(), RunOutcome::Halted);
+        assert_eq!(vm.call_stack().depth(), 0);
+
+        let program = assemble(".ORIG x3000\nLOOP JSR LOOP\n.END").unwrap();
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .max_instructions(100)
+            .build()
+            .unwrap();
+        assert_eq!(vm.run(), RunOutcome::StepLimitExceeded);
+        let backtrace = vm.backtrace(None, &BTreeMap::new());
+        assert_eq!(backtrace.lines().count(), 65);
+        assert!(backtrace.ends_with("#63 x3000 in x3000\n... 37 more\n"));
+    }
+}
@@ -0,0 +1,460 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::assembler::{Gpr, Instruction, Operand};
+
+use super::{profiler::frame_name, Frame, Register};
+
+/// A breach of the calling convention
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// RET executed while no subroutine was called
+    UnmatchedReturn,
+    /// RET to another address than the one after the JSR
+    WrongReturnAddress {
+        /// where the RET jumped
+        target: u16,
+        /// the address after the JSR
+        expected: u16,
+    },
+    /// R6 on return differs from R6 at the call
+    StackImbalance {
+        /// R6 on return
+        sp: u16,
+        /// R6 at the call
+        expected: u16,
+    },
+    /// R7 was overwritten before the return address it held was stored or copied
+    ReturnAddressOverwritten {
+        /// the address R7 held
+        return_address: u16,
+    },
+    /// A store to the free part of the stack, below R6
+    WriteBelowStack {
+        /// the address written
+        address: u16,
+        /// R6 at the time
+        sp: u16,
+    },
+}
+
+impl std::fmt::Display for ViolationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ViolationKind::UnmatchedReturn => f.write_str("RET without a matching JSR"),
+            ViolationKind::WrongReturnAddress { target, expected } => write!(
+                f,
+                "RET to x{:04X}, the call returns to x{:04X}",
+                target, expected
+            ),
+            ViolationKind::StackImbalance { sp, expected } => write!(
+                f,
+                "R6 is x{:04X} on return, it was x{:04X} at the call",
+                sp, expected
+            ),
+            ViolationKind::ReturnAddressOverwritten { return_address } => write!(
+                f,
+                "R7 overwritten before the return address x{:04X} was saved",
+                return_address
+            ),
+            ViolationKind::WriteBelowStack { address, sp } => write!(
+                f,
+                "write to x{:04X} below the stack pointer x{:04X}",
+                address, sp
+            ),
+        }
+    }
+}
+
+/// A violation, where it happened and the calls in progress, innermost first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// address of the instruction
+    pub pc: u16,
+    /// what was breached
+    pub kind: ViolationKind,
+    /// the calls in progress, innermost first
+    pub call_stack: Vec<Frame>,
+}
+
+// Per call state, what the convention is checked against
+#[derive(Debug, Clone)]
+struct Call {
+    frame: Frame,
+    sp: u16,
+    // the return address was stored to memory or copied to another register
+    saved: bool,
+}
+
+/// Checks the calling convention while the program runs: every JSR is matched by a RET to the
+/// address after it with R6 back to its value at the call, R7 isn't overwritten before the
+/// return address is saved and nothing is stored below the stack pointer
+///
+/// Each kind of violation is only reported once per address.
+#[derive(Debug, Clone)]
+pub struct Checker {
+    calls: Vec<Call>,
+    // lowest R6 since it was last set rather than adjusted, the stack below R6 down to it
+    // was popped
+    stack_low: u16,
+    // R6 after the last instruction, to notice R6 being set from outside
+    sp: u16,
+    violations: Vec<Violation>,
+}
+
+impl Checker {
+    pub(crate) fn new(sp: u16) -> Self {
+        Checker {
+            calls: vec![],
+            stack_low: sp,
+            sp,
+            violations: vec![],
+        }
+    }
+
+    /// Violations in the order they happened
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// The calls in progress, innermost first
+    pub fn call_stack(&self) -> Vec<Frame> {
+        self.calls.iter().rev().map(|call| call.frame).collect()
+    }
+
+    // A call made from outside the program, e.g. by `Vm::call`
+    pub(crate) fn enter(&mut self, entry: u16, return_address: u16, sp: u16) {
+        self.calls.push(Call {
+            frame: Frame {
+                entry,
+                return_address,
+            },
+            sp,
+            saved: false,
+        });
+    }
+
+    // Records an executed instruction, `before` and `after` are the registers around it and
+    // `memory` is read for the pointer of STI
+    pub(crate) fn record(
+        &mut self,
+        pc: u16,
+        instruction: &Instruction,
+        before: &[u16],
+        after: &[u16],
+        memory: &[u16],
+    ) {
+        let sp = before[Register::R6 as usize];
+        let r7 = before[Register::R7 as usize];
+        if sp != self.sp {
+            self.stack_low = sp;
+        }
+
+        let store = match *instruction {
+            Instruction::St { sr, offset } => {
+                Some((sr, pc.wrapping_add(1).wrapping_add(offset as u16), false))
+            }
+            Instruction::Sti { sr, offset } => {
+                let pointer = pc.wrapping_add(1).wrapping_add(offset as u16);
+                Some((sr, memory[pointer as usize], false))
+            }
+            Instruction::Str { sr, base_r, offset } => Some((
+                sr,
+                before[base_r.number() as usize].wrapping_add(offset as u16),
+                base_r == Gpr::R6 && offset < 0,
+            )),
+            _ => None,
+        };
+        if let Some((sr, address, relative_below)) = store {
+            if relative_below || (self.stack_low..sp).contains(&address) {
+                self.report(pc, ViolationKind::WriteBelowStack { address, sp });
+            }
+            if sr == Gpr::R7 {
+                self.return_address_saved(r7);
+            }
+        }
+
+        if let Instruction::Add {
+            dr,
+            sr1: Gpr::R7,
+            operand: Operand::Imm(0),
+        } = *instruction
+        {
+            if dr != Gpr::R7 {
+                self.return_address_saved(r7);
+            }
+        }
+
+        let overwrites_r7 = match *instruction {
+            Instruction::Add { dr, .. }
+            | Instruction::And { dr, .. }
+            | Instruction::Not { dr, .. }
+            | Instruction::Ld { dr, .. }
+            | Instruction::Ldi { dr, .. }
+            | Instruction::Ldr { dr, .. }
+            | Instruction::Lea { dr, .. } => dr == Gpr::R7,
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. } => true,
+            _ => false,
+        };
+        if overwrites_r7 && after[Register::R7 as usize] != r7 {
+            if let Some(call) = self.calls.last_mut() {
+                if !call.saved && call.frame.return_address == r7 {
+                    // reported once per call
+                    call.saved = true;
+                    self.report(
+                        pc,
+                        ViolationKind::ReturnAddressOverwritten { return_address: r7 },
+                    );
+                }
+            }
+        }
+
+        match *instruction {
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. } => self.enter(
+                after[Register::Pc as usize],
+                after[Register::R7 as usize],
+                sp,
+            ),
+            Instruction::Jmp { base_r: Gpr::R7 } => match self.calls.last() {
+                None => self.report(pc, ViolationKind::UnmatchedReturn),
+                Some(call) => {
+                    let (expected, expected_sp) = (call.frame.return_address, call.sp);
+                    if r7 != expected {
+                        self.report(
+                            pc,
+                            ViolationKind::WrongReturnAddress {
+                                target: r7,
+                                expected,
+                            },
+                        );
+                    }
+                    if sp != expected_sp {
+                        self.report(
+                            pc,
+                            ViolationKind::StackImbalance {
+                                sp,
+                                expected: expected_sp,
+                            },
+                        );
+                    }
+                    self.calls.pop();
+                }
+            },
+            _ => {}
+        }
+
+        let new_sp = after[Register::R6 as usize];
+        match *instruction {
+            Instruction::Add {
+                dr: Gpr::R6,
+                sr1: Gpr::R6,
+                operand: Operand::Imm(_),
+            } => self.stack_low = self.stack_low.min(new_sp),
+            _ if new_sp != sp => self.stack_low = new_sp,
+            _ => {}
+        }
+        self.sp = new_sp;
+    }
+
+    fn return_address_saved(&mut self, r7: u16) {
+        if let Some(call) = self.calls.last_mut() {
+            if call.frame.return_address == r7 {
+                call.saved = true;
+            }
+        }
+    }
+
+    fn report(&mut self, pc: u16, kind: ViolationKind) {
+        let seen = self.violations.iter().any(|violation| {
+            violation.pc == pc
+                && std::mem::discriminant(&violation.kind) == std::mem::discriminant(&kind)
+        });
+        if !seen {
+            let call_stack = self.call_stack();
+            self.violations.push(Violation {
+                pc,
+                kind,
+                call_stack,
+            });
+        }
+    }
+
+    /// One paragraph per violation: the PC and what happened, then the calls in progress
+    pub fn report_text(&self, symbols: &BTreeMap<String, u16>) -> String {
+        let mut text = String::new();
+        for violation in &self.violations {
+            writeln!(text, "x{:04X}: {}", violation.pc, violation.kind).unwrap();
+            for frame in &violation.call_stack {
+                writeln!(
+                    text,
+                    "    in {} called from x{:04X}",
+                    frame_name(frame.entry, symbols),
+                    frame.return_address.wrapping_sub(1)
+                )
+                .unwrap();
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        assembler::assemble,
+        vm::{RunOutcome, Vm},
+    };
+
+    use super::{Frame, ViolationKind};
+
+    fn violations(source: &str) -> (Vm, Vec<(u16, ViolationKind)>) {
+        let program = assemble(source).unwrap();
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .max_instructions(1000)
+            .checker(true)
+            .build()
+            .unwrap();
+        assert_eq!(vm.run(), RunOutcome::Halted);
+        let violations = vm
+            .checker()
+            .unwrap()
+            .violations()
+            .iter()
+            .map(|violation| (violation.pc, violation.kind))
+            .collect();
+        (vm, violations)
+    }
+
+    #[test]
+    fn test_clean_program() {
+        let (_, violations) = violations(
+            ".ORIG x3000
+            LD R6, STACK
+            JSR OUTER
+            HALT
+OUTER       ADD R6, R6, #-1
+            STR R7, R6, #0
+            JSR INNER
+            LDR R7, R6, #0
+            ADD R6, R6, #1
+            RET
+INNER       PUSH R1
+            ADD R1, R1, #1
+            POP R1
+            RET
+STACK       .FILL xFE00
+            .END",
+        );
+
+        assert_eq!(violations, vec![]);
+    }
+
+    #[test]
+    fn test_violations() {
+        let (vm, violations) = violations(
+            ".ORIG x3000
+            LD R6, STACK
+            JSR CLOBBER
+            JSR OUTER
+            STI R0, POPPED
+            JSR UNBALANCED
+            JSR WRONG
+            HALT
+            LD R7, BACK
+            RET
+BACK        .FILL x3006
+CLOBBER     AND R7, R7, #0
+            LD R7, RETURN
+            RET
+RETURN      .FILL x3002
+OUTER       ADD R6, R6, #-1
+            STR R7, R6, #0
+            JSR INNER
+            LDR R7, R6, #0
+            ADD R6, R6, #1
+            RET
+INNER       STR R0, R6, #-1
+            RET
+UNBALANCED  ADD R6, R6, #-1
+            RET
+WRONG       ADD R7, R7, #1
+            RET
+STACK       .FILL xFE00
+POPPED      .FILL xFDFF
+            .END",
+        );
+
+        assert_eq!(
+            violations,
+            vec![
+                (
+                    0x300A,
+                    ViolationKind::ReturnAddressOverwritten {
+                        return_address: 0x3002
+                    }
+                ),
+                (
+                    0x3014,
+                    ViolationKind::WriteBelowStack {
+                        address: 0xFDFE,
+                        sp: 0xFDFF
+                    }
+                ),
+                (
+                    0x3003,
+                    ViolationKind::WriteBelowStack {
+                        address: 0xFDFF,
+                        sp: 0xFE00
+                    }
+                ),
+                (
+                    0x3017,
+                    ViolationKind::StackImbalance {
+                        sp: 0xFDFF,
+                        expected: 0xFE00
+                    }
+                ),
+                (
+                    0x3018,
+                    ViolationKind::ReturnAddressOverwritten {
+                        return_address: 0x3006
+                    }
+                ),
+                (
+                    0x3019,
+                    ViolationKind::WrongReturnAddress {
+                        target: 0x3007,
+                        expected: 0x3006
+                    }
+                ),
+                (0x3008, ViolationKind::UnmatchedReturn),
+            ]
+        );
+
+        let checker = vm.checker().unwrap();
+        assert_eq!(
+            checker.violations()[1].call_stack,
+            vec![
+                Frame {
+                    entry: 0x3014,
+                    return_address: 0x3011
+                },
+                Frame {
+                    entry: 0x300E,
+                    return_address: 0x3003
+                }
+            ]
+        );
+        let symbols = BTreeMap::from([("INNER".to_string(), 0x3014)]);
+        assert!(checker.report_text(&symbols).starts_with(
+            "x300A: R7 overwritten before the return address x3002 was saved
+    in x300A called from x3001
+x3014: write to xFDFE below the stack pointer xFDFF
+    in INNER called from x3010
+    in x300E called from x3002
+"
+        ));
+    }
+}
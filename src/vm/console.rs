@@ -0,0 +1,123 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+};
+
+/// Where the traps and the keyboard registers read input and write output
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Console {
+    /// stdin and stdout of the process
+    #[default]
+    Terminal,
+    /// input given up front and output kept in memory, e.g. to test a program
+    Scripted {
+        /// the characters not read yet
+        input: VecDeque<u8>,
+        /// everything written so far
+        output: Vec<u8>,
+    },
+}
+
+impl Console {
+    /// A console reading `input` and recording the output
+    pub fn scripted(input: &[u8]) -> Self {
+        Console::Scripted {
+            input: input.iter().copied().collect(),
+            output: vec![],
+        }
+    }
+
+    // The next input character, None once the input is exhausted
+    pub(crate) fn read(&mut self) -> Option<u8> {
+        match self {
+            Console::Terminal => {
+                let mut buffer = [0; 1];
+                match io::stdin().read(&mut buffer).unwrap() {
+                    0 => None,
+                    _ => Some(buffer[0]),
+                }
+            }
+            Console::Scripted { input, .. } => input.pop_front(),
+        }
+    }
+
+    pub(crate) fn write(&mut self, text: &str) {
+        match self {
+            Console::Terminal => print!("{}", text),
+            Console::Scripted { output, .. } => output.extend(text.as_bytes()),
+        }
+    }
+
+    /// What the program wrote so far, empty for the terminal
+    pub fn output(&self) -> &[u8] {
+        match self {
+            Console::Terminal => &[],
+            Console::Scripted { output, .. } => output,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembler::assemble,
+        vm::{RunOutcome, Vm},
+    };
+
+    #[test]
+    fn test_scripted_console() {
+        let program = assemble(
+            ".ORIG x3000
+            LEA R0, PROMPT
+            PUTS
+LOOP        GETC
+            ADD R1, R0, #-10
+            BRz DONE
+            OUT
+            BR LOOP
+DONE        HALT
+PROMPT      .STRINGZ \"> \"
+            .END",
+        )
+        .unwrap();
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .input(b"abc\nd")
+            .build()
+            .unwrap();
+
+        vm.run();
+        assert_eq!(vm.console().output(), b"> abc");
+
+        // the keyboard registers read the same input
+        assert_eq!(vm.mem_read(0xFE00), 1 << 15);
+        assert_eq!(vm.mem_read(0xFE02), 'd' as u16);
+        assert_eq!(vm.mem_read(0xFE00), 0);
+    }
+
+    #[test]
+    fn test_display_registers() {
+        let program = assemble(
+            ".ORIG x3000
+            LD R1, CHAR
+WAIT        LDI R2, DSR
+            BRzp WAIT
+            STI R1, DDR
+            HALT
+CHAR        .FILL x21
+DSR         .FILL xFE04
+DDR         .FILL xFE06
+            .END",
+        )
+        .unwrap();
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .input(b"")
+            .max_instructions(100)
+            .build()
+            .unwrap();
+
+        assert_eq!(vm.run(), RunOutcome::Halted);
+        assert_eq!(vm.console().output(), b"!");
+    }
+}
@@ -0,0 +1,66 @@
+use super::opcodes::Opcodes;
+
+// Cycle model loosely following the LC-3 state machine: every state takes
+// one cycle and every memory access waits for MEMORY_ACCESS_CYCLES more
+const MEMORY_ACCESS_CYCLES: u64 = 5;
+
+// Fetch (3 states) and decode (1 state), including the instruction read
+const FETCH_STATES: u64 = 4;
+
+/// Counters of a single run of the vm
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counters {
+    /// instructions executed
+    pub instructions: u64,
+    /// modeled cycles, from the fetch to the completion of every instruction
+    pub cycles: u64,
+}
+
+impl Counters {
+    pub(crate) fn record(&mut self, opcode: Opcodes) {
+        self.instructions += 1;
+        self.cycles += cycles(opcode);
+    }
+}
+
+/// Modeled number of cycles an instruction takes, from fetch to completion
+pub(crate) fn cycles(opcode: Opcodes) -> u64 {
+    // (execute states, memory accesses after the fetch)
+    let (states, memory_accesses) = match opcode {
+        Opcodes::Add | Opcodes::And | Opcodes::Not | Opcodes::Lea => (1, 0),
+        Opcodes::Br | Opcodes::Jmp | Opcodes::Res => (1, 0),
+        Opcodes::Jsr => (2, 0),
+        Opcodes::Ld | Opcodes::Ldr | Opcodes::St | Opcodes::Str => (3, 1),
+        Opcodes::Ldi | Opcodes::Sti => (5, 2),
+        // reads the trap vector table
+        Opcodes::Trap => (3, 1),
+        // pops the PC and PSR off the supervisor stack
+        Opcodes::Rti => (8, 2),
+    };
+
+    FETCH_STATES + states + (1 + memory_accesses) * MEMORY_ACCESS_CYCLES
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::opcodes::Opcodes;
+
+    use super::{cycles, Counters};
+
+    #[test]
+    fn test_memory_accesses_cost_more() {
+        assert!(cycles(Opcodes::Ld) > cycles(Opcodes::Add));
+        assert!(cycles(Opcodes::Ldi) > cycles(Opcodes::Ld));
+        assert_eq!(cycles(Opcodes::Ldr), cycles(Opcodes::Ld));
+    }
+
+    #[test]
+    fn test_record() {
+        let mut counters = Counters::default();
+        counters.record(Opcodes::Add);
+        counters.record(Opcodes::Sti);
+
+        assert_eq!(counters.instructions, 2);
+        assert_eq!(counters.cycles, cycles(Opcodes::Add) + cycles(Opcodes::Sti));
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write},
+};
+
+use crate::assembler::DebugInfo;
+
+/// Records which addresses were executed and which way conditional branches went
+#[derive(Debug)]
+pub struct Coverage {
+    hits: Vec<u64>,
+    // (taken, not taken) for every conditional branch that was executed
+    branches: BTreeMap<u16, (u64, u64)>,
+}
+
+/// Line and branch totals of a coverage report
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CoverageSummary {
+    /// source lines holding an instruction
+    pub lines_found: usize,
+    /// those executed at least once
+    pub lines_hit: usize,
+    /// two per conditional branch, taken and not taken
+    pub branches_found: usize,
+    /// directions followed at least once
+    pub branches_hit: usize,
+}
+
+impl Display for CoverageSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let percent = |hit: usize, found: usize| hit as f64 * 100.0 / found.max(1) as f64;
+        write!(
+            f,
+            "lines: {}/{} ({:.1}%), branches: {}/{} ({:.1}%)",
+            self.lines_hit,
+            self.lines_found,
+            percent(self.lines_hit, self.lines_found),
+            self.branches_hit,
+            self.branches_found,
+            percent(self.branches_hit, self.branches_found)
+        )
+    }
+}
+
+impl Coverage {
+    pub(crate) fn new() -> Self {
+        Coverage {
+            hits: vec![0; 1 << 16],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, address: u16) {
+        self.hits[address as usize] += 1;
+    }
+
+    pub(crate) fn record_branch(&mut self, address: u16, taken: bool) {
+        let directions = self.branches.entry(address).or_default();
+        if taken {
+            directions.0 += 1;
+        } else {
+            directions.1 += 1;
+        }
+    }
+
+    /// How many times the instruction at `address` was executed
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits[address as usize]
+    }
+
+    /// How many times the branch at `address` was (taken, not taken), None if never executed
+    pub fn branch(&self, address: u16) -> Option<(u64, u64)> {
+        self.branches.get(&address).copied()
+    }
+
+    // Execution count of every source line that holds an instruction
+    fn line_hits(&self, debug_info: &DebugInfo) -> BTreeMap<usize, u64> {
+        let mut lines = BTreeMap::new();
+        for (address, line) in &debug_info.lines {
+            let count = lines.entry(*line).or_default();
+            *count = self.hits(*address).max(*count);
+        }
+        lines
+    }
+
+    /// The line and branch totals, `conditional_branches` lists the addresses of the BR
+    /// instructions that can go either way
+    pub fn summary(&self, debug_info: &DebugInfo, conditional_branches: &[u16]) -> CoverageSummary {
+        let lines = self.line_hits(debug_info);
+        let mut summary = CoverageSummary {
+            lines_found: lines.len(),
+            lines_hit: lines.values().filter(|count| **count > 0).count(),
+            branches_found: conditional_branches.len() * 2,
+            branches_hit: 0,
+        };
+
+        for address in conditional_branches {
+            if let Some((taken, not_taken)) = self.branch(*address) {
+                summary.branches_hit += (taken > 0) as usize + (not_taken > 0) as usize;
+            }
+        }
+
+        summary
+    }
+
+    /// Coverage in the lcov tracefile format, see [`summary`](Coverage::summary) for
+    /// `conditional_branches`
+    pub fn lcov(&self, debug_info: &DebugInfo, conditional_branches: &[u16]) -> String {
+        let mut lcov = String::new();
+        writeln!(lcov, "TN:").unwrap();
+        writeln!(lcov, "SF:{}", debug_info.source).unwrap();
+
+        for address in conditional_branches {
+            let line = match debug_info.line(*address) {
+                Some(line) => line,
+                None => continue,
+            };
+            match self.branch(*address) {
+                Some((taken, not_taken)) => {
+                    writeln!(lcov, "BRDA:{},{},0,{}", line, address, taken).unwrap();
+                    writeln!(lcov, "BRDA:{},{},1,{}", line, address, not_taken).unwrap();
+                }
+                None => {
+                    writeln!(lcov, "BRDA:{},{},0,-", line, address).unwrap();
+                    writeln!(lcov, "BRDA:{},{},1,-", line, address).unwrap();
+                }
+            }
+        }
+
+        let summary = self.summary(debug_info, conditional_branches);
+        writeln!(lcov, "BRF:{}", summary.branches_found).unwrap();
+        writeln!(lcov, "BRH:{}", summary.branches_hit).unwrap();
+
+        for (line, count) in self.line_hits(debug_info) {
+            writeln!(lcov, "DA:{},{}", line, count).unwrap();
+        }
+        writeln!(lcov, "LF:{}", summary.lines_found).unwrap();
+        writeln!(lcov, "LH:{}", summary.lines_hit).unwrap();
+        writeln!(lcov, "end_of_record").unwrap();
+
+        lcov
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembler::{assemble, DebugInfo},
+        vm::Vm,
+    };
+
+    const PROGRAM: &str = ".ORIG x3000
+            AND R1, R1, #0
+            ADD R1, R1, #2
+    LOOP    ADD R1, R1, #-1
+            BRp LOOP
+            BRn NEVER
+            HALT
+    NEVER   ADD R2, R2, #1
+            HALT
+            .END";
+
+    fn run_with_coverage() -> (DebugInfo, Vm) {
+        let program = assemble(PROGRAM).unwrap();
+        let mut vm = Vm::initialize();
+        vm.load_program(program.to_image().unwrap()).unwrap();
+        vm.enable_coverage();
+        vm.run();
+        (DebugInfo::new("loop.asm", &program), vm)
+    }
+
+    #[test]
+    fn test_coverage_addresses_and_branches() {
+        let (_, vm) = run_with_coverage();
+        let coverage = vm.coverage().unwrap();
+
+        assert_eq!(coverage.hits(0x3002), 2);
+        assert_eq!(coverage.hits(0x3005), 1);
+        assert_eq!(coverage.hits(0x3006), 0);
+        assert_eq!(coverage.branch(0x3003), Some((1, 1)));
+        assert_eq!(coverage.branch(0x3004), Some((0, 1)));
+    }
+
+    #[test]
+    fn test_lcov_report() {
+        let (debug_info, vm) = run_with_coverage();
+        let coverage = vm.coverage().unwrap();
+        let branches = vm.conditional_branches(&debug_info);
+        let lcov = coverage.lcov(&debug_info, &branches);
+
+        assert_eq!(
+            lcov,
+            "TN:\nSF:loop.asm\n\
+             BRDA:5,12291,0,1\nBRDA:5,12291,1,1\nBRDA:6,12292,0,0\nBRDA:6,12292,1,1\n\
+             BRF:4\nBRH:3\n\
+             DA:2,1\nDA:3,1\nDA:4,2\nDA:5,2\nDA:6,1\nDA:7,1\nDA:8,0\nDA:9,0\n\
+             LF:8\nLH:6\nend_of_record\n"
+        );
+        assert_eq!(
+            coverage.summary(&debug_info, &branches).to_string(),
+            "lines: 6/8 (75.0%), branches: 3/4 (75.0%)"
+        );
+    }
+}
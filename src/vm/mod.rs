@@ -1,15 +1,50 @@
 mod registers;
-use std::{fs::File, io::Read};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    io::Read,
+    path::Path,
+};
 
-use registers::{Cond, Register};
+use registers::Cond;
+pub use registers::Register;
 
 mod trapcodes;
 use trapcodes::{Mmr, TrapCodes};
 
 pub(crate) mod opcodes;
-use opcodes::Opcodes;
 
-use crate::assembler::{decode_instruction, Instruction};
+mod counters;
+pub use counters::Counters;
+
+mod profiler;
+pub use profiler::Profiler;
+
+mod coverage;
+pub use coverage::{Coverage, CoverageSummary};
+
+mod call_stack;
+pub use call_stack::{CallStack, Frame};
+
+mod checker;
+pub use checker::{Checker, Violation, ViolationKind};
+
+mod sanitizer;
+pub use sanitizer::{Location, Sanitizer, UninitializedRead};
+
+mod protection;
+pub use protection::{Access, AccessViolation, MemoryMap, Permissions};
+
+mod builder;
+pub use builder::VmBuilder;
+
+mod console;
+pub use console::Console;
+
+use crate::{
+    assembler::{decode_instruction, DebugInfo, Instruction, Operand, Segment},
+    object,
+};
 
 // Word size = 16 bits
 // Max addressable memory = 2^16 = 1 << 16 = 65536
@@ -17,271 +52,724 @@ use crate::assembler::{decode_instruction, Instruction};
 // Instruction size = 16 bits; max value = 1 << 16 = 65536 = 0x10000
 // Opcode size = 4 bits ; max value = 1 << 4 = 16
 
-const MAX_ADDRESSABLE_MEMORY: usize = 1 << 16;
+pub(crate) const MAX_ADDRESSABLE_MEMORY: usize = 1 << 16;
 const TOTAL_REGISTERS: usize = 10;
 
+// First address of the memory mapped device registers
+const DEVICE_REGISTERS: u16 = Mmr::Kbsr as u16;
+
+/// Return address of a subroutine called by [`Vm::call`], reaching it means the subroutine
+/// returned. It is in the device register page, where no code is expected.
+pub const RETURN_SENTINEL: u16 = 0xFFFF;
+
+/// Why a call to [`Vm::run`], [`Vm::resume`] or [`Vm::step`] stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The program executed HALT
+    Halted,
+    /// The run reached the limit set by [`Vm::set_max_instructions`]
+    StepLimitExceeded,
+    /// Stopped before executing the instruction at a breakpoint
+    Breakpoint(u16),
+    /// RTI, the reserved opcode or a TRAP without a service routine at this address, the PC
+    /// still points at it
+    IllegalInstruction(u16),
+    /// A subroutine called by [`Vm::call`] returned to [`RETURN_SENTINEL`]
+    Returned,
+    /// An access the memory map denies, the PC still points at the instruction
+    AccessViolation(AccessViolation),
+}
+
+impl Display for RunOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunOutcome::Halted => f.write_str("halted"),
+            RunOutcome::StepLimitExceeded => f.write_str("step limit exceeded"),
+            RunOutcome::Breakpoint(address) => write!(f, "breakpoint at x{:04X}", address),
+            RunOutcome::IllegalInstruction(address) => {
+                write!(f, "illegal instruction at x{:04X}", address)
+            }
+            RunOutcome::Returned => f.write_str("returned"),
+            RunOutcome::AccessViolation(violation) => {
+                write!(f, "access violation, {}", violation)
+            }
+        }
+    }
+}
+
+/// An LC-3 machine: 64K words of memory, eight general purpose registers, the PC and the
+/// condition codes
+///
+/// Use [`Vm::builder`] to load images and configure limits, profiling and coverage.
 #[derive(Debug)]
-struct Vm {
+pub struct Vm {
     running: bool,
     memory: [u16; MAX_ADDRESSABLE_MEMORY],
     registers: [u16; TOTAL_REGISTERS],
+    counters: Counters,
+    max_instructions: Option<u64>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    checker: Option<Checker>,
+    sanitizer: Option<Sanitizer>,
+    breakpoints: BTreeSet<u16>,
+    call_stack: CallStack,
+    memory_map: Option<MemoryMap>,
+    // illegal instruction or access violation that stopped the last step
+    fault: Option<RunOutcome>,
+    console: Console,
 }
 
 impl Vm {
-    // Initializes the vm
-    fn initialize() -> Self {
-        // Initialize the vm
+    /// A vm with zeroed memory and registers, the PC at x3000 and the Z flag set
+    pub fn initialize() -> Self {
         let mut vm = Vm {
             running: false,
             memory: [0; MAX_ADDRESSABLE_MEMORY],
             registers: [0; TOTAL_REGISTERS],
+            counters: Counters::default(),
+            max_instructions: None,
+            profiler: None,
+            coverage: None,
+            checker: None,
+            sanitizer: None,
+            breakpoints: BTreeSet::new(),
+            call_stack: CallStack::default(),
+            memory_map: None,
+            fault: None,
+            console: Console::Terminal,
         };
 
         // sets the conditional register to zero
-        vm.set_register(Register::Cond as u16, Cond::Zro as u16);
+        vm.write_register(Register::Cond as u16, Cond::Zro as u16);
 
         // sets the program counter to 0x3000
-        vm.set_register(Register::Pc as u16, 0x3000);
+        vm.write_register(Register::Pc as u16, 0x3000);
 
         vm
     }
 
-    // Loads a program to memory
-    fn load_program(&mut self, program: Vec<u16>) {
-        let mut program_start_address = program[0];
+    /// Starts configuring a vm, see [`VmBuilder`]
+    pub fn builder() -> VmBuilder {
+        VmBuilder::default()
+    }
 
-        assert!(
-            program.len() <= MAX_ADDRESSABLE_MEMORY - program_start_address as usize,
-            "Bytecode is too long"
-        );
+    /// Puts the vm back in its initial state: memory, registers, counters, profile, coverage,
+    /// convention violations and the initialized locations are cleared while the instruction
+    /// limit, the breakpoints and the console are kept
+    pub fn reset(&mut self) {
+        let profiling = self.profiler.is_some();
+        let recording_coverage = self.coverage.is_some();
+        let checking = self.checker.is_some();
+        let sanitizing = self.sanitizer.is_some();
+        *self = Vm {
+            max_instructions: self.max_instructions,
+            breakpoints: std::mem::take(&mut self.breakpoints),
+            memory_map: self.memory_map.take(),
+            console: std::mem::take(&mut self.console),
+            ..Vm::initialize()
+        };
+        if profiling {
+            self.enable_profiler();
+        }
+        if recording_coverage {
+            self.enable_coverage();
+        }
+        if checking {
+            self.enable_checker();
+        }
+        if sanitizing {
+            self.enable_sanitizer();
+        }
+    }
+
+    /// Loads an image, its origin followed by its words, and starts execution at its origin
+    ///
+    /// Fails for an empty image or one that goes past xFFFF.
+    pub fn load_program(&mut self, program: Vec<u16>) -> Result<(), String> {
+        self.load_programs(&[program])
+    }
 
-        for i in 1..program.len() {
-            self.mem_write(program_start_address.into(), program[i].into());
-            program_start_address += 1;
+    /// Loads several images at once, e.g. an OS, a program and its data tables
+    ///
+    /// Execution starts at the origin of the first image. Images must not overlap.
+    pub fn load_programs(&mut self, images: &[Vec<u16>]) -> Result<(), String> {
+        let mut segments: Vec<Segment> = vec![];
+        for image in images {
+            if image.is_empty() {
+                return Err("Empty image, expected its origin".to_string());
+            }
+            let segment = Segment {
+                origin: image[0],
+                words: image[1..].to_vec(),
+            };
+            if segment.end() > MAX_ADDRESSABLE_MEMORY {
+                return Err(format!(
+                    "Image at x{:04X} does not fit in memory",
+                    segment.origin
+                ));
+            }
+            if let Some(other) = segments.iter().find(|other| other.overlaps(&segment)) {
+                return Err(format!(
+                    "Image x{:04X}-x{:04X} overlaps image x{:04X}-x{:04X}",
+                    segment.origin,
+                    segment.end() - 1,
+                    other.origin,
+                    other.end() - 1
+                ));
+            }
+            segments.push(segment);
         }
+
+        for image in images {
+            self.write_image(image);
+        }
+        if let Some(image) = images.first() {
+            self.set_entry(image[0]);
+        }
+        Ok(())
+    }
+
+    // Writes an image `load_programs` checked, it fits in memory
+    fn write_image(&mut self, program: &[u16]) {
+        let origin = usize::from(program[0]);
+        for (offset, word) in program[1..].iter().enumerate() {
+            self.store((origin + offset) as u16, *word);
+        }
+    }
+
+    /// Loads images in any of the supported object formats, see [`load_programs`](Vm::load_programs)
+    pub fn load_programs_from_files(&mut self, paths: &[String]) -> Result<(), String> {
+        let images = paths
+            .iter()
+            .map(|path| object::read_file(Path::new(path)))
+            .collect::<Result<Vec<Vec<u16>>, String>>()?;
+
+        self.load_programs(&images)
+    }
+
+    /// Sets the address of the next instruction to execute
+    pub fn set_entry(&mut self, address: u16) {
+        self.write_register(Register::Pc as u16, address);
     }
 
-    fn load_program_from_file(&mut self, path: String) {
-        let mut file = File::open(path).unwrap();
+    /// Runs until the program halts, reaches a breakpoint or an illegal instruction, or the
+    /// instruction limit is reached
+    ///
+    /// The counters are reset at the start of every run.
+    pub fn run(&mut self) -> RunOutcome {
+        self.counters = Counters::default();
+        self.resume()
+    }
 
-        let mut prog = vec![];
+    /// Continues a run, e.g. after a breakpoint, without stopping at a breakpoint at the PC
+    pub fn resume(&mut self) -> RunOutcome {
+        self.running = true;
+        let mut first = true;
 
-        file.read_to_end(&mut prog).unwrap();
+        while self.running {
+            if self
+                .max_instructions
+                .is_some_and(|max| self.counters.instructions >= max)
+            {
+                self.running = false;
+                return RunOutcome::StepLimitExceeded;
+            }
+            let pc = self.get_register(Register::Pc as u16);
+            if !first && self.breakpoints.contains(&pc) {
+                self.running = false;
+                return RunOutcome::Breakpoint(pc);
+            }
+            first = false;
 
-        let mut program = vec![];
+            self.fetch_execute();
+            if let Some(outcome) = self.fault.take() {
+                return outcome;
+            }
+        }
 
-        let mut i = 0;
+        RunOutcome::Halted
+    }
 
-        while i < prog.len() {
-            program.push((prog[i] as u16) << 8 | (prog[i + 1] as u16));
-            i += 2;
+    /// Calls the subroutine at `address` with R7 set to [`RETURN_SENTINEL`] and runs until it
+    /// returns there, the registers and memory are left as they are otherwise
+    pub fn call(&mut self, address: u16) -> RunOutcome {
+        self.write_register(Register::R7 as u16, RETURN_SENTINEL);
+        self.set_entry(address);
+        self.call_stack.enter(address, RETURN_SENTINEL);
+        if let Some(checker) = &mut self.checker {
+            let sp = self.registers[Register::R6 as usize];
+            checker.enter(address, RETURN_SENTINEL, sp);
+        }
+        let added = self.breakpoints.insert(RETURN_SENTINEL);
+        let outcome = self.run();
+        if added {
+            self.breakpoints.remove(&RETURN_SENTINEL);
         }
 
-        self.load_program(program);
+        match outcome {
+            RunOutcome::Breakpoint(RETURN_SENTINEL) => RunOutcome::Returned,
+            outcome => outcome,
+        }
     }
 
-    fn run(&mut self) {
+    /// Executes a single instruction, the outcome is `None` while the program can go on
+    pub fn step(&mut self) -> Option<RunOutcome> {
         self.running = true;
+        self.fetch_execute();
+        match self.fault.take() {
+            Some(outcome) => Some(outcome),
+            None if !self.running => Some(RunOutcome::Halted),
+            None => None,
+        }
+    }
 
-        while self.running {
-            let instruction = self.fetch();
-            let instr = decode_instruction(instruction);
-            self.update_pc();
-            self.execute(instr);
+    /// Makes [`run`](Vm::run) and [`resume`](Vm::resume) stop before the instruction at `address`
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Returns false when there was no breakpoint at the address
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// The addresses where [`run`](Vm::run) stops
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    /// The subroutines the program is in
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// The PC and the call sites of the subroutines the program is in, see
+    /// [`CallStack::backtrace`]
+    pub fn backtrace(
+        &self,
+        debug_info: Option<&DebugInfo>,
+        symbols: &BTreeMap<String, u16>,
+    ) -> String {
+        self.call_stack
+            .backtrace(self.get_register(Register::Pc as u16), debug_info, symbols)
+    }
+
+    /// The value of a register, the condition codes are one of the N, Z and P bits (4, 2, 1)
+    pub fn register(&self, register: Register) -> u16 {
+        self.get_register(register as u16)
+    }
+
+    /// Sets a register, the condition codes are left as they are
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        self.write_register(register as u16, value);
+    }
+
+    /// Replaces the console the traps and the keyboard registers use
+    pub fn set_console(&mut self, console: Console) {
+        self.console = console;
+    }
+
+    /// Where the program reads and writes characters
+    pub fn console(&self) -> &Console {
+        &self.console
+    }
+
+    /// Reads a word without the side effects of the memory-mapped device registers
+    pub fn read_memory(&self, address: u16) -> u16 {
+        self.memory[address as usize]
+    }
+
+    /// Writes a word whatever the memory map allows
+    pub fn write_memory(&mut self, address: u16, value: u16) {
+        self.store(address, value);
+    }
+
+    /// Checks every access of the program against `memory_map`, `None` allows everything
+    pub fn set_memory_map(&mut self, memory_map: Option<MemoryMap>) {
+        self.memory_map = memory_map;
+    }
+
+    /// The permissions the program runs with, if any
+    pub fn memory_map(&self) -> Option<&MemoryMap> {
+        self.memory_map.as_ref()
+    }
+
+    // Fetches, decodes and executes a single instruction
+    // RTI, the reserved opcode, unknown traps and access violations stop the program with the PC
+    // left at the instruction
+    fn fetch_execute(&mut self) {
+        let address = self.get_register(Register::Pc as u16);
+        if let Some(sanitizer) = &mut self.sanitizer {
+            sanitizer.start(address);
+        }
+        let Some(instruction) = self.fetch() else {
+            return;
+        };
+        let instr = decode_instruction(instruction);
+        if let Instruction::Rti | Instruction::Res = instr {
+            self.running = false;
+            self.fault = Some(RunOutcome::IllegalInstruction(address));
+            return;
+        }
+        if let Some(sanitizer) = &mut self.sanitizer {
+            sanitizer.read_registers(&instr);
+        }
+        let before = self.registers;
+        self.update_pc();
+
+        // branch directions are only worked out when coverage is recorded
+        let taken = match (&self.coverage, instr) {
+            (Some(_), Instruction::Br { n, z, p, .. }) => Some(self.branch_taken(n, z, p)),
+            _ => None,
+        };
+
+        self.execute(instr);
+        // a faulting instruction has no effect, not even on the counters
+        if self.fault.is_some() {
+            self.registers = before;
+            return;
+        }
+        self.counters.record(instr.opcode());
+
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(address);
+            if let Some(taken) = taken {
+                coverage.record_branch(address, taken);
+            }
+        }
+
+        let next_pc = self.get_register(Register::Pc as u16);
+        self.call_stack
+            .record(&instr, next_pc, self.get_register(Register::R7 as u16));
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(address, &instr, next_pc);
         }
+
+        if let Some(checker) = &mut self.checker {
+            checker.record(address, &instr, &before, &self.registers, &self.memory);
+        }
+    }
+
+    /// The instructions and cycles of the current run
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// Starts profiling from the current PC, discarding any previous profile
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new(self.get_register(Register::Pc as u16)));
+    }
+
+    /// The profile, if the profiler is enabled
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// The profile as a table of the hottest addresses and subroutines, labelled with `symbols`
+    pub fn profile_report(&self, symbols: &BTreeMap<String, u16>) -> Option<String> {
+        self.profiler
+            .as_ref()
+            .map(|profiler| profiler.report(&self.memory, symbols))
+    }
+
+    /// Starts recording coverage, discarding any previous recording
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    /// The coverage, if it is recorded
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Starts checking the calling convention, discarding any previous violations
+    pub fn enable_checker(&mut self) {
+        self.checker = Some(Checker::new(self.get_register(Register::R6 as u16)));
+    }
+
+    /// The convention checker, if it is enabled
+    pub fn checker(&self) -> Option<&Checker> {
+        self.checker.as_ref()
+    }
+
+    /// Starts tracking which memory words and registers were initialized, only the PC and the
+    /// condition codes are until images are loaded or values written
+    pub fn enable_sanitizer(&mut self) {
+        self.sanitizer = Some(Sanitizer::new());
+    }
+
+    /// The initialized locations and uninitialized reads, if the sanitizer is enabled
+    pub fn sanitizer(&self) -> Option<&Sanitizer> {
+        self.sanitizer.as_ref()
+    }
+
+    /// Addresses of the BR instructions that can go either way
+    pub fn conditional_branches(&self, debug_info: &DebugInfo) -> Vec<u16> {
+        debug_info
+            .lines
+            .keys()
+            .copied()
+            .filter(|address| {
+                matches!(
+                    decode_instruction(self.memory[*address as usize]),
+                    Instruction::Br { n, z, p, .. } if !(n && z && p) && (n || z || p)
+                )
+            })
+            .collect()
+    }
+
+    /// Limits the number of instructions a single run may execute
+    pub fn set_max_instructions(&mut self, max_instructions: Option<u64>) {
+        self.max_instructions = max_instructions;
     }
 
     fn update_pc(&mut self) {
-        self.set_register(
+        self.write_register(
             Register::Pc as u16,
-            self.get_register(Register::Pc as u16) + 1,
+            self.get_register(Register::Pc as u16).wrapping_add(1),
         );
     }
 
-    // Fetches an instruction from memory
-    fn fetch(&mut self) -> u16 {
-        self.mem_read(self.get_register(Register::Pc as u16))
+    // Fetches an instruction from memory, None when it may not be executed
+    fn fetch(&mut self) -> Option<u16> {
+        let pc = self.get_register(Register::Pc as u16);
+        self.check_access(pc, pc, Access::Execute)
+            .then(|| self.load(pc))
     }
 
     // Executes an instruction
     fn execute(&mut self, instruction: Instruction) {
-        let opcode: Opcodes = instruction.opcode;
-
-        match opcode {
-            Opcodes::Br => {
-                let cond = self.get_register(Register::Cond as u16);
-
-                if (instruction.nzp & cond) > 0 {
-                    self.set_register(
-                        Register::Pc as u16,
-                        sign_extend(instruction.pc_offset_9, 9)
-                            .wrapping_add(self.get_register(Register::Pc as u16)),
-                    );
+        match instruction {
+            Instruction::Br { n, z, p, offset } => {
+                if self.branch_taken(n, z, p) {
+                    self.write_register(Register::Pc as u16, self.pc_relative(offset));
                 }
             }
 
-            Opcodes::Add => {
-                let val2 = if instruction.imm_or_cond_flag == 0 {
-                    self.get_register(instruction.sr2)
-                } else {
-                    sign_extend(instruction.imm5, 5)
-                };
+            Instruction::Add { dr, sr1, operand } => {
+                let val2 = self.operand_value(operand);
 
-                self.set_register(
-                    instruction.dr,
-                    self.get_register(instruction.sr1).wrapping_add(val2),
+                self.write_register(
+                    dr.number(),
+                    self.get_register(sr1.number()).wrapping_add(val2),
                 );
-                self.update_flag(instruction.dr);
+                self.update_flag(dr.number());
             }
 
-            Opcodes::Ld => {
-                let memory_address = sign_extend(instruction.pc_offset_9, 9)
-                    .wrapping_add(self.get_register(Register::Pc as u16));
-                let val = self.mem_read(memory_address);
-                self.set_register(instruction.dr, val);
-                self.update_flag(instruction.dr);
+            Instruction::Ld { dr, offset } => {
+                let val = self.mem_read(self.pc_relative(offset));
+                self.write_register(dr.number(), val);
+                self.update_flag(dr.number());
             }
 
-            Opcodes::St => {
-                self.mem_write(
-                    sign_extend(instruction.pc_offset_9, 9)
-                        .wrapping_add(self.get_register(Register::Pc as u16)),
-                    self.get_register(instruction.sr1),
-                );
+            Instruction::St { sr, offset } => {
+                self.mem_write(self.pc_relative(offset), self.get_register(sr.number()));
             }
 
-            Opcodes::Jsr => {
-                self.set_register(Register::R7 as u16, self.get_register(Register::Pc as u16));
-
-                if instruction.imm_or_cond_flag == 1 {
-                    self.set_register(
-                        Register::Pc as u16,
-                        sign_extend(instruction.pc_offset_11, 11)
-                            .wrapping_add(self.get_register(Register::Pc as u16)),
-                    );
-                } else {
-                    self.set_register(Register::Pc as u16, self.get_register(instruction.base_r));
-                }
+            Instruction::Jsr { offset } => {
+                let target = self.pc_relative(offset);
+                self.write_register(Register::R7 as u16, self.get_register(Register::Pc as u16));
+                self.write_register(Register::Pc as u16, target);
             }
 
-            Opcodes::And => {
-                let val2 = if instruction.imm_or_cond_flag == 1 {
-                    sign_extend(instruction.imm5, 5)
-                } else {
-                    self.get_register(instruction.sr2)
-                };
-                self.set_register(instruction.dr, self.get_register(instruction.sr1) & val2);
-                self.update_flag(instruction.dr);
+            Instruction::Jsrr { base_r } => {
+                // read the base register first in case it is R7
+                let target = self.get_register(base_r.number());
+                self.write_register(Register::R7 as u16, self.get_register(Register::Pc as u16));
+                self.write_register(Register::Pc as u16, target);
             }
 
-            Opcodes::Ldr => {
-                let val = self.mem_read(
-                    sign_extend(instruction.offset_6, 6)
-                        .wrapping_add(self.get_register(instruction.base_r)),
-                );
-                self.set_register(instruction.dr, val);
-                self.update_flag(instruction.dr);
+            Instruction::And { dr, sr1, operand } => {
+                let val2 = self.operand_value(operand);
+                self.write_register(dr.number(), self.get_register(sr1.number()) & val2);
+                self.update_flag(dr.number());
             }
 
-            Opcodes::Str => {
+            Instruction::Ldr { dr, base_r, offset } => {
+                let val =
+                    self.mem_read((offset as u16).wrapping_add(self.get_register(base_r.number())));
+                self.write_register(dr.number(), val);
+                self.update_flag(dr.number());
+            }
+
+            Instruction::Str { sr, base_r, offset } => {
                 self.mem_write(
-                    sign_extend(instruction.offset_6, 6)
-                        .wrapping_add(self.get_register(instruction.base_r)),
-                    self.get_register(instruction.sr1),
+                    (offset as u16).wrapping_add(self.get_register(base_r.number())),
+                    self.get_register(sr.number()),
                 );
             }
 
-            Opcodes::Rti => unimplemented!(),
+            // rejected by `step`
+            Instruction::Rti | Instruction::Res => unreachable!(),
 
-            Opcodes::Not => {
-                self.set_register(instruction.dr, !self.get_register(instruction.sr1));
-                self.update_flag(instruction.dr);
+            Instruction::Not { dr, sr } => {
+                self.write_register(dr.number(), !self.get_register(sr.number()));
+                self.update_flag(dr.number());
             }
 
-            Opcodes::Ldi => {
-                let memory_address = sign_extend(instruction.pc_offset_9, 9)
-                    .wrapping_add(self.get_register(Register::Pc as u16));
-                let value_address = self.mem_read(memory_address);
+            Instruction::Ldi { dr, offset } => {
+                let value_address = self.mem_read(self.pc_relative(offset));
 
                 let val = self.mem_read(value_address);
-                self.set_register(instruction.dr, val);
-                self.update_flag(instruction.dr);
+                self.write_register(dr.number(), val);
+                self.update_flag(dr.number());
             }
 
-            Opcodes::Sti => {
-                let addr = self.mem_read(
-                    sign_extend(instruction.pc_offset_9, 9)
-                        .wrapping_add(self.get_register(Register::Pc as u16)),
-                );
-                self.mem_write(addr, self.get_register(instruction.sr1));
+            Instruction::Sti { sr, offset } => {
+                let addr = self.mem_read(self.pc_relative(offset));
+                self.mem_write(addr, self.get_register(sr.number()));
             }
 
-            Opcodes::Jmp => {
-                self.set_register(Register::Pc as u16, self.get_register(instruction.base_r));
+            Instruction::Jmp { base_r } => {
+                self.write_register(Register::Pc as u16, self.get_register(base_r.number()));
             }
 
-            Opcodes::Res => unimplemented!(),
+            Instruction::Lea { dr, offset } => {
+                self.write_register(dr.number(), self.pc_relative(offset));
+                self.update_flag(dr.number());
+            }
 
-            Opcodes::Lea => {
-                self.set_register(
-                    instruction.dr,
-                    sign_extend(instruction.pc_offset_9, 9)
-                        .wrapping_add(self.get_register(Register::Pc as u16)),
-                );
-                self.update_flag(instruction.dr);
+            Instruction::Trap { trap_vect } => {
+                match TrapCodes::try_from(trap_vect as u16) {
+                    Ok(trap_instruction) => trap_instruction.execute(self),
+                    // there is no operating system to run other service routines
+                    Err(()) => {
+                        let address = self.get_register(Register::Pc as u16).wrapping_sub(1);
+                        self.running = false;
+                        self.fault = Some(RunOutcome::IllegalInstruction(address));
+                    }
+                }
             }
+        }
+    }
 
-            Opcodes::Trap => {
-                let trap_instruction: TrapCodes = instruction.trap_vect_8.into();
+    fn branch_taken(&self, n: bool, z: bool, p: bool) -> bool {
+        let cond = self.get_register(Register::Cond as u16);
+        let nzp = (n as u16) << 2 | (z as u16) << 1 | p as u16;
 
-                trap_instruction.execute(self);
-            }
+        (nzp & cond) > 0
+    }
+
+    // Computes an address relative to the (already incremented) program counter
+    fn pc_relative(&self, offset: i16) -> u16 {
+        self.get_register(Register::Pc as u16)
+            .wrapping_add(offset as u16)
+    }
+
+    fn operand_value(&self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Reg(sr2) => self.get_register(sr2.number()),
+            Operand::Imm(imm5) => imm5 as u16,
         }
     }
 
-    fn get_register(&self, register_address: u16) -> u16 {
+    pub(crate) fn get_register(&self, register_address: u16) -> u16 {
         self.registers[register_address as usize]
     }
 
-    fn set_register(&mut self, register_address: u16, value: u16) {
+    fn write_register(&mut self, register_address: u16, value: u16) {
+        // the registers are restored after a fault, they don't become initialized
+        if let (Some(sanitizer), None) = (&mut self.sanitizer, &self.fault) {
+            sanitizer.write_register(register_address);
+        }
         self.registers[register_address as usize] = value;
     }
 
-    fn mem_read(&mut self, memory_address: u16) -> u16 {
+    // Reads a word for the instruction being executed, 0 when the memory map denies it or an
+    // earlier access of the instruction was denied
+    pub(crate) fn mem_read(&mut self, memory_address: u16) -> u16 {
+        // memory accesses happen after the PC moved past the instruction
+        let pc = self.get_register(Register::Pc as u16).wrapping_sub(1);
+        match self.fault.is_none() && self.check_access(pc, memory_address, Access::Read) {
+            true => self.load(memory_address),
+            false => 0,
+        }
+    }
+
+    // Whether the memory map allows the instruction at `pc` to access `address`, a denied
+    // access stops the program
+    fn check_access(&mut self, pc: u16, address: u16, access: Access) -> bool {
+        let Some(memory_map) = &self.memory_map else {
+            return true;
+        };
+        if memory_map.permissions(address).allows(access) {
+            return true;
+        }
+        self.running = false;
+        // the first violation of an instruction is reported
+        self.fault
+            .get_or_insert(RunOutcome::AccessViolation(AccessViolation {
+                pc,
+                address,
+                access,
+            }));
+        false
+    }
+
+    fn load(&mut self, memory_address: u16) -> u16 {
+        if let Some(sanitizer) = &mut self.sanitizer {
+            sanitizer.read_memory(memory_address);
+        }
         if memory_address == Mmr::Kbsr as u16 {
-            self.mem_write(memory_address, 1 << 15);
-            let mut val = [0];
-            std::io::stdin().read_exact(&mut val).unwrap();
-            self.mem_write(Mmr::Kbdr as u16, val[0] as u16);
+            match &mut self.console {
+                Console::Terminal => {
+                    self.store(memory_address, 1 << 15);
+                    let mut val = [0];
+                    std::io::stdin().read_exact(&mut val).unwrap();
+                    self.store(Mmr::Kbdr as u16, val[0] as u16);
+                }
+                // no key is ready once the input is exhausted
+                console => match console.read() {
+                    Some(char) => {
+                        self.store(memory_address, 1 << 15);
+                        self.store(Mmr::Kbdr as u16, char as u16);
+                    }
+                    None => self.store(memory_address, 0),
+                },
+            }
         } else {
-            self.mem_write(Mmr::Kbsr as u16, 0);
+            self.store(Mmr::Kbsr as u16, 0);
+        }
+        // the display is always ready for the next character
+        if memory_address == Mmr::Dsr as u16 {
+            self.store(memory_address, 1 << 15);
         }
         self.memory[memory_address as usize]
     }
 
+    // Writes a word for the instruction being executed unless the memory map denies it
+    // Writes a word for the instruction being executed, unless the memory map denies it or an
+    // earlier access of the instruction was denied, e.g. the pointer read by STI
     fn mem_write(&mut self, memory_address: u16, value: u16) {
+        let pc = self.get_register(Register::Pc as u16).wrapping_sub(1);
+        if self.fault.is_none() && self.check_access(pc, memory_address, Access::Write) {
+            if memory_address == Mmr::Ddr as u16 {
+                let char = (value & 0xFF) as u8 as char;
+                self.console.write(&char.to_string());
+            }
+            self.store(memory_address, value);
+        }
+    }
+
+    fn store(&mut self, memory_address: u16, value: u16) {
+        if let Some(sanitizer) = &mut self.sanitizer {
+            sanitizer.write_memory(memory_address);
+        }
         self.memory[memory_address as usize] = value
     }
 
     fn update_flag(&mut self, register_address: u16) {
         if self.get_register(register_address) == 0 {
-            self.set_register(Register::Cond as u16, Cond::Zro as u16);
+            self.write_register(Register::Cond as u16, Cond::Zro as u16);
         } else if (self.get_register(register_address) >> 15) == 1 {
-            self.set_register(Register::Cond as u16, Cond::Neg as u16);
+            self.write_register(Register::Cond as u16, Cond::Neg as u16);
         } else {
-            self.set_register(Register::Cond as u16, Cond::Pos as u16);
+            self.write_register(Register::Cond as u16, Cond::Pos as u16);
         }
     }
 }
 
-fn sign_extend(value: u16, bit_count: usize) -> u16 {
-    if (value >> (bit_count - 1)) & 1 == 1 {
-        (0xFFFF << bit_count) | value
-    } else {
-        value
-    }
-}
-
 #[cfg(test)]
 mod tests {
 
@@ -290,7 +778,11 @@ mod tests {
     // FEAA -> 1111 111 010 1 01010
     // EAA ->  0000 111 010 1 01010
 
-    use crate::vm::{decode_instruction, sign_extend, Register, Vm};
+    use crate::assembler::{assemble, sign_extend};
+    use crate::vm::{
+        counters::cycles, decode_instruction, opcodes::Opcodes, Cond, Console, Register,
+        RunOutcome, Vm, RETURN_SENTINEL,
+    };
 
     fn create_vm() -> Vm {
         Vm::initialize()
@@ -300,7 +792,7 @@ mod tests {
     fn test_add_instruction() {
         let mut vm = create_vm();
         // set register 2 for immediate value 50
-        vm.set_register(0x2, 50);
+        vm.write_register(0x2, 50);
         assert_eq!(vm.get_register(0x2), 50);
         // Run instruction 0x1EAA
         // 1EAA -> 0001 111 010 1 01010
@@ -339,51 +831,239 @@ mod tests {
         // Instruction
         // 575 -> 0000 010 101110101
         // 475 -> 0000 010 001110101
-        dbg!(vm.get_register(Register::Pc as u16));
+        assert_eq!(vm.get_register(Register::Pc as u16), 0x3000);
+        vm.execute(decode_instruction(0x475));
+        assert_eq!(vm.get_register(Register::Pc as u16), 0x3075);
+
+        // not taken once the condition is positive
+        vm.write_register(Register::Cond as u16, Cond::Pos as u16);
         vm.execute(decode_instruction(0x475));
-        dbg!(vm.get_register(Register::Pc as u16));
+        assert_eq!(vm.get_register(Register::Pc as u16), 0x3075);
     }
 
     #[test]
     fn test_load_program() {
         let mut vm = create_vm();
 
-        vm.set_register(0x2, 50);
+        vm.write_register(0x2, 50);
 
         let program = vec![0x3000, 0x1EAA];
-        vm.load_program(program);
-        let instruction = vm.fetch();
+        vm.load_program(program).unwrap();
+        let instruction = vm.fetch().unwrap();
 
         vm.execute(decode_instruction(instruction));
 
         assert_eq!(vm.get_register(Register::R7 as u16), 60);
         assert_eq!(vm.get_register(Register::Cond as u16), 1);
+
+        // the last word of memory can be loaded, not one more
+        assert_eq!(vm.load_program(vec![0xFFFF, 0x1234]), Ok(()));
+        assert_eq!(vm.read_memory(0xFFFF), 0x1234);
+        assert_eq!(
+            vm.load_program(vec![0xFFFF, 1, 2]),
+            Err("Image at xFFFF does not fit in memory".to_string())
+        );
+        assert_eq!(
+            vm.load_programs(&[vec![0x3000, 1], vec![]]),
+            Err("Empty image, expected its origin".to_string())
+        );
+        assert!(vm.load_program(vec![]).is_err());
     }
 
     #[test]
-    fn test_load_program_from_file() {
+    fn test_pc_wraps_at_end_of_memory() {
         let mut vm = create_vm();
+        vm.load_program(vec![0xFFFF, 0x1021]).unwrap();
 
-        vm.load_program_from_file(String::from("src/examples/2048.obj"));
+        // ADD R0, R0, #1 at xFFFF, the next instruction is at x0000
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.register(Register::R0), 1);
+        assert_eq!(vm.register(Register::Pc), 0x0000);
+    }
 
-        dbg!(vm.get_register(Register::Pc as u16));
+    #[test]
+    fn test_load_program_from_file() {
+        let mut vm = create_vm();
 
-        let instruction = vm.fetch();
-        dbg!(instruction);
+        vm.load_programs_from_files(&[String::from("src/examples/2048.obj")])
+            .unwrap();
 
-        vm.execute(decode_instruction(instruction));
+        assert_eq!(vm.get_register(Register::Pc as u16), 0x3000);
+
+        // LD R6, #23, the offset applies to the incremented PC
+        assert_eq!(vm.read_memory(0x3000), 0x2C17);
+        assert_eq!(vm.step(), None);
 
-        dbg!(vm.get_register(Register::Pc as u16));
+        assert_eq!(vm.get_register(Register::Pc as u16), 0x3001);
+        assert_eq!(vm.get_register(Register::R6 as u16), vm.read_memory(0x3018));
     }
 
     #[test]
     fn test_run_program() {
         let mut vm = create_vm();
 
-        vm.load_program_from_file(String::from("src/examples/2048.obj"));
-        // vm.load_program_from_file(String::from("src/examples/rogue.obj"));
-        // vm.load_program_from_file(String::from("src/examples/hello-world.obj"));
+        vm.load_programs_from_files(&[String::from("src/examples/2048.obj")])
+            .unwrap();
+        // the game waits for keys forever, answer its question and stop it once it waits
+        vm.set_console(Console::scripted(b"n"));
+        vm.set_max_instructions(Some(20_000));
+
+        assert_eq!(vm.run(), RunOutcome::StepLimitExceeded);
+        let output = String::from_utf8_lossy(vm.console().output()).to_string();
+        assert!(output.starts_with(
+            "Control the game using WASD keys.\nAre you on an ANSI terminal (y/n)? n\n+---"
+        ));
+    }
+
+    #[test]
+    fn test_run_counters() {
+        let mut vm = create_vm();
+
+        let program = assemble(
+            ".ORIG x3000
+            ADD R1, R1, #1
+            LD R2, DATA
+            HALT
+            DATA .FILL #5
+            .END",
+        )
+        .unwrap();
+        vm.load_program(program.to_image().unwrap()).unwrap();
+
+        assert_eq!(vm.run(), RunOutcome::Halted);
+        assert_eq!(vm.counters().instructions, 3);
+        assert_eq!(
+            vm.counters().cycles,
+            cycles(Opcodes::Add) + cycles(Opcodes::Ld) + cycles(Opcodes::Trap)
+        );
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut vm = create_vm();
+
+        let program = assemble(".ORIG x3000\nLOOP BR LOOP\n.END").unwrap();
+        vm.load_program(program.to_image().unwrap()).unwrap();
+        vm.set_max_instructions(Some(1000));
+
+        assert_eq!(vm.run(), RunOutcome::StepLimitExceeded);
+        assert_eq!(vm.counters().instructions, 1000);
+
+        // the limit applies to every run separately
+        assert_eq!(vm.run(), RunOutcome::StepLimitExceeded);
+        assert_eq!(vm.counters().instructions, 1000);
+    }
+
+    #[test]
+    fn test_load_program_sets_entry() {
+        let mut vm = create_vm();
+
+        let program = assemble(".ORIG x4000\nADD R1, R1, #7\nHALT\n.END").unwrap();
+        vm.load_program(program.to_image().unwrap()).unwrap();
 
+        assert_eq!(vm.get_register(Register::Pc as u16), 0x4000);
         vm.run();
+        assert_eq!(vm.get_register(0x1), 7);
+    }
+
+    #[test]
+    fn test_load_programs() {
+        let mut vm = create_vm();
+
+        let program =
+            assemble(".ORIG x3000\nLDI R1, TABLE\nHALT\nTABLE .FILL x5000\n.END").unwrap();
+        let data = vec![0x5000, 42];
+        vm.load_programs(&[data.clone(), program.to_image().unwrap()])
+            .unwrap();
+        assert_eq!(vm.get_register(Register::Pc as u16), 0x5000);
+
+        vm.set_entry(0x3000);
+        vm.run();
+        assert_eq!(vm.get_register(0x1), 42);
+
+        assert_eq!(
+            vm.load_programs(&[vec![0x3000, 1, 2, 3], vec![0x3002, 4]])
+                .unwrap_err(),
+            "Image x3002-x3002 overlaps image x3000-x3002"
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut vm = create_vm();
+
+        let program = assemble(
+            ".ORIG x3000
+            ADD R1, R1, #1
+LOOP        ADD R1, R1, #1
+            BRp LOOP
+            .END",
+        )
+        .unwrap();
+        vm.load_program(program.to_image().unwrap()).unwrap();
+        vm.add_breakpoint(0x3001);
+
+        assert_eq!(vm.run(), RunOutcome::Breakpoint(0x3001));
+        assert_eq!(vm.get_register(0x1), 1);
+        // resuming executes the instruction at the breakpoint
+        assert_eq!(vm.resume(), RunOutcome::Breakpoint(0x3001));
+        assert_eq!(vm.get_register(0x1), 2);
+        assert_eq!(vm.counters().instructions, 3);
+
+        assert!(vm.remove_breakpoint(0x3001));
+        assert!(!vm.remove_breakpoint(0x3001));
+        vm.set_max_instructions(Some(10));
+        assert_eq!(vm.resume(), RunOutcome::StepLimitExceeded);
+    }
+
+    #[test]
+    fn test_call() {
+        let mut vm = create_vm();
+
+        let program = assemble(
+            ".ORIG x3000
+            HALT
+DOUBLE      ADD R0, R0, R0
+            RET
+FOREVER     BR FOREVER
+            .END",
+        )
+        .unwrap();
+        vm.load_program(program.to_image().unwrap()).unwrap();
+        vm.write_register(0x0, 21);
+
+        assert_eq!(vm.call(0x3001), RunOutcome::Returned);
+        assert_eq!(vm.get_register(0x0), 42);
+        assert_eq!(vm.get_register(Register::Pc as u16), RETURN_SENTINEL);
+        assert_eq!(vm.counters().instructions, 2);
+        assert!(vm.breakpoints().is_empty());
+
+        vm.set_max_instructions(Some(50));
+        assert_eq!(vm.call(0x3003), RunOutcome::StepLimitExceeded);
+    }
+
+    #[test]
+    fn test_illegal_instruction() {
+        let mut vm = create_vm();
+
+        let program = assemble(".ORIG x3000\nADD R1, R1, #1\nRTI\nHALT\n.END").unwrap();
+        vm.load_program(program.to_image().unwrap()).unwrap();
+
+        assert_eq!(vm.run(), RunOutcome::IllegalInstruction(0x3001));
+        assert_eq!(vm.get_register(Register::Pc as u16), 0x3001);
+        assert_eq!(vm.get_register(0x1), 1);
+
+        vm.set_entry(0x3000);
+        assert_eq!(vm.step(), None);
+        assert_eq!(vm.step(), Some(RunOutcome::IllegalInstruction(0x3001)));
+
+        // a trap vector without a service routine
+        let program = assemble(".ORIG x3000\nADD R1, R1, #1\nTRAP x30\nHALT\n.END").unwrap();
+        vm.reset();
+        vm.load_program(program.to_image().unwrap()).unwrap();
+        assert_eq!(vm.run(), RunOutcome::IllegalInstruction(0x3001));
+        assert_eq!(vm.get_register(Register::Pc as u16), 0x3001);
+        assert_eq!(vm.get_register(0x1), 1);
+        assert_eq!(vm.counters().instructions, 1);
     }
 }
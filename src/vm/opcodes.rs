@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum Opcodes {
     Br,   // branch
     Add,  // add
@@ -43,39 +43,39 @@ impl Display for Opcodes {
     }
 }
 
-impl Into<Opcodes> for u16 {
-    fn into(self) -> Opcodes {
-        if self == 0 {
+impl From<u16> for Opcodes {
+    fn from(value: u16) -> Self {
+        if value == 0 {
             Opcodes::Br
-        } else if self == 1 {
+        } else if value == 1 {
             Opcodes::Add
-        } else if self == 2 {
+        } else if value == 2 {
             Opcodes::Ld
-        } else if self == 3 {
+        } else if value == 3 {
             Opcodes::St
-        } else if self == 4 {
+        } else if value == 4 {
             Opcodes::Jsr
-        } else if self == 5 {
+        } else if value == 5 {
             Opcodes::And
-        } else if self == 6 {
+        } else if value == 6 {
             Opcodes::Ldr
-        } else if self == 7 {
+        } else if value == 7 {
             Opcodes::Str
-        } else if self == 8 {
+        } else if value == 8 {
             Opcodes::Rti
-        } else if self == 9 {
+        } else if value == 9 {
             Opcodes::Not
-        } else if self == 10 {
+        } else if value == 10 {
             Opcodes::Ldi
-        } else if self == 11 {
+        } else if value == 11 {
             Opcodes::Sti
-        } else if self == 12 {
+        } else if value == 12 {
             Opcodes::Jmp
-        } else if self == 13 {
+        } else if value == 13 {
             Opcodes::Res
-        } else if self == 14 {
+        } else if value == 14 {
             Opcodes::Lea
-        } else if self == 15 {
+        } else if value == 15 {
             Opcodes::Trap
         } else {
             panic!("Invalid opcode")
@@ -0,0 +1,286 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use crate::assembler::{decode_instruction, Gpr, Instruction};
+
+// Number of hottest addresses listed in the report
+const TOP_ADDRESSES: usize = 20;
+
+/// Counts executed instructions per address and per call stack
+///
+/// Subroutines are entered by JSR/JSRR and left by RET
+#[derive(Debug)]
+pub struct Profiler {
+    hits: Vec<u64>,
+    calls: BTreeMap<u16, u64>,
+    // entry addresses of the active subroutines, the first one is where profiling started
+    stack: Vec<u16>,
+    stacks: HashMap<Vec<u16>, u64>,
+}
+
+impl Profiler {
+    pub(crate) fn new(entry: u16) -> Self {
+        Profiler {
+            hits: vec![0; 1 << 16],
+            calls: BTreeMap::new(),
+            stack: vec![entry],
+            stacks: HashMap::new(),
+        }
+    }
+
+    // Records an executed instruction, `next_pc` is the PC after it executed
+    pub(crate) fn record(&mut self, address: u16, instruction: &Instruction, next_pc: u16) {
+        self.hits[address as usize] += 1;
+
+        match self.stacks.get_mut(self.stack.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+
+        match instruction {
+            Instruction::Jsr { .. } | Instruction::Jsrr { .. } => {
+                *self.calls.entry(next_pc).or_default() += 1;
+                self.stack.push(next_pc);
+            }
+            Instruction::Jmp { base_r: Gpr::R7 } if self.stack.len() > 1 => {
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    /// How many times the instruction at `address` was executed
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits[address as usize]
+    }
+
+    /// Instructions executed by each subroutine, keyed by entry address, as (by itself,
+    /// including its callees)
+    pub fn subroutines(&self) -> BTreeMap<u16, (u64, u64)> {
+        let mut subroutines = BTreeMap::<u16, (u64, u64)>::new();
+
+        for (stack, count) in &self.stacks {
+            subroutines.entry(*stack.last().unwrap()).or_default().0 += count;
+
+            // recursive calls only count once towards the inclusive total
+            let mut seen = stack.clone();
+            seen.sort();
+            seen.dedup();
+            for entry in seen {
+                subroutines.entry(entry).or_default().1 += count;
+            }
+        }
+
+        subroutines
+    }
+
+    /// One line per call stack: `frame;frame;frame count`, as read by flamegraph tools
+    pub fn folded_stacks(&self, symbols: &BTreeMap<String, u16>) -> String {
+        let mut lines = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let frames = stack
+                    .iter()
+                    .map(|entry| frame_name(*entry, symbols))
+                    .collect::<Vec<String>>();
+                format!("{} {}", frames.join(";"), count)
+            })
+            .collect::<Vec<String>>();
+        lines.sort();
+
+        let mut folded = lines.join("\n");
+        folded.push('\n');
+        folded
+    }
+
+    // Sorted report of the hottest addresses, subroutines and labels
+    pub(crate) fn report(&self, memory: &[u16], symbols: &BTreeMap<String, u16>) -> String {
+        let total = self.hits.iter().sum::<u64>().max(1);
+        let percent = |count: u64| count as f64 * 100.0 / total as f64;
+        let mut report = String::new();
+
+        writeln!(report, "Hottest addresses").unwrap();
+        writeln!(
+            report,
+            "{:<8} {:<20} {:>12} {:>8}  instruction",
+            "address", "location", "count", "%"
+        )
+        .unwrap();
+        let mut addresses = (0..=u16::MAX)
+            .filter(|address| self.hits(*address) > 0)
+            .collect::<Vec<u16>>();
+        addresses.sort_by_key(|address| (std::cmp::Reverse(self.hits(*address)), *address));
+        for address in addresses.into_iter().take(TOP_ADDRESSES) {
+            writeln!(
+                report,
+                "x{:04X}    {:<20} {:>12} {:>7.2}%  {}",
+                address,
+                location(address, symbols),
+                self.hits(address),
+                percent(self.hits(address)),
+                decode_instruction(memory[address as usize])
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nSubroutines").unwrap();
+        writeln!(
+            report,
+            "{:<20} {:>8} {:>12} {:>8} {:>12} {:>8}",
+            "subroutine", "calls", "self", "%", "total", "%"
+        )
+        .unwrap();
+        let mut subroutines = self.subroutines().into_iter().collect::<Vec<_>>();
+        subroutines.sort_by_key(|(entry, (own, _))| (std::cmp::Reverse(*own), *entry));
+        for (entry, (own, inclusive)) in subroutines {
+            writeln!(
+                report,
+                "{:<20} {:>8} {:>12} {:>7.2}% {:>12} {:>7.2}%",
+                frame_name(entry, symbols),
+                self.calls.get(&entry).copied().unwrap_or_default(),
+                own,
+                percent(own),
+                inclusive,
+                percent(inclusive)
+            )
+            .unwrap();
+        }
+
+        if !symbols.is_empty() {
+            writeln!(report, "\nLabels").unwrap();
+            writeln!(report, "{:<20} {:>12} {:>8}", "label", "count", "%").unwrap();
+            for (label, count) in self.label_hits(symbols) {
+                writeln!(
+                    report,
+                    "{:<20} {:>12} {:>7.2}%",
+                    label,
+                    count,
+                    percent(count)
+                )
+                .unwrap();
+            }
+        }
+
+        report
+    }
+
+    // Instructions executed between each label and the next one, hottest first
+    fn label_hits(&self, symbols: &BTreeMap<String, u16>) -> Vec<(String, u64)> {
+        let mut labels = symbols
+            .iter()
+            .map(|(label, address)| (*address, label.clone()))
+            .collect::<Vec<(u16, String)>>();
+        labels.sort();
+
+        let mut counts = labels
+            .iter()
+            .enumerate()
+            .map(|(index, (start, label))| {
+                let end = labels
+                    .get(index + 1)
+                    .map_or(self.hits.len(), |(end, _)| *end as usize);
+                let count = self.hits[*start as usize..end].iter().sum::<u64>();
+                (label.clone(), count)
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect::<Vec<(String, u64)>>();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
+    }
+}
+
+// Label at exactly this address, or its address
+pub(super) fn frame_name(address: u16, symbols: &BTreeMap<String, u16>) -> String {
+    symbols
+        .iter()
+        .find(|(_, value)| **value == address)
+        .map_or(format!("x{:04X}", address), |(label, _)| label.clone())
+}
+
+// Closest label at or before the address plus an offset, e.g. `LOOP+2`
+pub(super) fn location(address: u16, symbols: &BTreeMap<String, u16>) -> String {
+    symbols
+        .iter()
+        .filter(|(_, value)| **value <= address)
+        .max_by_key(|(_, value)| **value)
+        .map_or(String::new(), |(label, value)| match address - value {
+            0 => label.clone(),
+            offset => format!("{}+{}", label, offset),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assembler::assemble, vm::Vm};
+
+    fn profile(source: &str) -> (crate::assembler::Program, Vm) {
+        let program = assemble(source).unwrap();
+        let mut vm = Vm::initialize();
+        vm.load_program(program.to_image().unwrap()).unwrap();
+        vm.enable_profiler();
+        vm.run();
+        (program, vm)
+    }
+
+    const CALLS: &str = ".ORIG x3000
+                AND R1, R1, #0
+                ADD R1, R1, #3
+        LOOP    JSR TWICE
+                ADD R1, R1, #-1
+                BRp LOOP
+                HALT
+        TWICE   ST R7, SAVE
+                JSR ONCE
+                JSR ONCE
+                LD R7, SAVE
+                RET
+        ONCE    ADD R2, R2, #1
+                RET
+        SAVE    .BLKW 1
+                .END";
+
+    #[test]
+    fn test_profile_addresses() {
+        let (program, vm) = profile(CALLS);
+        let profiler = vm.profiler().unwrap();
+
+        assert_eq!(profiler.hits(0x3000), 1);
+        assert_eq!(profiler.hits(program.symbols["LOOP"]), 3);
+        assert_eq!(profiler.hits(program.symbols["ONCE"]), 6);
+    }
+
+    #[test]
+    fn test_profile_subroutines() {
+        let (program, vm) = profile(CALLS);
+        let subroutines = vm.profiler().unwrap().subroutines();
+
+        // ONCE runs two instructions per call
+        assert_eq!(subroutines[&program.symbols["ONCE"]], (12, 12));
+        // TWICE runs five instructions per call plus its two calls to ONCE
+        assert_eq!(subroutines[&program.symbols["TWICE"]], (15, 27));
+        assert_eq!(subroutines[&0x3000], (12, 39));
+    }
+
+    #[test]
+    fn test_folded_stacks() {
+        let (program, vm) = profile(CALLS);
+        let folded = vm.profiler().unwrap().folded_stacks(&program.symbols);
+
+        assert_eq!(folded, "x3000 12\nx3000;TWICE 15\nx3000;TWICE;ONCE 12\n");
+    }
+
+    #[test]
+    fn test_report() {
+        let (program, vm) = profile(CALLS);
+        let report = vm.profile_report(&program.symbols).unwrap();
+
+        assert!(report.contains("Hottest addresses"));
+        assert!(report.contains("ONCE+1"));
+        assert!(report.contains("Labels"));
+    }
+}
@@ -0,0 +1,371 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::assembler::{parse_address, DebugInfo};
+
+use super::{DEVICE_REGISTERS, MAX_ADDRESSABLE_MEMORY};
+
+// First address of user space
+const USER_SPACE: u16 = 0x3000;
+
+/// What the program may do with a memory word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    /// loads and indirect addresses
+    pub read: bool,
+    /// stores
+    pub write: bool,
+    /// instruction fetches
+    pub execute: bool,
+}
+
+impl Permissions {
+    /// `rwx`
+    pub const ALL: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: true,
+    };
+    /// `---`
+    pub const NONE: Permissions = Permissions {
+        read: false,
+        write: false,
+        execute: false,
+    };
+    /// `r-x`
+    pub const CODE: Permissions = Permissions {
+        read: true,
+        write: false,
+        execute: true,
+    };
+    /// `rw-`
+    pub const DATA: Permissions = Permissions {
+        read: true,
+        write: true,
+        execute: false,
+    };
+
+    /// Whether `access` is permitted
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        }
+    }
+}
+
+// `rwx` with `-` for a missing permission, e.g. `r-x`
+impl FromStr for Permissions {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let flag = |index: usize, letter: char| match text.chars().nth(index) {
+            Some('-') => Ok(false),
+            Some(c) if c.eq_ignore_ascii_case(&letter) => Ok(true),
+            _ => Err(format!(
+                "Invalid permissions `{}`, expected e.g. `r-x`",
+                text
+            )),
+        };
+        if text.chars().count() != 3 {
+            return Err(format!(
+                "Invalid permissions `{}`, expected e.g. `r-x`",
+                text
+            ));
+        }
+        Ok(Permissions {
+            read: flag(0, 'r')?,
+            write: flag(1, 'w')?,
+            execute: flag(2, 'x')?,
+        })
+    }
+}
+
+impl Display for Permissions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flag = |set: bool, letter: char| if set { letter } else { '-' };
+        write!(
+            f,
+            "{}{}{}",
+            flag(self.read, 'r'),
+            flag(self.write, 'w'),
+            flag(self.execute, 'x')
+        )
+    }
+}
+
+/// How an instruction uses memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// a load, or the address read by LDI/STI
+    Read,
+    /// a store
+    Write,
+    /// the fetch of an instruction
+    Execute,
+}
+
+/// An access the memory map denies, `pc` is the address of the instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessViolation {
+    /// address of the instruction
+    pub pc: u16,
+    /// the word it tried to use
+    pub address: u16,
+    /// what it tried to do with it
+    pub access: Access,
+}
+
+impl AccessViolation {
+    /// The denied access alone, e.g. `write to x3004`
+    pub fn operation(&self) -> String {
+        match self.access {
+            Access::Read => format!("read from x{:04X}", self.address),
+            Access::Write => format!("write to x{:04X}", self.address),
+            Access::Execute => format!("execution of x{:04X}", self.address),
+        }
+    }
+}
+
+impl Display for AccessViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at x{:04X}", self.operation(), self.pc)
+    }
+}
+
+/// The permissions of every memory word, everything is allowed until regions are protected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+    permissions: Vec<Permissions>,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        MemoryMap {
+            permissions: vec![Permissions::ALL; MAX_ADDRESSABLE_MEMORY],
+        }
+    }
+}
+
+impl MemoryMap {
+    /// The usual layout of a user program: the system space below x3000 is out of reach, the
+    /// rest of memory holds data, and the code regions of `debug_info` can be executed but not
+    /// written
+    pub fn user(debug_info: &DebugInfo) -> Self {
+        let mut map = MemoryMap::default();
+        map.protect(0, USER_SPACE - 1, Permissions::NONE);
+        map.protect(USER_SPACE, DEVICE_REGISTERS - 1, Permissions::DATA);
+        map.protect(DEVICE_REGISTERS, u16::MAX, Permissions::DATA);
+        for region in &debug_info.regions {
+            let permissions = match region.code {
+                true => Permissions::CODE,
+                false => Permissions::DATA,
+            };
+            map.protect(region.start, region.end, permissions);
+        }
+        map
+    }
+
+    /// Sets the permissions from `start` to `end` included, replacing the previous ones
+    pub fn protect(&mut self, start: u16, end: u16, permissions: Permissions) {
+        if start <= end {
+            self.permissions[start as usize..=end as usize].fill(permissions);
+        }
+    }
+
+    /// Parses and protects a region given as `start-end:rwx`, e.g. `x3000-x30FF:r-x`
+    pub fn protect_region(&mut self, region: &str) -> Result<(), String> {
+        let invalid = || {
+            format!(
+                "Invalid region `{}`, expected e.g. `x3000-x30FF:r-x`",
+                region
+            )
+        };
+        let (range, permissions) = region.split_once(':').ok_or_else(invalid)?;
+        let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+        let address = |text: &str| parse_address(text).ok_or_else(invalid);
+        let (start, end) = (address(start)?, address(end)?);
+        if start > end {
+            return Err(invalid());
+        }
+        self.protect(start, end, permissions.parse()?);
+        Ok(())
+    }
+
+    /// The permissions of the word at `address`
+    pub fn permissions(&self, address: u16) -> Permissions {
+        self.permissions[address as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembler::{assemble, DebugInfo},
+        vm::{Register, RunOutcome, Vm},
+    };
+
+    use super::{Access, AccessViolation, MemoryMap, Permissions};
+
+    #[test]
+    fn test_memory_map() {
+        let mut map = MemoryMap::default();
+        assert_eq!(map.permissions(0x1234), Permissions::ALL);
+
+        map.protect_region("x3000-x30FF:r-x").unwrap();
+        map.protect_region("x3010-x3010:RW-").unwrap();
+        assert_eq!(map.permissions(0x3000), Permissions::CODE);
+        assert_eq!(map.permissions(0x3010), Permissions::DATA);
+        assert_eq!(map.permissions(0x3100), Permissions::ALL);
+        assert_eq!(map.permissions(0x3010).to_string(), "rw-");
+
+        assert!(map.protect_region("x3000:r-x").is_err());
+        assert!(map.protect_region("x3010-x3000:r-x").is_err());
+        assert!(map.protect_region("x3000-x3010:rx").is_err());
+        assert!(map.protect_region("x3000-x3010:w--").is_err());
+    }
+
+    fn run(source: &str) -> (Vm, RunOutcome) {
+        let program = assemble(source).unwrap();
+        let debug_info = DebugInfo::new("main.asm", &program);
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .memory_map(MemoryMap::user(&debug_info))
+            .input(b"")
+            .build()
+            .unwrap();
+        let outcome = vm.run();
+        (vm, outcome)
+    }
+
+    #[test]
+    fn test_access_violations() {
+        let (vm, outcome) = run(".ORIG x3000
+            LD R1, COUNT
+            ADD R1, R1, #1
+            ST R1, COUNT
+            ST R1, PATCH
+PATCH       HALT
+COUNT       .FILL 0
+            .END");
+        let violation = AccessViolation {
+            pc: 0x3003,
+            address: 0x3004,
+            access: Access::Write,
+        };
+        assert_eq!(outcome, RunOutcome::AccessViolation(violation));
+        assert_eq!(violation.to_string(), "write to x3004 at x3003");
+        // the PC still points at the instruction and the code is unchanged
+        assert_eq!(vm.register(Register::Pc), 0x3003);
+        assert_eq!(vm.read_memory(0x3004), 0xF025);
+        assert_eq!(vm.read_memory(0x3005), 1);
+
+        let (_, outcome) = run(".ORIG x3000
+            JSR DATA
+            HALT
+DATA        .FILL x1261
+            .END");
+        assert_eq!(
+            outcome,
+            RunOutcome::AccessViolation(AccessViolation {
+                pc: 0x3002,
+                address: 0x3002,
+                access: Access::Execute
+            })
+        );
+
+        let (_, outcome) = run(".ORIG x3000
+            LDI R0, VECTOR
+            HALT
+VECTOR      .FILL x0025
+            .END");
+        assert_eq!(
+            outcome,
+            RunOutcome::AccessViolation(AccessViolation {
+                pc: 0x3000,
+                address: 0x0025,
+                access: Access::Read
+            })
+        );
+
+        let (_, outcome) = run(".ORIG x3000
+            LEA R0, TEXT
+            PUTS
+            LD R6, STACK
+            STR R0, R6, #-1
+            HALT
+TEXT        .STRINGZ \"ok\"
+STACK       .FILL xFE00
+            .END");
+        assert_eq!(outcome, RunOutcome::Halted);
+    }
+
+    #[test]
+    fn test_faulting_instruction_has_no_effect() {
+        let program = assemble(
+            ".ORIG x3000
+            ADD R0, R0, #5
+            LDI R0, VECTOR
+            HALT
+VECTOR      .FILL x0025
+            .END",
+        )
+        .unwrap();
+        let debug_info = DebugInfo::new("main.asm", &program);
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .memory_map(MemoryMap::user(&debug_info))
+            .sanitizer(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(vm.step(), None);
+        let counters = vm.counters();
+        assert_eq!(
+            vm.step(),
+            Some(RunOutcome::AccessViolation(AccessViolation {
+                pc: 0x3001,
+                address: 0x0025,
+                access: Access::Read
+            }))
+        );
+        assert_eq!(vm.register(Register::R0), 5);
+        assert_eq!(vm.register(Register::Cond), 1);
+        assert_eq!(vm.register(Register::Pc), 0x3001);
+        assert_eq!(vm.counters(), counters);
+        assert_eq!(vm.counters().instructions, 1);
+    }
+
+    #[test]
+    fn test_faulting_sti_writes_nothing() {
+        let program = assemble(
+            ".ORIG x3000
+            LD R1, VALUE
+            STI R1, POINTER
+            HALT
+POINTER     .FILL x4000
+VALUE       .FILL x1234
+            .END",
+        )
+        .unwrap();
+        let mut map = MemoryMap::default();
+        map.protect_region("x3003-x3003:---").unwrap();
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .memory_map(map)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            vm.run(),
+            RunOutcome::AccessViolation(AccessViolation {
+                pc: 0x3001,
+                address: 0x3003,
+                access: Access::Read
+            })
+        );
+        // neither the address the denied read gives nor the one the pointer holds is written
+        assert_eq!(vm.read_memory(0x0000), 0);
+        assert_eq!(vm.read_memory(0x4000), 0);
+    }
+}
@@ -1,14 +1,70 @@
-pub(crate) enum Register {
+/// A register of the vm, see [`Vm::register`](super::Vm::register)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    /// general purpose, holds the character of GETC, IN, OUT and PUTS
     R0,
+    /// general purpose
     R1,
+    /// general purpose
     R2,
+    /// general purpose
     R3,
+    /// general purpose
     R4,
+    /// general purpose, the frame pointer by convention
     R5,
+    /// general purpose, the stack pointer by convention
     R6,
+    /// general purpose, the return address of JSR, JSRR and TRAP
     R7,
-    Pc,   // program counter
-    Cond, // conditional
+    /// program counter
+    Pc,
+    /// condition codes, N, Z or P
+    Cond,
+}
+
+impl Register {
+    /// The general purpose registers, R0 to R7
+    pub const GENERAL: [Register; 8] = [
+        Register::R0,
+        Register::R1,
+        Register::R2,
+        Register::R3,
+        Register::R4,
+        Register::R5,
+        Register::R6,
+        Register::R7,
+    ];
+}
+
+impl std::fmt::Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Register::Pc => f.write_str("PC"),
+            Register::Cond => f.write_str("COND"),
+            register => write!(f, "R{}", *register as u16),
+        }
+    }
+}
+
+// Accepts `R0` to `R7`, `PC` and `COND`, in any case
+impl std::str::FromStr for Register {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let register = match name.to_uppercase().as_str() {
+            "PC" => Register::Pc,
+            "COND" => Register::Cond,
+            upper => match upper
+                .strip_prefix('R')
+                .and_then(|n| n.parse::<usize>().ok())
+            {
+                Some(index) if index < 8 => Register::GENERAL[index],
+                _ => return Err(format!("Unknown register `{}`", name)),
+            },
+        };
+        Ok(register)
+    }
 }
 
 pub(crate) enum Cond {
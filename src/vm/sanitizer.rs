@@ -0,0 +1,216 @@
+use std::fmt::Write;
+
+use crate::assembler::{Gpr, Instruction, Operand};
+
+use super::{Register, DEVICE_REGISTERS, MAX_ADDRESSABLE_MEMORY, TOTAL_REGISTERS};
+
+/// A memory word or a register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// the word at an address
+    Memory(u16),
+    /// a register
+    Register(Register),
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Memory(address) => write!(f, "x{:04X}", address),
+            Location::Register(register) => write!(f, "{}", register),
+        }
+    }
+}
+
+/// A read of a location nothing was loaded into or written to, by the instruction at `pc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UninitializedRead {
+    /// address of the instruction
+    pub pc: u16,
+    /// what it read
+    pub location: Location,
+}
+
+/// Keeps a shadow bit per memory word and register telling whether it was initialized, by
+/// loading an image or by a write, and records reads of the others
+///
+/// Fetching an instruction and the loads read memory, the operands and the base registers of
+/// instructions read registers, as does R0 for the output traps. Storing a register is not a
+/// read: saving registers that were never written is usual at the start of a subroutine.
+/// Each location is only reported once per address.
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    memory: Vec<bool>,
+    registers: [bool; TOTAL_REGISTERS],
+    // address of the instruction being executed
+    pc: u16,
+    reads: Vec<UninitializedRead>,
+}
+
+impl Sanitizer {
+    pub(crate) fn new() -> Self {
+        let mut registers = [false; TOTAL_REGISTERS];
+        registers[Register::Pc as usize] = true;
+        registers[Register::Cond as usize] = true;
+        Sanitizer {
+            memory: vec![false; MAX_ADDRESSABLE_MEMORY],
+            registers,
+            pc: 0,
+            reads: vec![],
+        }
+    }
+
+    /// Reads of uninitialized locations in the order they happened
+    pub fn reads(&self) -> &[UninitializedRead] {
+        &self.reads
+    }
+
+    /// Whether `location` was loaded or written, the device registers always are
+    pub fn is_initialized(&self, location: Location) -> bool {
+        match location {
+            Location::Memory(address) => {
+                address >= DEVICE_REGISTERS || self.memory[address as usize]
+            }
+            Location::Register(register) => self.registers[register as usize],
+        }
+    }
+
+    // Starts executing the instruction at `pc`, fetching it is its first read
+    pub(crate) fn start(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub(crate) fn write_memory(&mut self, address: u16) {
+        self.memory[address as usize] = true;
+    }
+
+    pub(crate) fn write_register(&mut self, register: u16) {
+        self.registers[register as usize] = true;
+    }
+
+    pub(crate) fn read_memory(&mut self, address: u16) {
+        self.read(Location::Memory(address));
+    }
+
+    // Checks the registers the instruction reads before it executes
+    pub(crate) fn read_registers(&mut self, instruction: &Instruction) {
+        let sources = match *instruction {
+            // clearing a register doesn't depend on its value
+            Instruction::And {
+                operand: Operand::Imm(0),
+                ..
+            } => vec![],
+            Instruction::Add { sr1, operand, .. } | Instruction::And { sr1, operand, .. } => {
+                match operand {
+                    Operand::Reg(sr2) => vec![sr1, sr2],
+                    Operand::Imm(_) => vec![sr1],
+                }
+            }
+            Instruction::Not { sr, .. } => vec![sr],
+            Instruction::Ldr { base_r, .. }
+            | Instruction::Str { base_r, .. }
+            | Instruction::Jmp { base_r }
+            | Instruction::Jsrr { base_r } => vec![base_r],
+            // OUT, PUTS and PUTSP
+            Instruction::Trap {
+                trap_vect: 0x21 | 0x22 | 0x24,
+            } => vec![Gpr::R0],
+            _ => vec![],
+        };
+        for register in sources {
+            self.read(Location::Register(
+                Register::GENERAL[register.number() as usize],
+            ));
+        }
+    }
+
+    fn read(&mut self, location: Location) {
+        let read = UninitializedRead {
+            pc: self.pc,
+            location,
+        };
+        if !self.is_initialized(location) && !self.reads.contains(&read) {
+            self.reads.push(read);
+        }
+    }
+
+    /// One line per read: the PC and the location
+    pub fn report(&self) -> String {
+        let mut text = String::new();
+        for read in &self.reads {
+            writeln!(
+                text,
+                "x{:04X}: read of uninitialized {}",
+                read.pc, read.location
+            )
+            .unwrap();
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembler::assemble,
+        vm::{Register, RunOutcome, Vm},
+    };
+
+    use super::{Location, UninitializedRead};
+
+    #[test]
+    fn test_uninitialized_reads() {
+        let program = assemble(
+            ".ORIG x3000
+            LD R6, STACK
+            AND R5, R5, #0
+            JSR SAVE
+            LEA R0, TEXT
+            PUTS
+            LDI R4, POINTER
+            HALT
+SAVE        STR R1, R6, #-1
+            ADD R6, R6, #-1
+            LDR R1, R6, #0
+            ADD R6, R6, #1
+            ADD R2, R2, R1
+            RET
+STACK       .FILL xFE00
+POINTER     .FILL x4000
+TEXT        .FILL x41
+            .END",
+        )
+        .unwrap();
+        let mut vm = Vm::builder()
+            .image(program.to_image().unwrap())
+            .input(b"")
+            .sanitizer(true)
+            .build()
+            .unwrap();
+        vm.write_memory(0x4001, 7);
+
+        assert_eq!(vm.run(), RunOutcome::Halted);
+        let sanitizer = vm.sanitizer().unwrap();
+        let read = |pc, location| UninitializedRead { pc, location };
+        // the string isn't terminated and nothing was stored at x4000
+        assert_eq!(
+            sanitizer.reads(),
+            [
+                read(0x300B, Location::Register(Register::R2)),
+                read(0x3004, Location::Memory(0x3010)),
+                read(0x3005, Location::Memory(0x4000)),
+            ]
+        );
+        assert!(sanitizer.is_initialized(Location::Memory(0x4001)));
+        assert!(sanitizer.is_initialized(Location::Memory(0xFE00)));
+        assert!(sanitizer.is_initialized(Location::Register(Register::R4)));
+        assert!(!sanitizer.is_initialized(Location::Register(Register::R3)));
+        assert_eq!(
+            sanitizer.report(),
+            "x300B: read of uninitialized R2
+x3004: read of uninitialized x3010
+x3005: read of uninitialized x4000
+"
+        );
+    }
+}
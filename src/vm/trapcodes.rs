@@ -1,6 +1,4 @@
-use std::io::{self, Read};
-
-use super::{Register, Vm};
+use super::{Console, Register, Vm};
 
 pub(crate) enum TrapCodes {
     Getc = 0x20, // gets character from keyboard, does not echo to the terminal
@@ -15,74 +13,74 @@ impl TrapCodes {
     pub(crate) fn execute(&self, vm: &mut Vm) {
         match self {
             TrapCodes::Getc => {
-                let mut buffer = [0; 1];
-                io::stdin().read(&mut buffer).unwrap();
-                vm.set_register(Register::R0 as u16, buffer[0] as u16);
+                let char = read_char(vm);
+                vm.write_register(Register::R0 as u16, char);
                 vm.update_flag(Register::R0 as u16);
             }
 
             TrapCodes::Out => {
-                print!(
-                    "{}",
-                    (vm.get_register(Register::R0 as u16) & 0xFF) as u8 as char
-                );
+                let char = (vm.get_register(Register::R0 as u16) & 0xFF) as u8 as char;
+                vm.console.write(&char.to_string());
             }
 
             TrapCodes::Puts => {
                 let mut r0 = vm.get_register(Register::R0 as u16);
+                let mut text = String::new();
                 while vm.mem_read(r0) != 0 {
-                    print!("{}", vm.mem_read(r0) as u8 as char);
-                    r0 += 1;
+                    text.push(vm.mem_read(r0) as u8 as char);
+                    r0 = r0.wrapping_add(1);
                 }
+                vm.console.write(&text);
             }
 
             TrapCodes::In => {
-                println!("Please pass in a value!");
-                let mut buffer = [0; 1];
-                io::stdin().read(&mut buffer).unwrap();
-                print!("{}", buffer[0] as u8 as char);
-                vm.set_register(Register::R0 as u16, buffer[0] as u16);
+                vm.console.write("Please pass in a value!\n");
+                let char = read_char(vm);
+                vm.console.write(&(char as u8 as char).to_string());
+                vm.write_register(Register::R0 as u16, char);
                 vm.update_flag(Register::R0 as u16);
             }
 
             TrapCodes::Putsp => {
                 let mut r0 = vm.get_register(Register::R0 as u16);
-
+                let mut text = String::new();
                 while vm.mem_read(r0) != 0 {
-                    print!(
-                        "{}{}",
-                        (vm.mem_read(r0) >> 8) as u8 as char,
-                        vm.mem_read(r0) as u8 as char
-                    );
-                    r0 += 1;
+                    text.push((vm.mem_read(r0) >> 8) as u8 as char);
+                    text.push(vm.mem_read(r0) as u8 as char);
+                    r0 = r0.wrapping_add(1);
                 }
+                vm.console.write(&text);
             }
 
             TrapCodes::Halt => {
                 vm.running = false;
-                println!("Program execution halted")
+                // a message of the vm rather than output of the program
+                if vm.console == Console::Terminal {
+                    println!("Program execution halted")
+                }
             }
         }
     }
 }
 
-impl Into<TrapCodes> for u16 {
-    fn into(self) -> TrapCodes {
-        if self == 0x20 {
-            TrapCodes::Getc
-        } else if self == 0x21 {
-            TrapCodes::Out
-        } else if self == 0x22 {
-            TrapCodes::Puts
-        } else if self == 0x23 {
-            TrapCodes::In
-        } else if self == 0x24 {
-            TrapCodes::Putsp
-        } else if self == 0x25 {
-            TrapCodes::Halt
-        } else {
-            dbg!(self);
-            panic!("Invalid trapcode")
+// Reads a single character from the console, returning 0 once the input is exhausted
+fn read_char(vm: &mut Vm) -> u16 {
+    vm.console.read().map_or(0, u16::from)
+}
+
+// An error for a vector without a service routine
+impl TryFrom<u16> for TrapCodes {
+    type Error = ();
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            0x20 => Ok(TrapCodes::Getc),
+            0x21 => Ok(TrapCodes::Out),
+            0x22 => Ok(TrapCodes::Puts),
+            0x23 => Ok(TrapCodes::In),
+            0x24 => Ok(TrapCodes::Putsp),
+            0x25 => Ok(TrapCodes::Halt),
+            _ => Err(()),
         }
     }
 }
@@ -94,7 +92,6 @@ pub(crate) enum Mmr {
     Kbdr = 0xFE02, // keyboard data register
     Dsr = 0xFE04,  // display status register
     Ddr = 0xFE06,  // display data register
-    Mcr = 0xFFFE,  // machine control register
 }
 
 #[cfg(test)]
@@ -135,7 +132,7 @@ mod tests {
     #[test]
     fn test_out_trapcode() {
         let mut vm = create_vm();
-        vm.set_register(Register::R0 as u16, 98);
+        vm.write_register(Register::R0 as u16, 98);
 
         // instruction
         // F021 -> 1111 0000 00100001